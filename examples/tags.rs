@@ -5,7 +5,7 @@
 // Run with: cargo run --example tags
 //
 // This example extends hello_world by demonstrating the use of { tags "<markup>" }
-// blocks and the .sgtag template type.
+// blocks, the .sgtag template type, and a site map generated from a .sgindex template.
 //
 
 use std::path::Path;
@@ -0,0 +1,115 @@
+use anyhow::{Context, Result};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+use std::time::Instant;
+
+use crate::sitegen::{Config, SiteGen};
+
+// Number of groups the synthetic pages are spread across.
+const GROUP_COUNT: usize = 10;
+
+// Number of tags assigned to each synthetic page.
+const TAGS_PER_PAGE: usize = 3;
+
+// Generate a synthetic webroot with the given number of pages and tags, build it, and report
+// build throughput and peak memory usage. The synthetic site is removed afterwards.
+pub fn run(page_count: usize, tag_count: usize) -> Result<()> {
+    let webroot = std::env::temp_dir().join(format!("sitewinder-bench-{}", std::process::id()));
+    fs::create_dir_all(&webroot)
+        .with_context(|| format!("Unable to create benchmark folder '{}'", webroot.display()))?;
+
+    let result = generate_site(&webroot, page_count, tag_count).and_then(|_| build_site(&webroot, page_count));
+
+    fs::remove_dir_all(&webroot)
+        .with_context(|| format!("Unable to remove benchmark folder '{}'", webroot.display()))?;
+    result
+}
+
+// Write the include files, tag template and page templates making up the synthetic site.
+fn generate_site(webroot: &Path, page_count: usize, tag_count: usize) -> Result<()> {
+    println!("generating synthetic site with {} pages and {} tags in '{}'", page_count, tag_count, webroot.display());
+
+    write(webroot, "header.sginc", "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n    <title>Bench | { title }</title>\n    { head.prevnext }\n    <link rel=\"stylesheet\" href=\"/style.css\">\n</head>\n<body>\n")?;
+    write(webroot, "footer.sginc", "{ group \"/group_nav.sginc\" }\n<div class=\"tags\">\n    { tags '<a href=\"{ tag.page.link }\" style=\"font-size: { tag.page.link_size }pt;\">{ tag.page.title }</a> ' }\n</div>\n<p>&copy; { current_year }</p>\n</body>\n</html>\n")?;
+    write(webroot, "group_nav.sginc", "<a href=\"{ prev.path }\">&larr; { prev.title }</a>\n<a href=\"{ next.path }\">{ next.title } &rarr;</a>\n")?;
+    write(webroot, "style.css", "body { font-family: sans-serif; }\n")?;
+    write(webroot, "tags/tag.sgtag", "--\ntitle: { title }\n--\n{ include \"/header.sginc\" }\n<h1>{ title }</h1>\n{ pages '<p><a href=\"{ page.link }\">{ page.title }</a></p>' }\n{ include \"/footer.sginc\" }\n")?;
+
+    let start_date = chrono::NaiveDate::from_ymd_opt(2000, 1, 1).unwrap();
+    for i in 0..page_count {
+        let date = start_date + chrono::Days::new(i as u64);
+        let tags = if tag_count == 0 {
+            String::new()
+        } else {
+            (0..TAGS_PER_PAGE.min(tag_count))
+                .map(|n| format!("Tag {}", (i * 7 + n * 13) % tag_count))
+                .collect::<Vec<String>>()
+                .join(", ")
+        };
+
+        let mut contents = String::new();
+        writeln!(contents, "--\ntitle: Post {}\nauthor: Bench\ngroup: Group {}\ndate: {}", i, i % GROUP_COUNT, date.format("%Y-%m-%d"))?;
+        if !tags.is_empty() {
+            writeln!(contents, "tags: {}", tags)?;
+        }
+        contents.push_str("--\n{ include \"/header.sginc\" }\n<h1>{ title }</h1>\n<p>by { author } &middot; { date }</p>\n");
+        for paragraph in 0..5 {
+            writeln!(contents, "<p>Paragraph {} of post {}. Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor incididunt ut labore et dolore magna aliqua. <a href=\"/posts/{}/post_{}.html\">Related post</a>.</p>", paragraph, i, (i + paragraph) % GROUP_COUNT, (i + paragraph + 1) % page_count)?;
+        }
+        contents.push_str("{ include \"/footer.sginc\" }\n");
+
+        write(webroot, &format!("posts/{}/post_{}.sgpage", i % GROUP_COUNT, i), &contents)?;
+    }
+
+    Ok(())
+}
+
+// Build the synthetic site and print the benchmark results.
+fn build_site(webroot: &Path, page_count: usize) -> Result<()> {
+    let config = Config {
+        quiet: true,
+        ..Config::default()
+    };
+
+    let start = Instant::now();
+    let mut sitegen = SiteGen::new(webroot, config)?;
+    let summary = sitegen.run()?;
+    let elapsed = start.elapsed();
+
+    // A benchmark of a partial build would be misleading
+    if let Some((path, error)) = summary.failures.first() {
+        anyhow::bail!("{} of the synthetic pages could not be generated, e.g. '{}': {:#}", summary.failures.len(), path, error);
+    }
+
+    // Every tag in use has a tag page, so the generated pages other than the posts are tag pages
+    let generated = summary.generated;
+    println!("built {} pages ({} posts, {} tag pages) in {:.3}s", generated, page_count, generated.saturating_sub(page_count), elapsed.as_secs_f64());
+    println!("throughput: {:.1} pages/s", generated as f64 / elapsed.as_secs_f64());
+    match peak_memory_kib() {
+        Some(kib) => println!("peak memory: {:.1} MiB", kib as f64 / 1024.0),
+        None => println!("peak memory: unavailable on this platform"),
+    }
+
+    Ok(())
+}
+
+// Write a file below the webroot, creating parent folders as needed.
+fn write(webroot: &Path, filename: &str, contents: &str) -> Result<()> {
+    let path = webroot.join(filename);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Unable to create folder '{}'", parent.display()))?;
+    }
+    fs::write(&path, contents)
+        .with_context(|| format!("Unable to write benchmark file '{}'", path.display()))
+}
+
+// Peak resident set size of the current process in KiB, where the platform exposes it.
+fn peak_memory_kib() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmHWM:"))
+        .and_then(|value| value.trim().trim_end_matches("kB").trim().parse().ok())
+}
@@ -1,24 +1,214 @@
 use anyhow::Result;
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use std::process::ExitCode;
 
+mod bench;
 mod sitegen;
-use sitegen::SiteGen;
+use sitegen::{verify_checksums, Config, Prune, SiteGen};
 
 #[derive(Parser, Debug)]
 #[command(
     author,
     version,
     about = concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION"), " - a static site generator, licensed under ", env!("CARGO_PKG_LICENSE")),
-    long_about = None
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
 )]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Local root folder containing template files
-    root: std::path::PathBuf,
+    #[arg(required = true)]
+    root: Option<std::path::PathBuf>,
+
+    /// Append a ?v=<hash> query string to local asset references, based on each asset's contents
+    #[arg(long)]
+    cache_bust: bool,
+
+    /// Only regenerate pages affected by changes made since the last incremental build
+    #[arg(long)]
+    incremental: bool,
+
+    /// Write links as absolute paths below this path, for sites served from a subdirectory (e.g. /myproject)
+    #[arg(long, value_name = "PATH")]
+    base_path: Option<String>,
+
+    /// Write a build report (_report.html) with failed pages, broken links, and the slowest and biggest pages
+    #[arg(long)]
+    report: bool,
+
+    /// Write the SHA-256 checksums of the published files to SHA256SUMS, for the verify command
+    #[arg(long)]
+    checksums: bool,
+
+    /// Preview build: include draft pages, and mark drafts and future-dated pages with a badge
+    #[arg(long)]
+    drafts: bool,
+
+    /// Include pages dated in the future, which are otherwise left out until their date
+    #[arg(long)]
+    include_future: bool,
+
+    /// After the build, print the dates on which building again would give different output, e.g. when scheduled pages are due
+    #[arg(long)]
+    next_change: bool,
+
+    /// Define a build variable for { var.<key> } placeholders, e.g. -D flavour=staging (repeatable)
+    #[arg(short = 'D', long = "define", value_name = "KEY=VALUE", value_parser = parse_define)]
+    define: Vec<(String, String)>,
+
+    /// After the build, report the assets that no page references, or delete them with --prune=delete
+    #[arg(long, value_name = "MODE", num_args = 0..=1, require_equals = true, default_missing_value = "report", value_parser = parse_prune)]
+    prune: Option<Prune>,
+
+    /// Use the options of a profile from the configuration file, e.g. --profile prod
+    #[arg(long, value_name = "NAME")]
+    profile: Option<String>,
+
+    /// Allow include files outside the root folder, e.g. "/../shared/header.sginc"
+    #[arg(long)]
+    allow_outside_root: bool,
+
+    /// Don't print the name of each page as it is generated
+    #[arg(short, long)]
+    quiet: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Generate a synthetic site, build it, and report throughput and peak memory usage
+    Bench {
+        /// Number of pages in the synthetic site
+        #[arg(long, default_value_t = 1000)]
+        pages: usize,
+
+        /// Number of distinct tags in the synthetic site
+        #[arg(long, default_value_t = 50)]
+        tags: usize,
+    },
+
+    /// Check the site for problems without writing any files: pages that can't be generated, and misspelled words if spell checking is configured
+    Lint {
+        /// Local root folder containing template files
+        root: std::path::PathBuf,
+
+        /// Include draft pages
+        #[arg(long)]
+        drafts: bool,
+
+        /// Use the options of a profile from the configuration file
+        #[arg(long, value_name = "NAME")]
+        profile: Option<String>,
+    },
+
+    /// Check the files in a folder, e.g. a deployed copy of the site, against the SHA256SUMS manifest in that folder
+    Verify {
+        /// Folder containing the published site and its SHA256SUMS manifest
+        root: std::path::PathBuf,
+    },
+}
+
+// Exit code used when the build completed, but some pages could not be generated.
+const EXIT_BUILT_WITH_WARNINGS: u8 = 2;
+
+// Exit code used when the lint or verify command found problems with the site.
+const EXIT_PROBLEMS_FOUND: u8 = 3;
+
+fn main() -> ExitCode {
+    // Exit with 0 if all pages were generated, 2 if the build completed with page failures,
+    // 3 if lint or verify found problems, and 1 if the build or command failed altogether
+    match run(Args::parse()) {
+        Ok(code) => code,
+        Err(error) => {
+            eprintln!("Error: {:?}", error);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+// Run the command given on the command line, and return the code to exit with.
+fn run(args: Args) -> Result<ExitCode> {
+    let checked = |ok: bool| if ok { ExitCode::SUCCESS } else { ExitCode::from(EXIT_PROBLEMS_FOUND) };
+    match args.command {
+        Some(Command::Bench { pages, tags }) => {
+            bench::run(pages, tags)?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::Lint { root, drafts, profile }) => return lint(&root, drafts, profile.as_deref()).map(checked),
+        Some(Command::Verify { root }) => return verify(&root).map(checked),
+        None => {}
+    }
+
+    // The root argument is required unless a subcommand was given
+    let root = args.root.expect("root argument is required");
+
+    // Command line options override the configuration file
+    let mut config = Config::load(&root, args.profile.as_deref())?;
+    config.cache_bust |= args.cache_bust;
+    config.incremental |= args.incremental;
+    config.quiet |= args.quiet;
+    config.drafts |= args.drafts;
+    config.include_future |= args.include_future;
+    config.next_change |= args.next_change;
+    config.report |= args.report;
+    config.checksums |= args.checksums;
+    config.allow_outside_root |= args.allow_outside_root;
+    if args.base_path.is_some() {
+        config.base_path = args.base_path;
+    }
+    if args.prune.is_some() {
+        config.prune = args.prune;
+    }
+    config.define.extend(args.define);
+
+    let mut sitegen = SiteGen::new(&root, config)?;
+    let summary = sitegen.run()?;
+    Ok(if summary.failures.is_empty() { ExitCode::SUCCESS } else { ExitCode::from(EXIT_BUILT_WITH_WARNINGS) })
+}
+
+// Parse a build variable given on the command line as key=value
+fn parse_define(define: &str) -> Result<(String, String), String> {
+    match define.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => Ok((key.trim().to_string(), value.to_string())),
+        _ => Err(format!("invalid build variable '{}', expected KEY=VALUE", define)),
+    }
 }
 
-fn main() -> Result<()> {
-    let args = Args::parse();
+// Parse the mode of the --prune option
+fn parse_prune(mode: &str) -> Result<Prune, String> {
+    match mode {
+        "report" => Ok(Prune::Report),
+        "delete" => Ok(Prune::Delete),
+        _ => Err(format!("invalid prune mode '{}', expected report or delete", mode)),
+    }
+}
+
+// Check the site for problems. Returns false if any problems were found.
+fn lint(root: &std::path::Path, drafts: bool, profile: Option<&str>) -> Result<bool> {
+    let mut config = Config::load(root, profile)?;
+    config.drafts |= drafts;
+    config.quiet = true;
+
+    let problems = SiteGen::new(root, config)?.lint()?;
+    if problems > 0 {
+        eprintln!("{} problem{} found", problems, if problems == 1 { "" } else { "s" });
+    }
+    Ok(problems == 0)
+}
 
-    let mut sitegen = SiteGen::new(&args.root)?;
-    sitegen.run()
+// Check the published files against the checksum manifest. Returns false if any files are
+// missing, modified or not in the manifest.
+fn verify(root: &std::path::Path) -> Result<bool> {
+    let (problems, count) = verify_checksums(root)?;
+    for problem in &problems {
+        eprintln!("{}", problem);
+    }
+    if problems.is_empty() {
+        println!("{} files verified", count);
+    } else {
+        eprintln!("{} problem{} found", problems.len(), if problems.len() == 1 { "" } else { "s" });
+    }
+    Ok(problems.is_empty())
 }
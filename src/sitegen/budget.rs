@@ -0,0 +1,91 @@
+use serde::Deserialize;
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::sitegen::config::Config;
+use crate::sitegen::gallery::IMAGE_EXTENSIONS;
+use crate::sitegen::regexes::*;
+
+// Performance budgets of the generated pages, set in a [budget] table in the configuration file.
+// Pages are only checked if this is set.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BudgetConfig {
+    // Maximum size of a generated page, in kilobytes.
+    pub max_html_size: Option<u64>,
+
+    // Maximum total size of the local images a page shows, in kilobytes.
+    pub max_image_weight: Option<u64>,
+
+    // Maximum time it takes to generate a page, in milliseconds.
+    pub max_render_time: Option<u64>,
+
+    // Fail the build if a page exceeds a budget, rather than only warning.
+    pub fail: bool,
+}
+
+// Check a generated page against the budgets: the size of its HTML, the total size of the local
+// images it references with src attributes, each counted once, and the time it took to generate.
+// Returns a warning for each budget the page exceeds.
+pub fn check(root: &Path, path: &str, html: &str, duration: Duration, config: &Config, budget: &BudgetConfig) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let kilobytes = |bytes: u64| bytes as f64 / 1000.0;
+
+    let html_size = html.len() as u64;
+    if let Some(max) = budget.max_html_size.filter(|max| html_size > max * 1000) {
+        warnings.push(format!("HTML is {:.1} kB, more than the budget of {} kB", kilobytes(html_size), max));
+    }
+
+    if let Some(max) = budget.max_image_weight {
+        let images: BTreeSet<String> = RE_LINK_SRC.captures_iter(html)
+            .filter_map(|caps| super::resolve_link(path, &caps[2], config))
+            .filter(|image| image.rsplit_once('.').is_some_and(|(_, extension)| IMAGE_EXTENSIONS.contains(&extension.to_lowercase().as_str())))
+            .collect();
+        let weight: u64 = images.iter()
+            .filter_map(|image| std::fs::metadata(root.join(image.trim_start_matches('/'))).ok())
+            .map(|metadata| metadata.len())
+            .sum();
+        if weight > max * 1000 {
+            warnings.push(format!("images are {:.1} kB, more than the budget of {} kB", kilobytes(weight), max));
+        }
+    }
+
+    let render_time = duration.as_secs_f64() * 1000.0;
+    if let Some(max) = budget.max_render_time.filter(|max| render_time > *max as f64) {
+        warnings.push(format!("took {:.1} ms to generate, more than the budget of {} ms", render_time, max));
+    }
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sitegen::testing::TempSite;
+
+    // Test that pages over the HTML size, image weight and render time budgets are reported, and
+    // that images referenced more than once are counted once.
+    #[test]
+    fn test_budgets() {
+        let root = TempSite::new("budget");
+        root.write("photos/a.jpg", vec![0; 1500]);
+        root.write("photos/b.PNG", vec![0; 700]);
+        root.write("photos/clip.mp4", vec![0; 5000]);
+        let html = format!("<img src=\"../photos/a.jpg\"><img src=\"/photos/a.jpg?v=1\"><img src=\"../photos/b.PNG\">\
+            <video src=\"../photos/clip.mp4\"></video><img src=\"https://example.com/c.jpg\">{}", "x".repeat(1800));
+
+        let config = Config::default();
+        let budget = BudgetConfig { max_html_size: Some(1), max_image_weight: Some(2), max_render_time: Some(50), fail: false };
+        let warnings = check(&root, "/posts/a.html", &html, Duration::from_millis(120), &config, &budget);
+        let within = check(&root, "/posts/a.html", &html, Duration::from_millis(20), &config, &BudgetConfig {
+            max_html_size: Some(2), max_image_weight: Some(3), ..budget
+        });
+
+        assert_eq!(warnings, [
+            "HTML is 2.0 kB, more than the budget of 1 kB",
+            "images are 2.2 kB, more than the budget of 2 kB",
+            "took 120.0 ms to generate, more than the budget of 50 ms",
+        ]);
+        assert!(within.is_empty());
+    }
+}
@@ -0,0 +1,199 @@
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::sitegen::config::Config;
+use crate::sitegen::page::include_paths;
+use crate::sitegen::regexes::*;
+use crate::sitegen::template::Block;
+
+// Blocks that bundle files, and the extension of the files they write.
+pub const BUNDLE_BLOCKS: [(&str, &str); 2] = [("bundle_css", "css"), ("bundle_js", "js")];
+
+// Concatenate the files matching the glob pattern of a { bundle_css } or { bundle_js } block into
+// the contents of the block's bundle, minifying stylesheets if the block ends with minify.
+// Relative url() references in stylesheets from other folders are rewritten, so they still point
+// to the same files from the bundle. Returns the path of the bundle relative to the web root and
+// its contents, or None if the pattern matches no files.
+pub fn bundle(root: &Path, block: &Block, config: &Config) -> Result<Option<(String, String)>> {
+    let (path, sources) = bundle_files(root, block)?;
+    let minify = block.values().get(1) == Some(&"minify");
+    if sources.is_empty() {
+        return Ok(None);
+    }
+
+    let bundle_path = root.join(path.trim_start_matches('/'));
+    let mut parts = Vec::new();
+    for source in &sources {
+        let contents = std::fs::read_to_string(root.join(source.trim_start_matches('/')))
+            .with_context(|| format!("Unable to read bundled file '{}'", source))?;
+        parts.push(match block.name.as_str() {
+            "bundle_css" => rebase_css_urls(&contents, source, &path, &bundle_path, root, config),
+            _ => contents,
+        });
+    }
+
+    let contents = match block.name.as_str() {
+        "bundle_css" if minify => minify_css(&parts.join("\n")),
+        "bundle_css" => parts.join("\n"),
+        // A script that doesn't end with a semicolon would otherwise run into the next one
+        _ => parts.iter().map(|part| part.trim_end()).collect::<Vec<_>>().join("\n;\n"),
+    };
+    Ok(Some((path, contents)))
+}
+
+// Write the bundles of the generated pages, by path relative to the web root. A bundle is only
+// written if its contents changed.
+pub fn write(root: &Path, bundles: &BTreeMap<String, String>) -> Result<()> {
+    for (path, contents) in bundles {
+        let bundle_path = root.join(path.trim_start_matches('/'));
+        if std::fs::read_to_string(&bundle_path).ok().as_deref() == Some(contents.as_str()) {
+            continue;
+        }
+        if let Some(folder) = bundle_path.parent() {
+            std::fs::create_dir_all(folder)
+                .with_context(|| format!("Unable to create folder '{}'", folder.display()))?;
+        }
+        std::fs::write(&bundle_path, contents)
+            .with_context(|| format!("Unable to write bundle '{}'", bundle_path.display()))?;
+    }
+    Ok(())
+}
+
+// The files bundled by a { bundle_css } or { bundle_js } block, relative to the web root, leaving
+// out the bundle itself.
+pub fn sources(root: &Path, block: &Block) -> Vec<String> {
+    bundle_files(root, block).map(|(_, sources)| sources).unwrap_or_default()
+}
+
+// The path of a block's bundle, given with out="<path>" or bundle.css or bundle.js in the folder
+// of the pattern, and the files that go into it, in order of path.
+fn bundle_files(root: &Path, block: &Block) -> Result<(String, Vec<String>)> {
+    let pattern = block.values().first().copied().context("Missing pattern in bundle block")?;
+    let extension = BUNDLE_BLOCKS.iter().find(|(name, _)| *name == block.name).map_or("", |(_, extension)| *extension);
+    let path = match block.arg("out") {
+        Some(out) if out.split(['/', '\\']).any(|component| component == "..") || out.ends_with('/') => {
+            anyhow::bail!("Invalid bundle path '{}', expected a file in the web root", out)
+        }
+        Some(out) => format!("/{}", out.trim_start_matches('/')),
+        None => {
+            // The folder is the part of the pattern before its first wildcard
+            let literal = &pattern[..pattern.find(['*', '?', '[']).unwrap_or(pattern.len())];
+            let folder = literal.rfind('/').map_or("", |end| literal[..end].trim_start_matches('/'));
+            if folder.is_empty() { format!("/bundle.{}", extension) } else { format!("/{}/bundle.{}", folder, extension) }
+        }
+    };
+    let sources = include_paths(root, pattern)?.into_iter().filter(|source| *source != path).collect();
+    Ok((path, sources))
+}
+
+// Rewrite the relative url() and @import references of a stylesheet that is moved to another
+// folder, i.e. bundled or inlined into a page at `path`, so they're relative to its new location.
+pub fn rebase_css_urls(css: &str, source: &str, path: &str, bundle_path: &Path, root: &Path, config: &Config) -> String {
+    let folder = |path: &str| path.rfind('/').map_or(String::new(), |end| path[..end].to_string());
+    if folder(source) == folder(path) {
+        return css.to_string();
+    }
+    RE_CSS_URL.replace_all(css, |caps: &regex::Captures| {
+        let url = caps.name("url").or(caps.name("import")).unwrap();
+        let suffix = url.as_str().find(['?', '#']).map_or("", |start| &url.as_str()[start..]);
+        match super::resolve_link(source, url.as_str().trim(), config) {
+            Some(target) if !url.as_str().trim().starts_with('/') => {
+                let link = super::make_relative_link(&target, bundle_path, root, config);
+                let start = url.start() - caps.get(0).unwrap().start();
+                let end = url.end() - caps.get(0).unwrap().start();
+                format!("{}{}{}{}", &caps[0][..start], link, suffix, &caps[0][end..])
+            }
+            _ => caps[0].to_string(),
+        }
+    }).to_string()
+}
+
+// Make a stylesheet smaller without changing its meaning: comments are removed, and runs of
+// whitespace are collapsed to a single space, or removed next to braces, semicolons and commas,
+// and after colons. Spaces before colons are kept, as "a :hover" and "a:hover" differ. Strings are
+// kept as they are.
+fn minify_css(css: &str) -> String {
+    let mut result = String::with_capacity(css.len());
+    let mut chars = css.chars().peekable();
+    let mut space = false;
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() {
+            space = true;
+            continue;
+        }
+        if c == '/' && chars.peek() == Some(&'*') {
+            chars.next();
+            let mut previous = ' ';
+            for c in chars.by_ref() {
+                if previous == '*' && c == '/' {
+                    break;
+                }
+                previous = c;
+            }
+            space = true;
+            continue;
+        }
+
+        // Characters that whitespace can be removed after, and before
+        let (after, before) = (|c: char| "{};,:".contains(c), |c: char| "{};,".contains(c));
+        if space && !result.is_empty() && !result.ends_with(after) && !before(c) {
+            result.push(' ');
+        }
+        space = false;
+        result.push(c);
+        if c == '"' || c == '\'' {
+            while let Some(next) = chars.next() {
+                result.push(next);
+                if next == '\\' {
+                    result.extend(chars.next());
+                } else if next == c {
+                    break;
+                }
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sitegen::testing::TempSite;
+    use crate::sitegen::template;
+
+    // Test that bundles concatenate the matching files in order of path, rebasing the references
+    // of stylesheets from other folders, and that stylesheets can be minified.
+    #[test]
+    fn test_bundles() {
+        let root = TempSite::new("bundles");
+        root.write("css/b.css", "/* Layout */\nmain  {\n  margin : 0 auto;\n}\n");
+        root.write("css/a.css", "a:hover, a:focus { content: \"a  /* b */\"; background: url(img/a.png) }\n");
+        root.write("css/vendor/c.css", "@import \"d.css\";\nbody { background: url('../../img/c.png#x') }\n");
+        root.write("js/a.js", "let a = 1\n");
+        root.write("js/b.js", "// b\n");
+        let block = |source: &str| match template::parse(source).unwrap().remove(0) {
+            template::Node::Block(block) => block,
+            _ => unreachable!(),
+        };
+        let config = Config::default();
+
+        let bundles: BTreeMap<_, _> = ["{ bundle_css \"/css/*.css\" out=\"/site.css\" minify }", "{ bundle_css \"css/**/*.css\" }", "{ bundle_js \"js/*.js\" }"]
+            .iter()
+            .map(|source| bundle(&root, &block(source), &config).unwrap().unwrap())
+            .collect();
+        let none = bundle(&root, &block("{ bundle_js \"lib/*.js\" }"), &config).unwrap();
+        write(&root, &bundles).unwrap();
+        let (css_bundle, minified_bundle, js_bundle) = (root.read("css/bundle.css"), root.read("site.css"), root.read("js/bundle.js"));
+        // Building again doesn't bundle the bundle
+        let again = sources(&root, &block("{ bundle_css \"css/*.css\" }"));
+
+        assert_eq!(bundles.keys().collect::<Vec<_>>(), ["/css/bundle.css", "/js/bundle.js", "/site.css"]);
+        assert!(none.is_none());
+        assert_eq!(css_bundle, "a:hover, a:focus { content: \"a  /* b */\"; background: url(img/a.png) }\n\n\
+            /* Layout */\nmain  {\n  margin : 0 auto;\n}\n\n@import \"vendor/d.css\";\nbody { background: url('../img/c.png#x') }\n");
+        assert_eq!(minified_bundle, "a:hover,a:focus{content:\"a  /* b */\";background:url(css/img/a.png)}main{margin :0 auto;}");
+        assert_eq!(js_bundle, "let a = 1\n;\n// b");
+        assert_eq!(again, ["/css/a.css", "/css/b.css"]);
+    }
+}
@@ -0,0 +1,148 @@
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::path::Path;
+use walkdir::WalkDir;
+
+use crate::sitegen::config::{Config, CONFIG_FILENAME};
+
+// Name of the checksum manifest in the web root. The format is that of the sha256sum tool, so
+// the manifest can also be checked with "sha256sum -c SHA256SUMS".
+pub const MANIFEST_FILENAME: &str = "SHA256SUMS";
+
+// File extensions of the templates and include files, which aren't published.
+pub const SOURCE_EXTENSIONS: [&str; 5] = ["sgpage", "sgtag", "sggroup", "sgindex", "sginc"];
+
+// A difference between the files in a folder and the checksum manifest.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Problem {
+    // A file in the manifest doesn't exist.
+    Missing(String),
+    // A file's contents don't match its checksum.
+    Modified(String),
+    // A file isn't in the manifest.
+    Unexpected(String),
+}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Problem::Missing(path) => write!(f, "missing: {}", path),
+            Problem::Modified(path) => write!(f, "modified: {}", path),
+            Problem::Unexpected(path) => write!(f, "not in manifest: {}", path),
+        }
+    }
+}
+
+// Write the checksum manifest, listing the SHA-256 hash of every published file in the web
+// root. Returns the number of files listed.
+pub fn write(root: &Path, config: &Config) -> Result<usize> {
+    let checksums = published_files(root)?;
+    let manifest: String = checksums.iter().map(|(path, hash)| format!("{}  {}\n", hash, path)).collect();
+
+    let path = root.join(MANIFEST_FILENAME);
+    std::fs::write(&path, manifest).with_context(|| format!("Unable to write checksum manifest '{}'", path.display()))?;
+    if !config.quiet {
+        println!("checksums of {} files written to '{}'", checksums.len(), path.display());
+    }
+    Ok(checksums.len())
+}
+
+// Verify the published files in a folder, e.g. a deployed copy of the site, against the
+// checksum manifest in that folder. Returns the problems found, sorted by path, and the number
+// of files in the manifest.
+pub fn verify(root: &Path) -> Result<(Vec<Problem>, usize)> {
+    let path = root.join(MANIFEST_FILENAME);
+    let manifest = std::fs::read_to_string(&path)
+        .with_context(|| format!("Unable to read checksum manifest '{}'", path.display()))?;
+
+    let mut expected = BTreeMap::new();
+    for (number, line) in manifest.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+        // Lines are "<hash>  <path>", or "<hash> *<path>" for files hashed in binary mode
+        let (hash, path) = line.split_once(' ')
+            .filter(|(hash, _)| hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit()))
+            .with_context(|| format!("Invalid line {} in checksum manifest '{}'", number + 1, path.display()))?;
+        let path = path.strip_prefix([' ', '*']).unwrap_or(path);
+        expected.insert(path.to_string(), hash.to_lowercase());
+    }
+
+    let actual = published_files(root)?;
+    let mut problems: Vec<Problem> = expected.iter()
+        .filter_map(|(path, hash)| match actual.get(path) {
+            None => Some(Problem::Missing(path.clone())),
+            Some(actual_hash) if actual_hash != hash => Some(Problem::Modified(path.clone())),
+            Some(_) => None,
+        })
+        .chain(actual.keys().filter(|path| !expected.contains_key(*path)).map(|path| Problem::Unexpected(path.clone())))
+        .collect();
+    problems.sort_by(|lhs, rhs| problem_path(lhs).cmp(problem_path(rhs)));
+
+    Ok((problems, expected.len()))
+}
+
+// The SHA-256 hashes of the published files in a folder, by path relative to the folder. Files
+// and folders whose names start with "." or "_" are private, and templates, include files, the
+// configuration file and the manifest itself aren't published either.
+fn published_files(root: &Path) -> Result<BTreeMap<String, String>> {
+    let mut files = BTreeMap::new();
+    let entries = WalkDir::new(root)
+        .into_iter()
+        .filter_entry(|entry| entry.depth() == 0 || !entry.file_name().to_string_lossy().starts_with(['.', '_']));
+
+    for entry in entries {
+        let entry = entry.with_context(|| format!("Unable to read folder '{}'", root.display()))?;
+        let filename = entry.file_name().to_string_lossy();
+        let extension = entry.path().extension().map(|extension| extension.to_string_lossy());
+        if !entry.file_type().is_file()
+            || filename == CONFIG_FILENAME
+            || filename == MANIFEST_FILENAME && entry.depth() == 1
+            || extension.is_some_and(|extension| SOURCE_EXTENSIONS.contains(&extension.as_ref()))
+        {
+            continue;
+        }
+
+        let contents = std::fs::read(entry.path())
+            .with_context(|| format!("Unable to read file '{}'", entry.path().display()))?;
+        let path = entry.path().strip_prefix(root).unwrap_or(entry.path());
+        let path = path.components().map(|component| component.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/");
+        files.insert(path, super::content_hash(&contents));
+    }
+    Ok(files)
+}
+
+fn problem_path(problem: &Problem) -> &str {
+    match problem {
+        Problem::Missing(path) | Problem::Modified(path) | Problem::Unexpected(path) => path,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sitegen::testing::TempSite;
+
+    // Test that verification finds missing, modified and unexpected files, but ignores sources and private files.
+    #[test]
+    fn test_verify_against_manifest() {
+        let root = TempSite::new("checksums");
+        for (path, contents) in [("index.html", "a"), ("about.html", "b"), ("css/site.css", "c"), ("index.sgpage", "d")] {
+            root.write(path, contents);
+        }
+
+        let config = Config { quiet: true, ..Config::default() };
+        assert_eq!(write(&root, &config).unwrap(), 3);
+        assert_eq!(verify(&root).unwrap(), (Vec::new(), 3));
+
+        root.write("index.html", "tampered");
+        std::fs::remove_file(root.join("about.html")).unwrap();
+        root.write("css/extra.css", "e");
+        root.write(".git/HEAD", "f");
+        root.write("_report.html", "g");
+        let (problems, _) = verify(&root).unwrap();
+
+        assert_eq!(problems, [
+            Problem::Missing(String::from("about.html")),
+            Problem::Unexpected(String::from("css/extra.css")),
+            Problem::Modified(String::from("index.html")),
+        ]);
+    }
+}
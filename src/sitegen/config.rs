@@ -0,0 +1,450 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::sitegen::feeds::FeedConfig;
+use crate::sitegen::budget::BudgetConfig;
+use crate::sitegen::quality::QualityConfig;
+use crate::sitegen::replacements::ReplaceRule;
+use crate::sitegen::spelling::SpellCheckConfig;
+use crate::sitegen::validation::ValidationRule;
+use crate::sitegen::video::VideoConfig;
+
+// Name of the optional configuration file in the web root.
+pub const CONFIG_FILENAME: &str = "sitewinder.toml";
+
+// Format of { date } placeholders, unless configured otherwise.
+const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d";
+
+// Formats of dates in page metadata, unless configured otherwise, e.g. "2024-01-02",
+// "02 Jan 2024" and "2 January 2024".
+const DEFAULT_METADATA_DATE_FORMATS: [&str; 3] = ["%Y-%m-%d", "%d %b %Y", "%d %B %Y"];
+
+// Marker that separates a page's summary from the rest of its content, unless configured otherwise.
+const DEFAULT_EXCERPT_SEPARATOR: &str = "<!-- more -->";
+
+// Maximum number of words in an automatic excerpt, unless configured otherwise.
+const DEFAULT_EXCERPT_WORDS: usize = 50;
+
+// Build configuration shared by the site generator and all pages.
+// Options are read from sitewinder.toml in the web root, and may be overridden on the command line.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    // Append a ?v=<hash> query string to local asset references in the generated HTML,
+    // so browsers fetch the asset again whenever its contents change.
+    pub cache_bust: bool,
+
+    // Suppress the per-page progress output.
+    pub quiet: bool,
+
+    // Only regenerate the pages affected by changes made since the last incremental build.
+    pub incremental: bool,
+
+    // Write a build report to _report.html in the web root.
+    pub report: bool,
+
+    // Write a manifest with the SHA-256 checksums of the published files to SHA256SUMS in the
+    // web root, so a deployed copy of the site can be verified against it.
+    pub checksums: bool,
+
+    // After the build, report or delete the assets in the web root that no page references.
+    pub prune: Option<Prune>,
+
+    // Glob patterns of assets that are never pruned, relative to the web root, e.g. "downloads/*"
+    // for files that are only linked to from other sites.
+    pub prune_keep: Vec<String>,
+
+    // Allow include files, layouts and { group } files outside the web root, e.g. shared
+    // between several sites. By default, including a file outside the web root is an error.
+    pub allow_outside_root: bool,
+
+    // Tags that are excluded from tag clouds and tag page generation. Pages keep these
+    // tags in their metadata, so they can still be used to filter page listings.
+    pub hidden_tags: Vec<String>,
+
+    // Path the site is served from, e.g. "/myproject" for a site deployed under
+    // https://example.com/myproject/. If set, links to files in the web root are written as
+    // absolute paths below the base path, rather than relative to the current page.
+    pub base_path: Option<String>,
+
+    // Seed for { random_page } blocks. If not set, the build date is used, so the selected
+    // pages change once a day.
+    pub random_seed: Option<u64>,
+
+    // Maximum width and height of the thumbnails generated for { gallery } blocks, in pixels.
+    pub thumbnail_size: Option<u32>,
+
+    // Options for { video } embeds, by provider name.
+    pub video: BTreeMap<String, VideoConfig>,
+
+    // Replace straight quotes, "--", "---" and "..." with their typographic equivalents in
+    // the text of generated pages. Pages can override this in their metadata.
+    pub smart_typography: bool,
+
+    // Render math between $...$ and $$...$$ delimiters in the text of generated pages.
+    // Pages can override this in their metadata.
+    pub math: bool,
+
+    // Default language of the pages, e.g. "en" or "ar", set as the lang attribute of the
+    // <html> element. Pages can override this in their metadata.
+    pub lang: Option<String>,
+
+    // Default format of { date } placeholders, in strftime syntax. Defaults to "%Y-%m-%d".
+    pub date_format: Option<String>,
+
+    // First year of the range given by { copyright_years }, e.g. 2018. Defaults to the year of the
+    // oldest dated page.
+    pub copyright_start: Option<i32>,
+
+    // Formats of dates in page metadata, in strftime syntax, tried in order. Defaults to
+    // "%Y-%m-%d", "%d %b %Y" and "%d %B %Y". Dates with a time in RFC 3339 format are always
+    // accepted.
+    pub metadata_date_formats: Vec<String>,
+
+    // Marker that separates a page's summary from the rest of its content. Defaults to
+    // "<!-- more -->".
+    pub excerpt_separator: Option<String>,
+
+    // Maximum number of words in the excerpts taken from pages without an excerpt in their
+    // metadata. Defaults to 50.
+    pub excerpt_words: Option<usize>,
+
+    // Paths of pages that have been removed from the site, relative to the web root, e.g.
+    // "/posts/old-news.html". A minimal "Gone" page is generated at each path, so links to the
+    // page don't end in a "Not Found" error.
+    pub removed: Vec<String>,
+
+    // Preview build: generate draft pages too, and mark drafts and future-dated pages with a
+    // draft badge.
+    pub drafts: bool,
+
+    // Print the dates on which building the site again gives different output, e.g. as a
+    // scheduled page is due, so builds can be scheduled for those dates.
+    pub next_change: bool,
+
+    // Generate pages dated in the future too. Otherwise they're left out until their date, so
+    // posts can be scheduled and are published by the first build on or after their date.
+    pub include_future: bool,
+
+    // Options for spell checking the generated pages with the lint command. Pages are only
+    // spell checked if this is set.
+    pub spell_check: Option<SpellCheckConfig>,
+
+    // Options for checking the titles, descriptions and dates of pages, which are reported after
+    // the build and by the lint command. Pages are only checked if this is set.
+    pub quality: Option<QualityConfig>,
+
+    // Performance budgets that generated pages are checked against, such as their size, which
+    // are reported after the build and by the lint command. Pages are only checked if this is set.
+    pub budget: Option<BudgetConfig>,
+
+    // Atom feeds of the site's newest dated pages, with optional yearly feeds, from [[feed]]
+    // tables, e.g. one of every post and one of a single group.
+    pub feed: Vec<FeedConfig>,
+
+    // Rules for the metadata of pages, e.g. keys that the pages of a group must have, from
+    // [[validate]] tables. Pages that break a rule fail the build.
+    pub validate: Vec<ValidationRule>,
+
+    // Make metadata keys that aren't built in an error, unless they're listed in custom_metadata
+    // or set in the [site] table, so misspelled keys like "Tags" aren't silently stored as custom
+    // metadata.
+    pub strict_metadata: bool,
+
+    // Custom metadata keys that pages may use with strict_metadata, e.g. "hero_image".
+    pub custom_metadata: Vec<String>,
+
+    // Layouts that pages select by name with their template metadata, from the [layouts] table,
+    // e.g. article = "/layouts/article.sginc".
+    pub layouts: BTreeMap<String, String>,
+
+    // Site-wide values for { site.<key> } placeholders, e.g. the name, base URL and author of
+    // the site, from the [site] table. Pages can override them with metadata of the same name.
+    pub site: toml::Table,
+
+    // Build variables for { var.<key> } placeholders, from the [define] table. Variables given
+    // on the command line with --define take precedence.
+    pub define: BTreeMap<String, String>,
+
+    // Line endings of the generated pages. Templates may use either, as their line endings are
+    // converted to "\n" when they're read.
+    pub line_endings: LineEndings,
+
+    // Order of pages with the same weight and date in groups, on tag pages and in listings sorted
+    // by date.
+    pub tiebreaker: Tiebreaker,
+
+    // Find/replace rules applied, in order, to the text of generated pages, from [[replace]]
+    // tables. Pages can opt out in their metadata.
+    pub replace: Vec<ReplaceRule>,
+
+    // Path of a stylesheet relative to the web root, e.g. "/css/critical.css", with the styles
+    // needed to show the top of the pages. It's inlined into the <head> of every page, and the
+    // pages' other stylesheets are loaded without blocking the first paint.
+    pub critical_css: Option<String>,
+
+    // Command that every generated page is passed through as the final step, given as the
+    // program and its arguments, e.g. ["tidy", "-quiet"]. It reads the page on its standard input
+    // and writes the transformed page to its standard output.
+    pub post_process: Vec<String>,
+
+    // The site owner's profiles on other sites, from [[social]] tables, for { social } blocks and
+    // the <link rel="me"> tags added to every page, which verify the profiles as the site's.
+    pub social: Vec<SocialProfile>,
+
+    // The site's authors by id, from [authors.<id>] tables, e.g. [authors.jane] with her name and
+    // URL. Pages give an id as their author to use the author's details, and so can the author in
+    // the [site] table.
+    pub authors: BTreeMap<String, Author>,
+
+    // Overrides of the options above for one environment, e.g. dev and prod, from [profiles.<name>]
+    // tables, selected with --profile. A profile's tables are merged into the site's, so a profile
+    // can override a single [site] or [define] value.
+    pub profiles: BTreeMap<String, toml::Table>,
+}
+
+// Line endings of the generated pages: "lf" for "\n", or "crlf" for "\r\n" as used on Windows.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LineEndings {
+    #[default]
+    Lf,
+    Crlf,
+}
+
+// Order of pages with the same weight and date: by "filename", i.e. by the path of the generated
+// page, or alphabetically by "title", and then by path.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Tiebreaker {
+    #[default]
+    Filename,
+    Title,
+}
+
+// What to do with assets that no page references: "report" them, or "delete" them.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Prune {
+    Report,
+    Delete,
+}
+
+// A profile on another site, e.g. name = "Mastodon" and url = "https://mastodon.social/@jane".
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SocialProfile {
+    pub name: String,
+    pub url: String,
+}
+
+// The author of a page: a name, with an email address, a URL, e.g. of the author's home page, and
+// an avatar image if known.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Author {
+    pub name: String,
+    pub email: String,
+    pub url: String,
+    pub avatar: String,
+}
+
+impl Author {
+    // Value of a field for { author.<field> } placeholders.
+    pub fn field(&self, field: &str) -> Option<&str> {
+        match field {
+            "name" => Some(&self.name),
+            "email" => Some(&self.email),
+            "url" => Some(&self.url),
+            "avatar" => Some(&self.avatar),
+            _ => None,
+        }
+    }
+}
+
+impl Config {
+    // Read the configuration file from the web root, or use the default configuration
+    // if the web root doesn't contain a configuration file. If a profile is given, its options
+    // override those of the configuration file.
+    pub fn load(root: &Path, profile: Option<&str>) -> Result<Config> {
+        let path = root.join(CONFIG_FILENAME);
+        let contents = if path.is_file() {
+            std::fs::read_to_string(&path)
+                .with_context(|| format!("Unable to read configuration file '{}'", path.display()))?
+        } else {
+            String::new()
+        };
+        Config::parse(&contents, profile)
+            .with_context(|| format!("Invalid configuration file '{}'", path.display()))
+    }
+
+    // Parse the contents of a configuration file, with the options of the given profile merged
+    // in. Every profile is checked, so a mistake in one is found whichever profile is used.
+    fn parse(contents: &str, profile: Option<&str>) -> Result<Config> {
+        let config: Config = toml::from_str(contents)?;
+        let mut selected = None;
+        for (name, overrides) in &config.profiles {
+            let mut table: toml::Table = toml::from_str(contents)?;
+            table.remove("profiles");
+            merge_tables(&mut table, overrides.clone());
+            let merged = toml::Value::Table(table).try_into::<Config>()
+                .with_context(|| format!("Invalid profile '{}'", name))?;
+            if profile == Some(name.as_str()) {
+                selected = Some(merged);
+            }
+        }
+
+        match (profile, selected) {
+            (None, _) => Ok(config),
+            (Some(_), Some(merged)) => Ok(merged),
+            (Some(profile), None) => {
+                let names: Vec<&str> = config.profiles.keys().map(String::as_str).collect();
+                anyhow::bail!("Unknown profile '{}' (profiles: {})", profile,
+                    if names.is_empty() { String::from("none") } else { names.join(", ") })
+            }
+        }
+    }
+
+    // Check whether a tag is hidden from tag clouds and tag pages (case-insensitive).
+    pub fn is_hidden_tag(&self, tag: &str) -> bool {
+        self.hidden_tags.iter().any(|hidden| hidden.to_lowercase() == tag.to_lowercase())
+    }
+
+    // The format of { date } placeholders without a format of their own.
+    pub fn date_format(&self) -> &str {
+        self.date_format.as_deref().unwrap_or(DEFAULT_DATE_FORMAT)
+    }
+
+    // The formats of dates in page metadata, tried in order.
+    pub fn metadata_date_formats(&self) -> Vec<&str> {
+        if self.metadata_date_formats.is_empty() {
+            DEFAULT_METADATA_DATE_FORMATS.to_vec()
+        } else {
+            self.metadata_date_formats.iter().map(String::as_str).collect()
+        }
+    }
+
+    // The marker that separates a page's summary from the rest of its content.
+    pub fn excerpt_separator(&self) -> &str {
+        self.excerpt_separator.as_deref().unwrap_or(DEFAULT_EXCERPT_SEPARATOR)
+    }
+
+    // The maximum number of words in an automatic excerpt.
+    pub fn excerpt_words(&self) -> usize {
+        self.excerpt_words.unwrap_or(DEFAULT_EXCERPT_WORDS)
+    }
+
+    // The site's URL from the base_url in the [site] table, without a trailing slash, for links
+    // that must be absolute, such as those of feeds and canonical URLs.
+    pub fn base_url(&self) -> Option<&str> {
+        self.site.get("base_url").and_then(toml::Value::as_str).map(|url| url.trim_end_matches('/'))
+    }
+
+    // The absolute URL of a path relative to the web root, e.g. "/posts/a.html", below the site's
+    // base_url and the base path. A base_url that already ends with the base path doesn't get it
+    // twice. None if there is no base_url.
+    pub fn absolute_url(&self, path: &str) -> Option<String> {
+        let base_url = self.base_url()?;
+        let prefix = self.base_prefix().unwrap_or_default();
+        let prefix = if base_url.ends_with(&prefix) { "" } else { prefix.as_str() };
+        Some(format!("{}{}{}", base_url, prefix, path))
+    }
+
+    // Check whether pages may use a metadata key that isn't built in: any key, unless
+    // strict_metadata is set.
+    pub fn allows_custom_metadata(&self, key: &str) -> bool {
+        !self.strict_metadata || self.custom_metadata.iter().any(|custom| custom == key) || self.site.contains_key(key)
+    }
+
+    // The author with the given id, or an author with the given name if there is no author with
+    // that id. An author configured without a name is named by their id.
+    pub fn author(&self, author: &str) -> Author {
+        match self.authors.get(author) {
+            Some(configured) if configured.name.is_empty() => Author { name: author.to_string(), ..configured.clone() },
+            Some(configured) => configured.clone(),
+            None => Author { name: author.to_string(), ..Author::default() },
+        }
+    }
+
+    // Options for embedding videos from the given provider.
+    pub fn video_config(&self, provider: &str) -> VideoConfig {
+        self.video.get(provider).cloned().unwrap_or_default()
+    }
+
+    // The base path with a leading slash and without a trailing slash, e.g. "/myproject", or an
+    // empty string if the base path is "/". Returns None if no base path is set.
+    pub fn base_prefix(&self) -> Option<String> {
+        let base_path = self.base_path.as_ref()?.trim_matches('/');
+        Some(if base_path.is_empty() { String::new() } else { format!("/{}", base_path) })
+    }
+
+    // Remove the base path from a link that already includes it, giving the link relative to
+    // the web root. Other links are returned unchanged.
+    pub fn strip_base_prefix<'a>(&self, url: &'a str) -> &'a str {
+        match self.base_prefix() {
+            Some(prefix) if !prefix.is_empty() => url
+                .strip_prefix(prefix.as_str())
+                .filter(|rest| rest.starts_with('/'))
+                .unwrap_or(url),
+            _ => url,
+        }
+    }
+}
+
+// Merge the values of one TOML table into another. Values of nested tables are merged key by key,
+// and other values, including arrays, replace those of the same key.
+fn merge_tables(table: &mut toml::Table, overrides: toml::Table) {
+    for (key, value) in overrides {
+        match (table.get_mut(&key), value) {
+            (Some(toml::Value::Table(existing)), toml::Value::Table(nested)) => merge_tables(existing, nested),
+            (_, value) => {
+                table.insert(key, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Test that a profile overrides options and single values of tables, and that unknown
+    // profiles and mistakes in any profile are errors.
+    #[test]
+    fn test_profiles() {
+        let contents = r#"
+            drafts = true
+            post_process = ["tidy"]
+            [site]
+            name = "Travel Notes"
+            base_url = "http://localhost:8000"
+            [define]
+            analytics = ""
+            [profiles.prod]
+            drafts = false
+            post_process = []
+            site.base_url = "https://example.com"
+            define.analytics = "<script src=\"/stats.js\"></script>"
+            [profiles.dev]
+        "#;
+
+        let config = Config::parse(contents, None).unwrap();
+        assert!(config.drafts);
+        assert_eq!(config.base_url(), Some("http://localhost:8000"));
+
+        let prod = Config::parse(contents, Some("prod")).unwrap();
+        assert!(!prod.drafts);
+        assert!(prod.post_process.is_empty());
+        assert_eq!(prod.base_url(), Some("https://example.com"));
+        assert_eq!(prod.site.get("name").and_then(toml::Value::as_str), Some("Travel Notes"));
+        assert_eq!(prod.define["analytics"], "<script src=\"/stats.js\"></script>");
+        assert!(prod.profiles.is_empty());
+
+        assert!(Config::parse(contents, Some("dev")).unwrap().drafts);
+        let error = Config::parse(contents, Some("staging")).unwrap_err();
+        assert_eq!(error.to_string(), "Unknown profile 'staging' (profiles: dev, prod)");
+        assert!(Config::parse("[profiles.prod]\ndraft = true", None).is_err());
+    }
+}
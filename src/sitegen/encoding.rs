@@ -0,0 +1,75 @@
+use anyhow::{Context, Result};
+use std::path::Path;
+
+// Characters of the bytes 0x80 to 0x9F in Windows-1252, the variant of Latin-1 that text editors
+// on Windows write, and that browsers use for Latin-1 pages. Bytes that Windows-1252 doesn't
+// define are decoded as in Latin-1.
+const WINDOWS_1252: [char; 32] = [
+    '\u{20AC}', '\u{81}', '\u{201A}', '\u{192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{2C6}', '\u{2030}', '\u{160}', '\u{2039}', '\u{152}', '\u{8D}', '\u{17D}', '\u{8F}',
+    '\u{90}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{2DC}', '\u{2122}', '\u{161}', '\u{203A}', '\u{153}', '\u{9D}', '\u{17E}', '\u{178}',
+];
+
+// Read a text file, such as a template or include file, decoding it with `decode`. Files that
+// aren't text are an error.
+pub fn read_text(path: &Path) -> Result<String> {
+    let bytes = std::fs::read(path).with_context(|| format!("Unable to read file '{}'", path.display()))?;
+    decode(&bytes).with_context(|| format!("File '{}' is not a text file", path.display()))
+}
+
+// Decode the contents of a text file: UTF-16 with a byte order mark, UTF-8, or otherwise Latin-1.
+// A UTF-8 byte order mark is removed, and Windows (CRLF) line endings are converted to "\n", so
+// templates edited on Windows are parsed like any other. Gives None for binary files, i.e. files
+// with control characters that text files don't contain.
+pub fn decode(bytes: &[u8]) -> Option<String> {
+    decode_text(bytes).map(|text| text.strip_prefix('\u{FEFF}').unwrap_or(&text).replace("\r\n", "\n"))
+}
+
+fn decode_text(bytes: &[u8]) -> Option<String> {
+    if let Some(text) = bytes.strip_prefix(&[0xFF, 0xFE]) {
+        return decode_utf16(text, u16::from_le_bytes);
+    }
+    if let Some(text) = bytes.strip_prefix(&[0xFE, 0xFF]) {
+        return decode_utf16(text, u16::from_be_bytes);
+    }
+    if let Ok(text) = std::str::from_utf8(bytes) {
+        return Some(text.to_string());
+    }
+
+    if bytes.iter().any(|&byte| (byte < 0x20 && !b"\t\n\x0C\r".contains(&byte)) || byte == 0x7F) {
+        return None;
+    }
+    Some(bytes.iter()
+        .map(|&byte| match byte {
+            0x80..=0x9F => WINDOWS_1252[usize::from(byte - 0x80)],
+            _ => char::from(byte),
+        })
+        .collect())
+}
+
+fn decode_utf16(bytes: &[u8], from_bytes: fn([u8; 2]) -> u16) -> Option<String> {
+    if !bytes.len().is_multiple_of(2) {
+        return None;
+    }
+    let units: Vec<u16> = bytes.chunks_exact(2).map(|pair| from_bytes([pair[0], pair[1]])).collect();
+    String::from_utf16(&units).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Test that UTF-8, UTF-16 and Latin-1 text is decoded, and binary files aren't.
+    #[test]
+    fn test_decode_text_encodings() {
+        assert_eq!(decode("Café { title }".as_bytes()).as_deref(), Some("Café { title }"));
+        assert_eq!(decode(b"\xFF\xFEC\x00a\x00f\x00\xE9\x00").as_deref(), Some("Café"));
+        assert_eq!(decode(b"\xFE\xFF\x00C\x00a\x00f\x00\xE9").as_deref(), Some("Café"));
+        assert_eq!(decode(b"Caf\xE9 \x93cr\xE8me\x94\r\n").as_deref(), Some("Café \u{201C}crème\u{201D}\n"));
+        assert_eq!(decode(b"\xEF\xBB\xBF--\r\ntitle: Caf\xC3\xA9\r\n--\r\n").as_deref(), Some("--\ntitle: Café\n--\n"));
+
+        assert_eq!(decode(b"\x89PNG\r\n\x1A\n\x00\x00\x00\rIHDR"), None);
+        assert_eq!(decode(b"\xFF\xFEC\x00a"), None);
+    }
+}
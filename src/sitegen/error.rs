@@ -0,0 +1,43 @@
+use std::path::PathBuf;
+use thiserror::Error;
+
+// Errors in reading a page template: its path, and the metadata at its start. Each error names
+// the template, given by its path relative to the web root, e.g. "/posts/italy.sgpage".
+#[derive(Debug, Error)]
+pub enum PageError {
+    #[error("Page template '{path}' is outside the web root '{root}'")]
+    OutsideRoot { path: PathBuf, root: PathBuf },
+
+    #[error("The path of page template '{0}' isn't valid UTF-8")]
+    InvalidPath(PathBuf),
+
+    #[error("The metadata block of page template '{template}' isn't closed with '--'")]
+    UnclosedMetadata { template: String },
+
+    #[error("Invalid date '{value}' in page template '{template}', expected a date in one of the formats {formats}")]
+    InvalidDate { template: String, value: String, formats: String },
+
+    #[error("Invalid weight '{value}' in page template '{template}', expected a whole number")]
+    InvalidWeight { template: String, value: String, source: std::num::ParseIntError },
+
+    #[error("Invalid {key} value '{value}' in page template '{template}', expected 'true' or 'false'")]
+    InvalidFlag { template: String, key: String, value: String },
+
+    #[error("Invalid slug '{slug}' in page template '{template}', expected a file name without folders or extension")]
+    InvalidSlug { template: String, slug: String },
+
+    #[error("Unknown template '{name}' in page template '{template}', expected one of the layouts configured in [layouts]: {names}")]
+    UnknownTemplate { template: String, name: String, names: String },
+
+    #[error("Invalid alias '{alias}' in page template '{template}', expected a path ending in '.html' or '/'")]
+    InvalidAlias { template: String, alias: String },
+
+    #[error("Invalid output path '{output}' in page template '{template}', expected a path in the web root ending in '.html' or '/'")]
+    InvalidOutput { template: String, output: String },
+
+    #[error("Unknown metadata key '{key}' in page template '{template}'{suggestion}")]
+    UnknownMetadata { template: String, key: String, suggestion: String },
+
+    #[error("Invalid [vars] section in page template '{template}'")]
+    InvalidVars { template: String, source: toml::de::Error },
+}
@@ -0,0 +1,338 @@
+use anyhow::{Context, Result};
+use chrono::{Datelike, NaiveDate};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+
+use crate::sitegen::config::Config;
+use crate::sitegen::metadata::Metadata;
+use crate::sitegen::page::escape_html_characters;
+use crate::sitegen::regexes::*;
+
+// Path of the site's feed, unless configured otherwise.
+const DEFAULT_FEED_PATH: &str = "/feed.xml";
+
+// Number of pages in the site's feed, unless configured otherwise.
+const DEFAULT_FEED_ITEMS: usize = 20;
+
+// Path of the yearly feeds, unless configured otherwise. "{year}" is replaced with the year.
+const DEFAULT_YEARLY_PATH: &str = "/feed/{year}.xml";
+
+// Options for an Atom feed, set in a [[feed]] table in the configuration file, one per feed.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeedConfig {
+    // Path of the feed relative to the web root. Defaults to "/feed.xml".
+    pub path: Option<String>,
+
+    // Number of pages in the feed, newest first. Defaults to 20.
+    pub items: Option<usize>,
+
+    // Whether entries contain the page's excerpt, or its whole content.
+    pub content: FeedContent,
+
+    // Only include pages in one of these groups, or with one of these tags.
+    pub groups: Vec<String>,
+    pub tags: Vec<String>,
+
+    // Leave out pages in any of these groups, or with any of these tags.
+    pub exclude_groups: Vec<String>,
+    pub exclude_tags: Vec<String>,
+
+    // Also generate a feed of all pages of each year, so readers can catch up on a whole year.
+    pub yearly: bool,
+
+    // Path of the yearly feeds, in which "{year}" is replaced with the year. Defaults to
+    // "/feed/{year}.xml".
+    pub yearly_path: Option<String>,
+}
+
+// What feed entries contain: the page's "summary", i.e. its excerpt, or its "full" content, i.e.
+// the generated HTML of its <main> or <article> element, or of its <body>.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FeedContent {
+    #[default]
+    Summary,
+    Full,
+}
+
+impl FeedConfig {
+    pub fn path(&self) -> String {
+        format!("/{}", self.path.as_deref().unwrap_or(DEFAULT_FEED_PATH).trim_start_matches('/'))
+    }
+
+    pub fn items(&self) -> usize {
+        self.items.unwrap_or(DEFAULT_FEED_ITEMS)
+    }
+
+    // Path of the yearly feeds, with "{year}" in place of the year.
+    fn yearly_pattern(&self) -> String {
+        format!("/{}", self.yearly_path.as_deref().unwrap_or(DEFAULT_YEARLY_PATH).trim_start_matches('/'))
+    }
+
+    pub fn yearly_path(&self, year: i32) -> String {
+        self.yearly_pattern().replace("{year}", &year.to_string())
+    }
+
+    // Whether a path relative to the web root is one of the feeds, so it isn't pruned as an
+    // unreferenced asset.
+    pub fn is_feed(&self, path: &str) -> bool {
+        if path == self.path() {
+            return true;
+        }
+        let pattern = self.yearly_pattern();
+        let Some((prefix, suffix)) = pattern.split_once("{year}").filter(|_| self.yearly) else {
+            return false;
+        };
+        path.strip_prefix(prefix).and_then(|rest| rest.strip_suffix(suffix))
+            .is_some_and(|year| !year.is_empty() && year.chars().all(|c| c.is_ascii_digit()))
+    }
+
+    // Whether a page passes the feed's group and tag filters. Tags are compared case-insensitively.
+    fn includes(&self, page: &Metadata) -> bool {
+        let tagged = |tags: &[String]| page.tags.iter().flatten()
+            .any(|tag| tags.iter().any(|filter| filter.to_lowercase() == tag.to_lowercase()));
+        ((self.groups.is_empty() && self.tags.is_empty()) || self.groups.iter().any(|group| page.in_group(group)) || tagged(&self.tags))
+            && !self.exclude_groups.iter().any(|group| page.in_group(group))
+            && !tagged(&self.exclude_tags)
+    }
+}
+
+// Write the configured feeds of the site's dated pages, i.e. their newest pages, and their
+// yearly feeds if configured. Pages without a date aren't included. Returns the paths of the
+// feeds, relative to the web root.
+pub fn write(root: &Path, pages: &[Metadata], config: &Config) -> Result<Vec<String>> {
+    let mut files = Vec::new();
+    for feed in &config.feed {
+        files.extend(feed_files(pages, feed)?.into_iter().map(|(path, pages)| (path, pages, feed)));
+    }
+
+    // Check the paths before anything is written, so no feed overwrites another
+    let mut unique = HashSet::new();
+    if let Some((path, _, _)) = files.iter().find(|(path, _, _)| !unique.insert(path.as_str())) {
+        anyhow::bail!("More than one feed would be written to '{}', give the feeds different paths", path);
+    }
+
+    for (path, pages, feed) in &files {
+        if !config.quiet {
+            println!("generating feed '{}'", path);
+        }
+        let file = root.join(path.trim_start_matches('/'));
+        if let Some(folder) = file.parent() {
+            std::fs::create_dir_all(folder).with_context(|| format!("Unable to create folder '{}'", folder.display()))?;
+        }
+        std::fs::write(&file, atom(root, path, pages, feed, config)?).with_context(|| format!("Unable to write feed '{}'", file.display()))?;
+    }
+    Ok(files.into_iter().map(|(path, _, _)| path).collect())
+}
+
+// The files of a feed, by path: the feed of its newest pages, and its yearly feeds if
+// configured, each with its pages, newest first.
+fn feed_files<'p>(pages: &'p [Metadata], feed: &FeedConfig) -> Result<Vec<(String, Vec<&'p Metadata>)>> {
+    if feed.yearly && !feed.yearly_pattern().contains("{year}") {
+        anyhow::bail!("Invalid yearly_path '{}' in a [[feed]] table, expected a path containing {{year}}", feed.yearly_pattern());
+    }
+
+    let mut dated: Vec<(NaiveDate, &Metadata)> = pages.iter()
+        .filter(|page| feed.includes(page))
+        .filter_map(|page| Some((page.date?, page)))
+        .collect();
+    dated.sort_by(|lhs, rhs| rhs.0.cmp(&lhs.0).then_with(|| lhs.1.path.cmp(&rhs.1.path)));
+
+    let mut files = vec![(feed.path(), dated.iter().take(feed.items()).map(|(_, page)| *page).collect::<Vec<_>>())];
+    if feed.yearly {
+        let mut years: BTreeMap<i32, Vec<&Metadata>> = BTreeMap::new();
+        for (date, page) in &dated {
+            years.entry(date.year()).or_default().push(page);
+        }
+        files.extend(years.into_iter().map(|(year, pages)| (feed.yearly_path(year), pages)));
+    }
+    Ok(files)
+}
+
+// An Atom feed of pages, with absolute links below the site's base_url and base path. Titles, excerpts and
+// contents are HTML, so they are escaped once more for the XML. The links in a page's content
+// are relative to the page, which its xml:base gives.
+fn atom(root: &Path, path: &str, pages: &[&Metadata], feed: &FeedConfig, config: &Config) -> Result<String> {
+    let site = |key: &str| config.site.get(key).and_then(toml::Value::as_str);
+    let base_url = config.base_url()
+        .context("Feeds need the site's URL, set base_url in the [site] table of the configuration file")?;
+    let timestamp = |date: Option<NaiveDate>| date.map_or(String::new(), |date| format!("{}T00:00:00Z", date));
+    // Pages that were revised after their date are updated on the date of the revision
+    let updated = |page: &Metadata| page.updated.or(page.date);
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+    xml.push_str(&format!("<title type=\"html\">{}</title>\n", escape_xml(&escape_html_characters(site("name").unwrap_or(base_url)))));
+    let (home, self_url) = (config.absolute_url("/").unwrap_or_default(), escape_xml(&config.absolute_url(path).unwrap_or_default()));
+    xml.push_str(&format!("<link href=\"{}\"/>\n<link rel=\"self\" href=\"{}\"/>\n", escape_xml(&home), self_url));
+    xml.push_str(&format!("<id>{}</id>\n<updated>{}</updated>\n", self_url, timestamp(pages.iter().filter_map(|page| updated(page)).max())));
+    if let Some(author) = site("author") {
+        xml.push_str(&format!("<author><name>{}</name></author>\n", escape_xml(&config.author(author).name)));
+    }
+    for page in pages {
+        let url = escape_xml(&config.absolute_url(&super::url_encode_path(&page.path)).unwrap_or_default());
+        xml.push_str("<entry>\n");
+        xml.push_str(&format!("<title type=\"html\">{}</title>\n", escape_xml(&escape_html_characters(&page.title))));
+        xml.push_str(&format!("<link href=\"{}\"/>\n<id>{}</id>\n<updated>{}</updated>\n", url, url, timestamp(updated(page))));
+        if page.updated.is_some() && page.date.is_some() {
+            xml.push_str(&format!("<published>{}</published>\n", timestamp(page.date)));
+        }
+        if !page.author.name.is_empty() {
+            xml.push_str(&format!("<author><name>{}</name>", escape_xml(&page.author.name)));
+            if !page.author.url.is_empty() {
+                xml.push_str(&format!("<uri>{}</uri>", escape_xml(&page.author.url)));
+            }
+            if !page.author.email.is_empty() {
+                xml.push_str(&format!("<email>{}</email>", escape_xml(&page.author.email)));
+            }
+            xml.push_str("</author>\n");
+        }
+        let content = match feed.content {
+            FeedContent::Full => std::fs::read_to_string(root.join(page.path.trim_start_matches('/'))).ok(),
+            FeedContent::Summary => None,
+        };
+        match content {
+            Some(html) => xml.push_str(&format!("<content type=\"html\" xml:base=\"{}\">{}</content>\n", url, escape_xml(main_content(&html).trim()))),
+            None if !page.excerpt.is_empty() => xml.push_str(&format!("<summary type=\"html\">{}</summary>\n", escape_xml(&page.excerpt))),
+            None => {}
+        }
+        xml.push_str("</entry>\n");
+    }
+    xml.push_str("</feed>\n");
+    Ok(xml)
+}
+
+// The main content of a generated page: the contents of its <main> element, or of its <article>
+// element or <body>, or the whole page if it has none of them.
+fn main_content(html: &str) -> &str {
+    if let Some(caps) = RE_MAIN_ELEMENT.captures(html).or_else(|| RE_ARTICLE_ELEMENT.captures(html)) {
+        return caps.name("content").map_or("", |content| content.as_str());
+    }
+    let start = RE_BODY_START.find(html).map_or(0, |body| body.end());
+    let end = RE_BODY_END.find_at(html, start).map_or(html.len(), |body| body.start());
+    &html[start..end]
+}
+
+// Escape text for XML, including the ampersands of character references, so HTML is kept as it is.
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sitegen::testing::TempSite;
+    use crate::sitegen::config::Author;
+
+    // Test that a feed lists the newest dated pages, that yearly feeds list all pages of their
+    // year, that revised pages are updated on the date of the revision, and that titles and
+    // excerpts are escaped.
+    #[test]
+    fn test_feeds() {
+        let root = TempSite::new("feeds");
+        let config: Config = toml::from_str(r#"
+            quiet = true
+            [site]
+            name = "Fish & Chips"
+            base_url = "https://example.com/"
+            [[feed]]
+            items = 2
+            yearly = true
+        "#).unwrap();
+        let page = |path: &str, title: &str, date: Option<(i32, u32)>| Metadata {
+            title: title.to_string(),
+            date: date.and_then(|(year, month)| NaiveDate::from_ymd_opt(year, month, 1)),
+            excerpt: String::from("Crisp &amp; <em>hot</em>"),
+            ..Metadata::new(path)
+        };
+        let pages = [
+            Metadata { updated: NaiveDate::from_ymd_opt(2024, 2, 1), ..page("/a.html", "Cod", Some((2023, 5))) },
+            Metadata { author: Author { name: String::from("Ann"), url: String::from("https://ann.example.com/"), ..Author::default() }, ..page("/b.html", "Haddock", Some((2024, 1))) },
+            page("/c.html", "Plaice", Some((2024, 6))),
+            page("/about.html", "About", None),
+        ];
+
+        let written = write(&root, &pages, &config).unwrap();
+        let (latest, year) = (root.read("feed.xml"), root.read("feed/2023.xml"));
+
+        let feed = &config.feed[0];
+        assert_eq!(written, ["/feed.xml", "/feed/2023.xml", "/feed/2024.xml"]);
+        let titles = |xml: &str| xml.match_indices("<entry>\n<title type=\"html\">").map(|(i, tag)| xml[i + tag.len()..].split('<').next().unwrap().to_string()).collect::<Vec<_>>();
+        assert_eq!(titles(&latest), ["Plaice", "Haddock"]);
+        assert_eq!(titles(&year), ["Cod"]);
+        assert!(latest.contains("<title type=\"html\">Fish &amp;amp; Chips</title>\n<link href=\"https://example.com/\"/>\n\
+            <link rel=\"self\" href=\"https://example.com/feed.xml\"/>\n<id>https://example.com/feed.xml</id>\n<updated>2024-06-01T00:00:00Z</updated>"));
+        assert!(latest.contains("<link href=\"https://example.com/c.html\"/>\n<id>https://example.com/c.html</id>\n<updated>2024-06-01T00:00:00Z</updated>\n<summary"));
+        assert!(latest.contains("<author><name>Ann</name><uri>https://ann.example.com/</uri></author>\n"));
+        assert!(year.contains("<id>https://example.com/feed/2023.xml</id>\n<updated>2024-02-01T00:00:00Z</updated>"));
+        assert!(year.contains("<updated>2024-02-01T00:00:00Z</updated>\n<published>2023-05-01T00:00:00Z</published>"));
+        assert!(latest.contains("<summary type=\"html\">Crisp &amp;amp; &lt;em&gt;hot&lt;/em&gt;</summary>"));
+
+        assert!(feed.is_feed("/feed.xml") && feed.is_feed("/feed/2023.xml"));
+        assert!(!feed.is_feed("/feed/notes.xml"));
+    }
+
+    // Test that feeds select pages by group and tag, and contain the pages' main content if
+    // configured, and that feeds at the same path are refused.
+    #[test]
+    fn test_feed_options() {
+        let root = TempSite::new("feed-options");
+        root.write("a.html", "<body><nav>Menu</nav><main>\n<p>Cod &amp; <a href=\"b.html\">chips</a></p>\n</main></body>");
+        root.write("c.html", "<body><p>Plaice</p></body>");
+        let config: Config = toml::from_str(r#"
+            quiet = true
+            [site]
+            base_url = "https://example.com"
+            [[feed]]
+            path = "/fish.xml"
+            content = "full"
+            groups = ["fish"]
+            exclude_tags = ["Draft"]
+            [[feed]]
+            path = "/recipes.xml"
+            tags = ["recipe"]
+        "#).unwrap();
+        let page = |path: &str, group: &str, tags: &[&str]| Metadata {
+            group: Some(group.to_string()),
+            tags: Some(tags.iter().map(|tag| tag.to_string()).collect()),
+            date: NaiveDate::from_ymd_opt(2024, 5, 1),
+            excerpt: String::from("Excerpt"),
+            ..Metadata::new(path)
+        };
+        let pages = [page("/a.html", "fish", &["recipe"]), page("/b.html", "fish", &["draft"]), page("/c.html", "chips", &["Recipe"])];
+
+        let written = write(&root, &pages, &config);
+        let (fish, recipes) = (root.read("fish.xml"), root.read("recipes.xml"));
+        let clash: Config = toml::from_str("[site]\nbase_url = \"https://example.com\"\n[[feed]]\n[[feed]]\ntags = [\"recipe\"]").unwrap();
+        let clashing = write(&root, &pages, &clash);
+        let clash_written = root.join("feed.xml").exists();
+
+        assert_eq!(written.unwrap(), ["/fish.xml", "/recipes.xml"]);
+        let links = |xml: &str| xml.matches("<entry>\n").count();
+        assert_eq!((links(&fish), links(&recipes)), (1, 2));
+        assert!(fish.contains("<content type=\"html\" xml:base=\"https://example.com/a.html\">\
+            &lt;p&gt;Cod &amp;amp; &lt;a href=&quot;b.html&quot;&gt;chips&lt;/a&gt;&lt;/p&gt;</content>"));
+        assert!(recipes.contains("<summary type=\"html\">Excerpt</summary>") && !recipes.contains("<content"));
+        assert!(clashing.is_err());
+        assert!(!clash_written);
+    }
+
+    // Test that the links of feeds are written below the base path, whether the base_url
+    // includes it or not.
+    #[test]
+    fn test_feed_base_path() {
+        let page = Metadata { date: NaiveDate::from_ymd_opt(2024, 5, 1), ..Metadata::new("/posts/fish chips.html") };
+        let feed = |base_url: &str| {
+            let config: Config = toml::from_str(&format!("base_path = \"/blog/\"\n[site]\nbase_url = \"{}\"", base_url)).unwrap();
+            atom(Path::new(""), "/feed.xml", &[&page], &FeedConfig::default(), &config).unwrap()
+        };
+
+        for xml in [feed("https://example.com"), feed("https://example.com/blog/")] {
+            assert!(xml.contains("<link href=\"https://example.com/blog/\"/>\n<link rel=\"self\" href=\"https://example.com/blog/feed.xml\"/>\n\
+                <id>https://example.com/blog/feed.xml</id>"));
+            assert!(xml.contains("<link href=\"https://example.com/blog/posts/fish%20chips.html\"/>\n\
+                <id>https://example.com/blog/posts/fish%20chips.html</id>"));
+        }
+    }
+}
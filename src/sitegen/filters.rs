@@ -0,0 +1,48 @@
+use anyhow::{Context, Result};
+
+use crate::sitegen::template::Filter;
+
+// Apply the filters of a placeholder, e.g. { title | upper | truncate 60 }, to its value. Filters
+// are applied from left to right. Placeholders without filters give the value as it is.
+pub fn apply(value: &str, filters: &[Filter]) -> Result<String> {
+    let mut value = value.to_string();
+    for filter in filters {
+        let name = filter.name.as_str();
+        let args: Vec<&str> = filter.args.iter().map(String::as_str).collect();
+        value = match (name, args.as_slice()) {
+            ("upper", []) => value.to_uppercase(),
+            ("lower", []) => value.to_lowercase(),
+            ("slugify", []) => super::slugify(&value),
+            ("truncate", [length]) => {
+                let length = length.parse()
+                    .with_context(|| format!("Invalid length '{}' for filter 'truncate'", length))?;
+                truncate(&value, length)
+            }
+            // Placeholders are escaped for HTML after the filters have been applied, unless they
+            // have a `raw` filter
+            ("raw", []) => value,
+            // The fallback for placeholders without a value, e.g. metadata that a page doesn't set
+            ("default", [fallback]) => if value.is_empty() { fallback.to_string() } else { value },
+            ("upper" | "lower" | "slugify" | "raw", _) => anyhow::bail!("Filter '{}' doesn't take arguments", name),
+            ("truncate", _) => anyhow::bail!("Filter 'truncate' takes a length, e.g. 'truncate 60'"),
+            ("default", _) => anyhow::bail!("Filter 'default' takes a value, e.g. 'default \"Anonymous\"'"),
+            _ => anyhow::bail!("Unknown filter '{}', expected upper, lower, truncate, slugify, default or raw", name),
+        };
+    }
+    Ok(value)
+}
+
+// Shorten text to at most the given number of characters, ending it with an ellipsis if it
+// was shortened.
+fn truncate(text: &str, length: usize) -> String {
+    if text.chars().count() <= length {
+        return text.to_string();
+    }
+
+    let mut truncated: String = text.chars().take(length.saturating_sub(1)).collect();
+    truncated.truncate(truncated.trim_end().len());
+    if length > 0 {
+        truncated.push('\u{2026}');
+    }
+    truncated
+}
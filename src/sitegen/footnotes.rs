@@ -0,0 +1,108 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::sitegen::regexes::*;
+
+// Replace footnote markers ([^label]) with superscript links, and move the footnote definitions
+// ([^label]: text, on a line of their own) to a footnotes section with backlinks to the markers.
+// The section replaces the { footnotes } placeholder, or is inserted before </body> if there is
+// no placeholder. Footnotes are numbered in the order they are first referenced.
+pub fn process_footnotes(html: &str, page_path: &str) -> String {
+    // Collect and remove the footnote definitions
+    let mut definitions = BTreeMap::new();
+    let html = RE_FOOTNOTE_DEF
+        .replace_all(html, |caps: &regex::Captures| {
+            definitions.insert(caps["label"].to_string(), caps["text"].trim().to_string());
+            String::new()
+        })
+        .to_string();
+
+    // Leave pages without footnotes alone, as text like [^a-z] in inline scripts and styles
+    // looks like a footnote marker
+    if definitions.is_empty() {
+        return RE_FOOTNOTES.replace_all(&html, "").to_string();
+    }
+
+    // Number the footnotes and replace the markers, counting the references to each footnote
+    // so every marker gets a unique id to link back to
+    let mut order: Vec<String> = Vec::new();
+    let mut references: HashMap<String, usize> = HashMap::new();
+    let html = RE_FOOTNOTE_REF
+        .replace_all(&html, |caps: &regex::Captures| {
+            let label = &caps["label"];
+            if !definitions.contains_key(label) {
+                eprintln!("page '{}': footnote '[^{}]' is not defined", page_path, label);
+                return caps[0].to_string();
+            }
+
+            if !order.iter().any(|existing| existing == label) {
+                order.push(label.to_string());
+            }
+            let number = order.iter().position(|existing| existing == label).unwrap() + 1;
+            let count = references.entry(label.to_string()).or_default();
+            *count += 1;
+
+            format!(
+                "<sup class=\"footnote-ref\" id=\"{}\"><a href=\"#fn-{}\">{}</a></sup>",
+                reference_id(number, *count), number, number
+            )
+        })
+        .to_string();
+
+    for label in definitions.keys().filter(|label| !order.contains(label)) {
+        eprintln!("page '{}': footnote '[^{}]' is never referenced", page_path, label);
+    }
+
+    // Build the footnotes section, with a backlink to each reference
+    let mut section = String::new();
+    if !order.is_empty() {
+        section.push_str("<section class=\"footnotes\">\n<ol>\n");
+        for (i, label) in order.iter().enumerate() {
+            let number = i + 1;
+            let backlinks: Vec<String> = (1..=references[label])
+                .map(|count| format!("<a href=\"#{}\" class=\"footnote-backref\">&#8617;</a>", reference_id(number, count)))
+                .collect();
+            section.push_str(&format!("<li id=\"fn-{}\">{} {}</li>\n", number, definitions[label], backlinks.join(" ")));
+        }
+        section.push_str("</ol>\n</section>\n");
+    }
+
+    if RE_FOOTNOTES.is_match(&html) {
+        RE_FOOTNOTES.replace_all(&html, regex::NoExpand(&section)).to_string()
+    } else if let Some(body_end) = RE_BODY_END.find_iter(&html).last() {
+        format!("{}{}{}", &html[..body_end.start()], section, &html[body_end.start()..])
+    } else {
+        html + &section
+    }
+}
+
+// Id of a footnote marker: "fnref-<number>" for the first reference to a footnote, and
+// "fnref-<number>-<count>" for any further references.
+fn reference_id(number: usize, count: usize) -> String {
+    if count == 1 {
+        format!("fnref-{}", number)
+    } else {
+        format!("fnref-{}-{}", number, count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Test that footnotes are numbered by first reference, with a backlink to every reference.
+    #[test]
+    fn test_footnotes_numbered_with_backlinks() {
+        let html = "<p>One[^b] two[^a] three[^b]</p>\n[^a]: First\n[^b]: Second\n{ footnotes }";
+
+        let expected = "<p>One<sup class=\"footnote-ref\" id=\"fnref-1\"><a href=\"#fn-1\">1</a></sup> \
+            two<sup class=\"footnote-ref\" id=\"fnref-2\"><a href=\"#fn-2\">2</a></sup> \
+            three<sup class=\"footnote-ref\" id=\"fnref-1-2\"><a href=\"#fn-1\">1</a></sup></p>\n\
+            <section class=\"footnotes\">\n<ol>\n\
+            <li id=\"fn-1\">Second <a href=\"#fnref-1\" class=\"footnote-backref\">&#8617;</a> \
+            <a href=\"#fnref-1-2\" class=\"footnote-backref\">&#8617;</a></li>\n\
+            <li id=\"fn-2\">First <a href=\"#fnref-2\" class=\"footnote-backref\">&#8617;</a></li>\n\
+            </ol>\n</section>\n";
+
+        assert_eq!(process_footnotes(html, "/test.html"), expected);
+    }
+}
@@ -0,0 +1,106 @@
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+// Default maximum width and height of generated thumbnails, in pixels.
+pub const DEFAULT_THUMBNAIL_SIZE: u32 = 320;
+
+// Name of the folder, next to the images, that generated thumbnails are stored in.
+const THUMBNAIL_FOLDER: &str = "thumbs";
+
+// File extensions of the image formats that galleries support.
+pub const IMAGE_EXTENSIONS: [&str; 5] = ["gif", "jpeg", "jpg", "png", "webp"];
+
+// An image in a { gallery } block. Paths are relative to the web root, like links in templates.
+#[derive(Clone, Debug)]
+pub struct GalleryImage {
+    pub src: String,
+    pub thumb: String,
+    pub alt: String,
+}
+
+// Find the images matching a glob pattern relative to the web root, sorted by path.
+pub fn find_images(root: &Path, pattern: &str) -> Result<Vec<PathBuf>> {
+    let full_pattern = format!(
+        "{}/{}",
+        glob::Pattern::escape(&root.to_string_lossy()),
+        pattern.trim_start_matches('/')
+    );
+    let paths = glob::glob(&full_pattern)
+        .with_context(|| format!("Invalid gallery pattern '{}'", pattern))?;
+
+    let mut images: Vec<PathBuf> = paths
+        .filter_map(|path| path.ok())
+        .filter(|path| path.is_file() && is_image(path))
+        .collect();
+    images.sort();
+    Ok(images)
+}
+
+// Find the images matching a glob pattern relative to the web root, generating thumbnails for
+// images larger than the thumbnail size. Existing thumbnails are only regenerated when the
+// image has changed since.
+pub fn gallery_images(root: &Path, pattern: &str, thumbnail_size: u32) -> Result<Vec<GalleryImage>> {
+    find_images(root, pattern)?
+        .into_iter()
+        .map(|path| {
+            let thumb = make_thumbnail(&path, thumbnail_size)?;
+            Ok(GalleryImage {
+                src: site_path(root, &path),
+                thumb: site_path(root, &thumb),
+                alt: alt_text(&path),
+            })
+        })
+        .collect()
+}
+
+// Create a thumbnail for an image, and return its path. Images that already fit within the
+// thumbnail size are their own thumbnail.
+fn make_thumbnail(path: &Path, size: u32) -> Result<PathBuf> {
+    let (width, height) = image::image_dimensions(path)
+        .with_context(|| format!("Unable to read image '{}'", path.display()))?;
+    if width <= size && height <= size {
+        return Ok(path.to_path_buf());
+    }
+
+    let folder = path.parent().unwrap_or(Path::new("")).join(THUMBNAIL_FOLDER);
+    let thumb = folder.join(path.file_name().unwrap_or_default());
+
+    // The thumbnail is up to date if it is newer than the image and has the configured size,
+    // i.e. the thumbnail size hasn't been changed since it was generated
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+    let up_to_date = modified(&thumb) >= modified(path)
+        && image::image_dimensions(&thumb).is_ok_and(|(width, height)| width.max(height) == size);
+    if up_to_date {
+        return Ok(thumb);
+    }
+
+    std::fs::create_dir_all(&folder)
+        .with_context(|| format!("Unable to create thumbnail folder '{}'", folder.display()))?;
+    image::open(path)
+        .with_context(|| format!("Unable to read image '{}'", path.display()))?
+        .thumbnail(size, size)
+        .save(&thumb)
+        .with_context(|| format!("Unable to write thumbnail '{}'", thumb.display()))?;
+    Ok(thumb)
+}
+
+// Check whether a file is an image, based on its file extension.
+fn is_image(path: &Path) -> bool {
+    path.extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .is_some_and(|ext| IMAGE_EXTENSIONS.contains(&ext.as_str()))
+}
+
+// Path of a file relative to the web root, with a leading slash, e.g. "/photos/italy/rome.jpg".
+fn site_path(root: &Path, path: &Path) -> String {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    format!("/{}", relative.to_string_lossy().replace('\\', "/"))
+}
+
+// Alternative text for an image, derived from its file name, e.g. "ponte vecchio" for
+// "ponte-vecchio.jpg".
+fn alt_text(path: &Path) -> String {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().replace(['-', '_'], " "))
+        .unwrap_or_default()
+}
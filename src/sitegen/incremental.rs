@@ -0,0 +1,264 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+use walkdir::WalkDir;
+
+use crate::sitegen::bundles::{self, BUNDLE_BLOCKS};
+use crate::sitegen::encoding;
+use crate::sitegen::page;
+use crate::sitegen::regexes::*;
+use crate::sitegen::template;
+
+// Name of the file in the web root that stores the state of the last incremental build.
+pub const STATE_FILENAME: &str = ".sitewinder-state";
+
+// State of a single generated page at the time of a build.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PageState {
+    // Hash of the page's metadata and template contents. For tag pages, the template contents
+    // include the list of tagged pages, so the hash changes whenever a tagged page is added,
+    // removed, renamed or reordered.
+    pub hash: String,
+    // Paths of the previous and next pages in the page's group, if any.
+    pub prev: Option<String>,
+    pub next: Option<String>,
+    // Paths of the first and last pages in the page's group, if any.
+    pub first: Option<String>,
+    pub last: Option<String>,
+    // Position of the page in its group, for { page.index }.
+    pub index: usize,
+    // Paths of the pages that the page embeds excerpts of or links to with { excerpt } and
+    // { link } blocks, or lists with { pages } blocks, in its template, layout or include files.
+    pub references: Vec<String>,
+}
+
+// State of a build, used by the next incremental build to determine which pages are
+// affected by the changes made in the meantime.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct BuildState {
+    // Hash of everything that may affect every page: the configuration, include files,
+    // the tag template, the tags in use and their page counts (which determine tag clouds),
+    // and the number of pages in the site and in each group.
+    pub site_hash: String,
+    // Page states, by page path.
+    pub pages: BTreeMap<String, PageState>,
+}
+
+impl BuildState {
+    // Read the state of the last build from the web root. Returns None if there is no
+    // previous state, or if it can't be read, in which case all pages must be generated.
+    pub fn load(root: &Path) -> Option<BuildState> {
+        let contents = std::fs::read_to_string(root.join(STATE_FILENAME)).ok()?;
+        toml::from_str(&contents).ok()
+    }
+
+    // Write the state of this build to the web root.
+    pub fn save(&self, root: &Path) -> Result<()> {
+        let path = root.join(STATE_FILENAME);
+        let contents = toml::to_string(self).context("Unable to serialise build state")?;
+        std::fs::write(&path, contents)
+            .with_context(|| format!("Unable to write build state file '{}'", path.display()))
+    }
+
+    // Determine the paths of the pages that need to be regenerated, compared to the state of
+    // the previous build. Returns None if all pages need to be regenerated.
+    //
+    // A page is regenerated if it is new or has changed, if its output file is missing, if its
+    // prev/next neighbours, the first/last pages of its group or its position in the group have
+    // changed, or if one of those related pages has changed (as the page displays their titles),
+    // or if a page that it takes an excerpt from or links to with { link } has changed.
+    // Tag pages are regenerated when their list of pages changes.
+    pub fn affected_pages(&self, previous: Option<&BuildState>, root: &Path) -> Option<HashSet<String>> {
+        let previous = previous.filter(|previous| previous.site_hash == self.site_hash)?;
+
+        let hash_changed = |path: &String| {
+            previous.pages.get(path).map(|state| &state.hash) != self.pages.get(path).map(|state| &state.hash)
+        };
+
+        let affected = self.pages.iter()
+            .filter(|(path, state)| {
+                let Some(previous_state) = previous.pages.get(*path) else {
+                    return true;
+                };
+                previous_state.hash != state.hash
+                    || previous_state.prev != state.prev
+                    || previous_state.next != state.next
+                    || previous_state.index != state.index
+                    || previous_state.first != state.first
+                    || previous_state.last != state.last
+                    || [&state.prev, &state.next, &state.first, &state.last].into_iter().flatten().any(hash_changed)
+                    || state.references.iter().any(hash_changed)
+                    || !root.join(path.trim_start_matches('/')).exists()
+            })
+            .map(|(path, _)| path.clone())
+            .collect();
+
+        Some(affected)
+    }
+}
+
+// Hash the contents of all include files, i.e. all .sginc files and any other files referenced
+// by { include }, { include_raw }, { include_all } and { group } blocks in templates and include
+// files, and by layout metadata, and of the files bundled by { bundle_css } and { bundle_js } blocks.
+// The values of the environment variables used by { env } blocks are included as well.
+pub fn include_files_hash(root: &Path) -> String {
+    let mut files = BTreeMap::new();
+    let mut variables = BTreeMap::new();
+
+    for entry in WalkDir::new(root).into_iter().filter_map(|e| e.ok()) {
+        let filename = entry.file_name().to_string_lossy();
+        if !entry.file_type().is_file() || ![".sgpage", ".sgtag", ".sggroup", ".sgindex", ".sginc"].iter().any(|ext| filename.ends_with(ext)) {
+            continue;
+        }
+
+        let bytes = std::fs::read(entry.path()).unwrap_or_default();
+        if filename.ends_with(".sginc") {
+            files.insert(entry.path().to_path_buf(), super::content_hash(&bytes));
+        }
+        let contents = encoding::decode(&bytes).unwrap_or_default();
+
+        let nodes = template::parse(&contents).unwrap_or_default();
+        let mut includes: Vec<String> = RE_LAYOUT_KEY.captures_iter(&contents)
+            .filter_map(|caps| caps.name("path").map(|path| path.as_str().to_string()))
+            .collect();
+        for block in template::find_blocks(&nodes, "include").into_iter().chain(template::find_blocks(&nodes, "include_raw")) {
+            includes.extend(block.values().first().map(|path| path.to_string()));
+        }
+        for block in template::find_blocks(&nodes, "include_all") {
+            if let Some(pattern) = block.values().first() {
+                includes.extend(page::include_paths(root, pattern).unwrap_or_default());
+            }
+        }
+        for (name, _) in BUNDLE_BLOCKS {
+            for block in template::find_blocks(&nodes, name) {
+                includes.extend(bundles::sources(root, block));
+            }
+        }
+        for block in template::find_blocks(&nodes, "group") {
+            if let Some((_, path, else_path)) = page::group_nav_args(&block.values()) {
+                includes.extend([Some(path), else_path].into_iter().flatten().map(str::to_string));
+            }
+        }
+        for block in template::find_blocks(&nodes, "env") {
+            if let Some(name) = block.values().first() {
+                variables.insert(name.to_string(), std::env::var(name).ok());
+            }
+        }
+        for include in includes {
+            let path = root.join(include.trim_start_matches('/'));
+            let hash = std::fs::read(&path).map_or(String::new(), |contents| super::content_hash(&contents));
+            files.insert(path, hash);
+        }
+    }
+
+    super::content_hash(format!("{:?}{:?}", files, variables).as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sitegen::testing::TempSite;
+    use crate::sitegen::config::Config;
+    use crate::sitegen::SiteGen;
+
+    // Test that only the changed pages are regenerated, with the tag pages that list a page whose
+    // tags changed, and that changed include files or a missing previous state regenerate every
+    // page.
+    #[test]
+    fn test_affected_pages() {
+        let root = TempSite::new("incremental");
+        let state = || {
+            let mut sitegen = SiteGen::new(&root, Config { quiet: true, ..Config::default() }).unwrap();
+            sitegen.read_templates().unwrap();
+            sitegen.process_tags().unwrap();
+            sitegen.sort_groups();
+            let state = sitegen.build_state();
+            // The pages of the previous build have been generated
+            for path in state.pages.keys() {
+                root.write(path.trim_start_matches('/'), "");
+            }
+            state
+        };
+        let affected = |previous: &BuildState, state: &BuildState| {
+            state.affected_pages(Some(previous), &root).map(|affected| {
+                let mut affected: Vec<_> = affected.into_iter().collect();
+                affected.sort();
+                affected
+            })
+        };
+
+        root.write("header.sginc", "<header>");
+        root.write("tags/tag.sgtag", "{ pages '{ page.title }' }");
+        root.write("a.sgpage", "--\ntitle: A\ntags: x\n--\n{ include \"/header.sginc\" }");
+        root.write("b.sgpage", "--\ntitle: B\ntags: y\n--\n<p>B</p>");
+        root.write("c.sgpage", "--\ntitle: C\ntags: x\n--\n<p>C</p>");
+        let initial = state();
+
+        root.write("c.sgpage", "--\ntitle: C\ntags: x\n--\n<p>Changed</p>");
+        let changed_page = state();
+
+        // A and B swap tags, so both tags keep their page count
+        root.write("a.sgpage", "--\ntitle: A\ntags: y\n--\n{ include \"/header.sginc\" }");
+        root.write("b.sgpage", "--\ntitle: B\ntags: x\n--\n<p>B</p>");
+        let changed_tags = state();
+
+        root.write("header.sginc", "<header class=\"new\">");
+        let changed_include = state();
+
+        let (full, unchanged, changed_page, changed_tags, changed_include) = (
+            initial.affected_pages(None, &root),
+            affected(&initial, &initial),
+            affected(&initial, &changed_page),
+            affected(&changed_page, &changed_tags),
+            affected(&changed_tags, &changed_include),
+        );
+
+        assert_eq!(full, None);
+        assert_eq!(unchanged, Some(Vec::new()));
+        assert_eq!(changed_page, Some(vec![String::from("/c.html")]));
+        assert_eq!(changed_tags, Some(["/a.html", "/b.html", "/tags/x.html", "/tags/y.html"].map(String::from).to_vec()));
+        assert_eq!(changed_include, None);
+    }
+
+    // Test that pages whose layout or include files list or link to other pages are regenerated
+    // when one of those pages changes.
+    #[test]
+    fn test_affected_pages_through_includes() {
+        let root = TempSite::new("incremental-includes");
+        let state = || {
+            let mut sitegen = SiteGen::new(&root, Config { quiet: true, ..Config::default() }).unwrap();
+            sitegen.read_templates().unwrap();
+            sitegen.sort_groups();
+            let state = sitegen.build_state();
+            for path in state.pages.keys() {
+                root.write(path.trim_start_matches('/'), "");
+            }
+            state
+        };
+
+        root.write("layouts/recent.sginc", "{ content }<aside>{ pages limit=2 '{ page.title }' }</aside>");
+        root.write("footer.sginc", "<footer><a href=\"{ link \"/b.sgpage\" }\">B</a></footer>");
+        root.write("a.sgpage", "--\ntitle: A\nlayout: /layouts/recent.sginc\n--\n<p>A</p>");
+        root.write("b.sgpage", "--\ntitle: B\n--\n<p>B</p>");
+        root.write("c.sgpage", "--\ntitle: C\n--\n<p>C</p>");
+        root.write("d.sgpage", "--\ntitle: D\n--\n{ include \"footer.sginc\" }");
+        let initial = state();
+
+        root.write("c.sgpage", "--\ntitle: C, retitled\n--\n<p>C</p>");
+        let retitled_c = state();
+
+        root.write("b.sgpage", "--\ntitle: B, retitled\n--\n<p>B</p>");
+        let retitled_b = state();
+
+        let affected = |previous: &BuildState, state: &BuildState| {
+            let mut affected: Vec<_> = state.affected_pages(Some(previous), &root).unwrap_or_default().into_iter().collect();
+            affected.sort();
+            affected
+        };
+        let results = (affected(&initial, &retitled_c), affected(&retitled_c, &retitled_b));
+
+        assert_eq!(results.0, ["/a.html", "/c.html"]);
+        assert_eq!(results.1, ["/a.html", "/b.html", "/d.html"]);
+    }
+}
@@ -0,0 +1,99 @@
+use std::collections::HashSet;
+use std::path::Path;
+use walkdir::WalkDir;
+
+use crate::sitegen::checksums::SOURCE_EXTENSIONS;
+use crate::sitegen::config::Config;
+use crate::sitegen::regexes::*;
+
+// Paths of the pages and files that local links can point to, relative to the web root, e.g.
+// "/blog/post_2.html": the generated pages, and the files in the web root other than templates
+// and include files.
+pub fn targets(root: &Path, pages: impl IntoIterator<Item = String>) -> HashSet<String> {
+    let mut targets: HashSet<String> = pages.into_iter().collect();
+    for entry in WalkDir::new(root).into_iter().filter_map(|e| e.ok()).filter(|entry| entry.file_type().is_file()) {
+        let extension = entry.path().extension().map(|extension| extension.to_string_lossy());
+        if extension.is_some_and(|extension| SOURCE_EXTENSIONS.contains(&extension.as_ref())) {
+            continue;
+        }
+        let path = entry.path().strip_prefix(root).unwrap_or(entry.path());
+        let components: Vec<_> = path.components().map(|component| component.as_os_str().to_string_lossy()).collect();
+        targets.insert(format!("/{}", components.join("/")));
+    }
+    targets
+}
+
+// Check the root-relative links and references in a page, e.g. "/blog/post_2.html", before they
+// are made relative to the page. Gives a warning for each link to a page or file that doesn't
+// exist, suggesting the closest existing path, as the link is most likely a typo.
+pub fn check(html: &str, page_path: &str, targets: &HashSet<String>, config: &Config) -> Vec<String> {
+    let mut warnings = Vec::new();
+    for caps in RE_LINK_HREF.captures_iter(html).chain(RE_LINK_SRC.captures_iter(html)) {
+        let url = &caps[2];
+        if !url.starts_with('/') || url.starts_with("//") || url.contains('{') {
+            continue;
+        }
+        let Some(path) = super::resolve_link(page_path, url, config) else {
+            continue;
+        };
+        if targets.contains(&path) {
+            continue;
+        }
+
+        warnings.push(match closest(&path, targets.iter().map(String::as_str)) {
+            Some(suggestion) => format!("link to '{}', which doesn't exist (did you mean '{}'?)", url, suggestion),
+            None => format!("link to '{}', which doesn't exist", url),
+        });
+    }
+    warnings
+}
+
+// The candidate closest to a text that isn't one of them, e.g. the existing path closest to a path
+// that doesn't exist, if it is close enough to be what was meant, i.e. differs by at most a
+// quarter of its characters (and at least two).
+pub fn closest<'t>(text: &str, candidates: impl IntoIterator<Item = &'t str>) -> Option<&'t str> {
+    let max_distance = 2.max(text.chars().count() / 4);
+    candidates.into_iter()
+        .map(|target| (edit_distance(text, target), target))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min()
+        .map(|(_, target)| target)
+}
+
+// The number of characters that have to be inserted, removed or replaced to turn one text into
+// the other (the Levenshtein distance).
+fn edit_distance(lhs: &str, rhs: &str) -> usize {
+    let rhs: Vec<char> = rhs.chars().collect();
+    let mut distances: Vec<usize> = (0..=rhs.len()).collect();
+    for (i, lhs_char) in lhs.chars().enumerate() {
+        let mut previous = distances[0];
+        distances[0] = i + 1;
+        for (j, rhs_char) in rhs.iter().enumerate() {
+            let replaced = previous + usize::from(lhs_char != *rhs_char);
+            previous = distances[j + 1];
+            distances[j + 1] = replaced.min(distances[j] + 1).min(previous + 1);
+        }
+    }
+    distances[rhs.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Test that links to unknown pages and files are found, with the closest match as suggestion.
+    #[test]
+    fn test_check_links() {
+        let targets: HashSet<String> = ["/index.html", "/blog/post_1.html", "/blog/post_2.html", "/blog/index.html", "/css/site.css"]
+            .iter().map(|path| path.to_string()).collect();
+        let html = "<link href=\"/css/site.css?v=1\"><a href=\"/blog/post_2.hmtl#top\">2</a><a href=\"/blog/\">Blog</a>\
+            <a href=\"../elsewhere.html\">Relative</a><a href=\"https://example.com/x\">External</a>\
+            <img src=\"/images/missing.png\"><a href=\"/{ var.path }\">Template</a>";
+
+        assert_eq!(check(html, "/blog/post_1.html", &targets, &Config::default()), [
+            "link to '/blog/post_2.hmtl#top', which doesn't exist (did you mean '/blog/post_2.html'?)",
+            "link to '/images/missing.png', which doesn't exist",
+        ]);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+}
@@ -0,0 +1,54 @@
+use anyhow::{Context, Result};
+
+use crate::sitegen::regexes::*;
+
+// Render TeX math to HTML with KaTeX, either inline or as a display (block) equation.
+// HTML entities for reserved characters are decoded first, so math can be written as
+// e.g. "a &lt; b" in templates.
+pub fn render(tex: &str, display: bool) -> Result<String> {
+    let tex = tex.replace("&lt;", "<").replace("&gt;", ">").replace("&amp;", "&");
+    let mut opts = katex::Opts::default();
+    opts.set_display_mode(display);
+    opts.set_throw_on_error(true);
+    katex::render_with_opts(&tex, &opts).with_context(|| format!("Unable to render math '{}'", tex))
+}
+
+// Render math between $...$ (inline) and $$...$$ (display) delimiters in an HTML document.
+// Math in code, pre, script, style and textarea elements is left alone, and \$ is written as a
+// literal dollar sign. Math that can't be rendered is left as it is, with a warning, as prices
+// and other uses of dollar signs may look like math.
+pub fn render_delimited(html: &str, page_path: &str) -> String {
+    RE_MATH_DELIMITED
+        .replace_all(html, |caps: &regex::Captures| {
+            let (tex, display) = match (caps.name("display"), caps.name("inline")) {
+                (Some(tex), _) => (tex.as_str(), true),
+                (None, Some(tex)) => (tex.as_str(), false),
+                // Literal elements and escaped dollar signs
+                (None, None) => return if &caps[0] == "\\$" { String::from("$") } else { caps[0].to_string() },
+            };
+            render(tex, display).unwrap_or_else(|error| {
+                eprintln!("page '{}': {:#}", page_path, error);
+                caps[0].to_string()
+            })
+        })
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Test that inline and display math are rendered, that escaped dollar signs are written as
+    // dollar signs, and that math in code elements and math that can't be rendered are left alone.
+    #[test]
+    fn test_render_delimited() {
+        let html = "<p>Area $\\pi r^2$ for \\$5</p>\n$$x &lt; y$$\n<code>$a$</code>$\\frac$";
+
+        assert_eq!(render_delimited(html, "/index.html"), format!(
+            "<p>Area {} for $5</p>\n{}\n<code>$a$</code>$\\frac$",
+            render("\\pi r^2", false).unwrap(), render("x < y", true).unwrap()
+        ));
+        assert!(!render("\\pi r^2", false).unwrap().contains("katex-display"));
+        assert!(render("x < y", true).unwrap().contains("katex-display"));
+    }
+}
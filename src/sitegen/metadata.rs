@@ -1,24 +1,122 @@
 use chrono::NaiveDate;
+use std::collections::BTreeMap;
+
+use crate::sitegen::config::Author;
 
 #[derive(Clone, Debug, Default)]
 pub struct Metadata {
     pub title: String,
+    // The page's primary group, the first of a comma-separated group value, in which it has
+    // prev/next neighbours.
     pub group: Option<String>,
+    // The other groups of a page that belongs to several, e.g. "announcements" for
+    // "group: blog, announcements", in whose listings the page appears too.
+    pub other_groups: Vec<String>,
+    // Name of the series that the page is a part of, e.g. a multi-part tutorial, independent of
+    // its groups. The parts are ordered by weight and date.
+    pub series: Option<String>,
     pub tags: Option<Vec<String>>,
     pub date: Option<NaiveDate>,
-    pub author: String,
+    // Date on which the page was last revised, if it was changed after its date, for { updated }
+    // and the updated dates of feeds.
+    pub updated: Option<NaiveDate>,
+    // Position of the page in its group and in listings. Pages with lower weights come first,
+    // and pages without a weight come after those with one, ordered by date.
+    pub weight: Option<i64>,
+    // The page's author, from the author's id in the configuration or the author's name, and
+    // author.<field> keys.
+    pub author: Author,
+    // Description of the page for search engines and link previews, for { description } and the
+    // page's <meta name="description"> tag.
+    pub description: String,
+    // Keywords for search engines, for { keywords } and the page's <meta name="keywords"> tag.
+    pub keywords: Vec<String>,
+    // Language of the page, overriding the configuration.
+    pub lang: Option<String>,
+    // URL of the authoritative copy of the page, e.g. the original of a cross-posted article,
+    // instead of the page's own URL.
+    pub canonical: Option<String>,
     pub path: String,
+    // Path of the page's template relative to the web root, e.g. "/posts/2024-05-01-hello.sgpage",
+    // by which other pages refer to it, whatever its slug.
+    pub template: String,
+    // The content above the excerpt separator, if the page has one.
+    pub summary: Option<String>,
+    // The template text of the page's { section "<name>" } blocks, by name, for { excerpt } blocks.
+    pub sections: BTreeMap<String, String>,
+    // Short text describing the page for listings, from the metadata or taken from the page's
+    // summary or first paragraph. This is HTML, i.e. '<' and '>' are escaped.
+    pub excerpt: String,
+    // Whether to apply smart typography to this page, overriding the configuration.
+    pub smart_typography: Option<bool>,
+    // Whether to render math between dollar signs on this page, overriding the configuration.
+    pub math: Option<bool>,
+    // Whether to apply the configured find/replace rules to this page.
+    pub replace: Option<bool>,
+    // Drafts are only generated in preview builds.
+    pub draft: bool,
+    // Tombstones of removed pages are generated, but left out of listings, tag pages and groups.
+    pub removed: bool,
+    // Unlisted pages are generated, and can be linked to, but are left out of listings, tag pages
+    // and groups, e.g. landing pages shared privately.
+    pub unlisted: bool,
+    // Whether the page is listed first on tag pages and in listings, whatever its weight and date.
+    pub pinned: bool,
+    // Path of the layout file that the page's contents are inserted into, relative to the web root.
+    pub layout: Option<String>,
+    // Alternate representations of the page, e.g. a JSON export or a print version, as
+    // "<path>" or "<title>=<path>".
+    pub alternates: Vec<String>,
+    // Paths at which the page used to be, e.g. "/2019/old-post.html", where redirect pages to the
+    // page are generated.
+    pub aliases: Vec<String>,
+    // Words that the spell check accepts on this page, e.g. names and technical terms.
+    pub spelling_ignore: Vec<String>,
+    // Metadata keys that sitewinder doesn't know about, for { meta.<key> } placeholders.
+    pub custom: BTreeMap<String, String>,
+    // Custom variables from the [vars] section of the page's metadata, written in TOML.
+    pub vars: toml::Table,
 }
 
 impl Metadata {
+    // Whether the page belongs to a group, as its primary group or one of its other groups.
+    pub fn in_group(&self, name: &str) -> bool {
+        self.group.as_deref() == Some(name) || self.other_groups.iter().any(|group| group == name)
+    }
+
     pub fn new(path: &str) -> Metadata {
         Metadata {
             title: String::new(),
             group: None,
+            other_groups: Vec::new(),
+            series: None,
             tags: None,
             date: None,
-            author: String::new(),
+            updated: None,
+            weight: None,
+            author: Author::default(),
+            description: String::new(),
+            keywords: Vec::new(),
+            lang: None,
+            canonical: None,
             path: path.to_string(),
+            template: String::new(),
+            summary: None,
+            sections: BTreeMap::new(),
+            excerpt: String::new(),
+            smart_typography: None,
+            math: None,
+            replace: None,
+            draft: false,
+            removed: false,
+            unlisted: false,
+            pinned: false,
+            layout: None,
+            alternates: Vec::new(),
+            aliases: Vec::new(),
+            spelling_ignore: Vec::new(),
+            custom: BTreeMap::new(),
+            vars: toml::Table::new(),
         }
     }
 }
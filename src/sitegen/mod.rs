@@ -1,19 +1,73 @@
+mod budget;
+mod bundles;
+mod checksums;
+mod config;
+mod encoding;
+mod error;
+mod feeds;
+mod filters;
+mod footnotes;
+mod gallery;
+mod incremental;
+mod links;
+mod math;
 mod metadata;
 mod page;
+mod postprocess;
+mod prune;
+mod quality;
 mod regexes;
+mod replacements;
+mod report;
+mod schedule;
+mod spelling;
+mod template;
+#[cfg(test)]
+mod testing;
+mod toc;
+mod typography;
+mod validation;
+mod video;
 
 use anyhow::{Context, Result};
 use chrono::Datelike;
-use std::{collections::HashMap, collections::BTreeMap, fs::File, io::BufReader, io::Read, path::Path};
+use std::{cell::RefCell, collections::HashMap, collections::HashSet, collections::BTreeMap, collections::BTreeSet, path::Path, path::PathBuf, rc::Rc};
 use walkdir::{DirEntry, WalkDir};
-use page::{Page, TemplateSource};
+use page::{escape_html_characters, filtered, format_date, Page, TemplateSource};
 use regexes::*;
 use metadata::Metadata;
-use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use incremental::{BuildState, PageState};
+use report::PageStats;
+use spelling::Dictionary;
+use template::{Block, Node};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use sha2::{Digest, Sha256};
+
+pub use checksums::verify as verify_checksums;
+pub use config::{Config, Prune};
+use config::Tiebreaker;
 
 // Define characters that need to be percent-encoded in URLs
 const FRAGMENT: &AsciiSet = &CONTROLS.add(b' ').add(b'"').add(b'<').add(b'>').add(b'`');
 
+// Template of the pages generated at the paths of removed pages.
+const GONE_PAGE_TEMPLATE: &str = "--\ntitle: Gone\nremoved: true\n--\n<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+    <title>{ title }</title>\n</head>\n<body>\n<h1>{ title }</h1>\n<p>This page has been removed.</p>\n</body>\n</html>\n";
+
+// Template of the redirect pages generated at the aliases of a page, which send visitors and
+// search engines on to the page's URL. "$URL" is replaced with the URL.
+const REDIRECT_PAGE_TEMPLATE: &str = "--\ntitle: Redirecting\n--\n<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+    <title>{ title }</title>\n<meta name=\"robots\" content=\"noindex\">\n<link rel=\"canonical\" href=\"$URL\">\n\
+    <meta http-equiv=\"refresh\" content=\"0; url=$URL\">\n</head>\n<body>\n<p>This page has moved to <a href=\"$URL\">$URL</a>.</p>\n\
+    </body>\n</html>\n";
+
+// Arguments of a { sitemap_html } block that it doesn't give itself: the pages are listed by
+// group, each group as a list under a heading.
+const SITEMAP_HTML_ARGS: [(&str, &str); 3] = [("by", "group"), ("before", "<h2>{ heading }</h2>\n<ul>\n"), ("after", "</ul>\n")];
+
+// Markup of each page in a { sitemap_html } block without markup of its own.
+const SITEMAP_HTML_MARKUP: &str = "<li><a href=\"{ page.link }\">{ page.title }</a></li>\n";
+
 // A TagPage represents a collection of pages that share a common tag.
 #[derive(Clone, Debug, Default)]
 pub struct TagPage {
@@ -23,6 +77,70 @@ pub struct TagPage {
     pub meta: Vec<Metadata>,
 }
 
+// Site-wide information that is made available to every page during generation.
+#[derive(Clone, Debug, Default)]
+pub struct Site {
+    // Metadata of all content pages (.sgpage files), excluding generated tag pages,
+    // sorted by path.
+    pub pages: Vec<Metadata>,
+    // Tags organised in a BTreeMap for sorted access. The key is the tag name,
+    // and the value is a TagPage containing the path to the page and the
+    // (metadata of) all the pages associated with that tag.
+    pub tags: BTreeMap<String, TagPage>,
+    // Seed for { random_page } blocks: the configured random seed, or the build date, so
+    // the selected pages only change from one day to the next.
+    pub random_seed: String,
+    // Date of the build, used to recognise future-dated pages.
+    pub today: chrono::NaiveDate,
+    // Paths of the generated pages and the other files in the web root, which root-relative
+    // links are checked against. Links aren't checked if this is empty.
+    pub link_targets: HashSet<String>,
+    // Paths that redirect to pages, with the paths of the pages they redirect to. Links to these
+    // paths are pointed at the pages instead.
+    pub redirects: BTreeMap<String, String>,
+    // Parsed include files and layouts by path, so files included by every page, such as the
+    // navigation or footer, are only read and parsed once per build.
+    pub includes: RefCell<HashMap<PathBuf, Rc<Vec<Node>>>>,
+    // Rendered include files by path, for include files that give the same markup on every page.
+    // Include files don't take parameters, so the path identifies the markup.
+    pub rendered_includes: RefCell<HashMap<PathBuf, String>>,
+    // Contents of the bundles of { bundle_css } and { bundle_js } blocks by path, which are
+    // written once the pages have been generated.
+    pub bundles: RefCell<BTreeMap<String, String>>,
+}
+
+// A page's position within its group, used to generate group navigation.
+#[derive(Clone, Debug, Default)]
+pub struct GroupContext {
+    // Metadata of the previous and next pages in the group, if any.
+    pub prev: Option<Metadata>,
+    pub next: Option<Metadata>,
+    // Metadata of the first (oldest) and last (newest) pages in the group.
+    pub first: Option<Metadata>,
+    pub last: Option<Metadata>,
+    // Number of pages in the group, or zero for pages that don't belong to a group.
+    pub page_count: usize,
+    // One-based position of the page in the group, or zero for pages that don't belong to a group.
+    pub index: usize,
+}
+
+// Outcome of a build that ran to completion.
+#[derive(Debug, Default)]
+pub struct BuildSummary {
+    // Number of pages that were generated successfully.
+    pub generated: usize,
+    // Pages that could not be generated, with the reason for each failure.
+    pub failures: Vec<(String, anyhow::Error)>,
+    // Statistics of the generated pages, if a build report was requested.
+    pub pages: Vec<PageStats>,
+    // Problems with the metadata of pages, such as missing descriptions, by page path, if
+    // quality checks are configured.
+    pub quality: Vec<(String, String)>,
+    // Pages that exceed the performance budgets, with the budget each exceeds, by page path, if
+    // budgets are configured.
+    pub budget: Vec<(String, String)>,
+}
+
 #[derive(Debug)]
 pub struct SiteGen {
     // Absolute path to the root directory where the site files are located.
@@ -32,10 +150,9 @@ pub struct SiteGen {
     // which can be None for pages that do not belong to any group.
     groups: HashMap<Option<String>, Vec<Page>>,
 
-    // Tags organised in a BTreeMap for sorted access. The key is the tag name,
-    // and the value is a TagPage containing the path to the page and the
-    // (metadata of) all the pages associated with that tag.
-    tags: BTreeMap<String, TagPage>,
+    // Content pages and tags, shared with all pages during generation.
+    site: Site,
+
     // Optional tag template file content. If present, this will be used to
     // generate tag pages that list all pages associated with each tag.
     tag_template: Option<String>,
@@ -44,13 +161,28 @@ pub struct SiteGen {
     // same location as the template file.
     tag_template_path: Option<std::path::PathBuf>,
 
+    // Optional group template (.sggroup file) content and path. If present, an index page
+    // listing the pages of each group is generated at the location of the template file.
+    group_template: Option<String>,
+    group_template_path: Option<std::path::PathBuf>,
+
+    // Paths of the index templates (.sgindex files). Each generates a page listing every
+    // content page, e.g. a site map.
+    index_templates: Vec<std::path::PathBuf>,
+
+    // Pages left out of the build as they're dated in the future, with their dates.
+    scheduled: Vec<(chrono::NaiveDate, String)>,
+
     // Current year, used for metadata and possibly in templates.
     // We generate this only once to ensure consistency across all pages.
     current_year: String,
+
+    // Build configuration, shared with every page.
+    config: Rc<Config>,
 }
 
 impl SiteGen {
-    pub fn new(root: &std::path::Path) -> Result<SiteGen> {
+    pub fn new(root: &std::path::Path, config: Config) -> Result<SiteGen> {
         // Ensure the root is an absolute path
         let root = if root.is_absolute() {
             root.to_path_buf()
@@ -71,29 +203,189 @@ impl SiteGen {
         Ok(SiteGen {
             root,
             groups: HashMap::new(),
-            tags: BTreeMap::new(),
+            site: Site {
+                random_seed: config.random_seed.map_or_else(
+                    || chrono::Local::now().format("%Y-%m-%d").to_string(), |seed| seed.to_string()),
+                today: chrono::Local::now().date_naive(),
+                ..Site::default()
+            },
             tag_template: None,
             tag_template_path: None,
-            current_year: chrono::Local::now().year().to_string()
+            group_template: None,
+            group_template_path: None,
+            index_templates: Vec::new(),
+            scheduled: Vec::new(),
+            current_year: chrono::Local::now().year().to_string(),
+            config: Rc::new(config),
         })
     }
 
-    pub fn run(&mut self) -> Result<()> {
+    pub fn run(&mut self) -> Result<BuildSummary> {
+        let start = std::time::Instant::now();
+
         // Read all templates (.sgpage files) from disk and process the metadata and contents.
         self.read_templates()?;
+        let quality = self.quality_warnings();
+
+        // Stop before anything is written if any page breaks the metadata rules
+        let violations = self.validation_errors();
+        if !violations.is_empty() {
+            for (path, message) in &violations {
+                eprintln!("page '{}': {}", path, message);
+            }
+            anyhow::bail!("{} metadata rule violation{} found", violations.len(), if violations.len() == 1 { "" } else { "s" });
+        }
 
         // If a tag template file was found, process metadata tags and generate tag pages.
         self.process_tags()?;
 
+        // If a group template file was found, generate an index page for each group.
+        self.process_group_pages()?;
+
+        // Create the pages of the index templates, listing every content page.
+        self.process_indexes()?;
+
+        // Create "Gone" pages at the paths of removed pages.
+        self.process_removed()?;
+
+        // Create redirect pages at the aliases of pages.
+        self.process_aliases()?;
+
+        // Make sure that no page overwrites another on case-insensitive file systems.
+        self.check_output_paths()?;
+        self.site.link_targets = links::targets(&self.root, self.groups.values().flatten().map(|page| page.get_metadata().path));
+
+        // Establish the order of pages within each group.
+        self.sort_groups();
+
+        // For incremental builds, determine which pages are affected by the changes made since
+        // the last build. Otherwise, all pages are generated.
+        let state = self.build_state();
+        let affected = if self.config.incremental {
+            let affected = state.affected_pages(BuildState::load(&self.root).as_ref(), &self.root);
+            if !self.config.quiet {
+                if let Some(affected) = &affected {
+                    println!("incremental build: regenerating {} of {} pages", affected.len(), state.pages.len());
+                }
+            }
+            affected
+        } else {
+            None
+        };
+
         // Generate all pages based on the templates and metadata.
         // This will write the HTML files to disk.       
-        self.generate_pages()
+        let mut summary = self.generate_pages(affected.as_ref());
+        bundles::write(&self.root, &self.site.bundles.borrow())?;
+        summary.quality = quality;
+        summary.budget.sort_by(|a, b| a.0.cmp(&b.0));
+        for (path, message) in summary.quality.iter().chain(&summary.budget) {
+            eprintln!("page '{}': {}", path, message);
+        }
+
+        if self.config.incremental {
+            // Forget failed pages, so they are retried by the next incremental build
+            let mut state = state;
+            for (path, _) in &summary.failures {
+                state.pages.remove(path);
+            }
+            state.save(&self.root)?;
+        }
+
+        if !summary.failures.is_empty() {
+            eprintln!("{} of {} pages could not be generated:", summary.failures.len(), summary.failures.len() + summary.generated);
+            for (path, error) in &summary.failures {
+                eprintln!("  {}: {:#}", path, error);
+            }
+        }
+
+        // Write the feeds of the site's dated pages, some of which contain the generated pages
+        feeds::write(&self.root, &self.site.pages, &self.config)?;
+
+        if self.config.report {
+            report::write(&self.root, &summary, start.elapsed(), &self.config)?;
+        }
+
+        // Remove the assets that are no longer referenced before they're listed in the manifest
+        if let Some(mode) = self.config.prune {
+            prune::run(&self.root, mode, &self.config)?;
+        }
+
+        // Write the checksum manifest once all files have been written
+        if self.config.checksums {
+            checksums::write(&self.root, &self.config)?;
+        }
+
+        if self.config.next_change {
+            schedule::print(&schedule::next_changes(&self.root, &self.scheduled, self.site.today, &self.config));
+        }
+
+        // Pages over budget only fail the build once everything else is done, so all of them are
+        // reported
+        if self.config.budget.as_ref().is_some_and(|budget| budget.fail) && !summary.budget.is_empty() {
+            let count = summary.budget.len();
+            anyhow::bail!("{} page budget{} exceeded", count, if count == 1 { "" } else { "s" });
+        }
+        Ok(summary)
+    }
+
+    // Check the site without writing any files: generate every page in memory, and spell check
+    // the generated pages if spell checking is configured. The problems found are printed,
+    // sorted by page, and their number is returned.
+    pub fn lint(&mut self) -> Result<usize> {
+        self.read_templates()?;
+        let mut problems = self.validation_errors();
+        problems.extend(self.quality_warnings());
+        self.process_tags()?;
+        self.process_group_pages()?;
+        self.process_indexes()?;
+        self.process_removed()?;
+        self.process_aliases()?;
+        self.check_output_paths()?;
+        self.site.link_targets = links::targets(&self.root, self.groups.values().flatten().map(|page| page.get_metadata().path));
+        self.sort_groups();
+
+        let dictionary = match &self.config.spell_check {
+            Some(spell_check) => Some(Dictionary::load(&self.root, spell_check)?),
+            None => None,
+        };
+
+        for (group, pages) in &mut self.groups {
+            for i in 0..pages.len() {
+                let context = group_context(group, pages, i);
+                let metadata = pages[i].get_metadata();
+                let start = std::time::Instant::now();
+                if let Err(error) = pages[i].generate(&context, &self.site) {
+                    problems.push((metadata.path, format!("{:#}", error)));
+                    continue;
+                }
+                problems.extend(pages[i].get_warnings().iter().map(|warning| (metadata.path.clone(), warning.clone())));
+                if let Some(budget) = &self.config.budget {
+                    let warnings = budget::check(&self.root, &metadata.path, pages[i].get_contents(), start.elapsed(), &self.config, budget);
+                    problems.extend(warnings.into_iter().map(|warning| (metadata.path.clone(), warning)));
+                }
+
+                if let Some(dictionary) = &dictionary {
+                    for misspelling in spelling::check(pages[i].get_contents(), dictionary, &metadata.spelling_ignore) {
+                        let message = format!("unknown word '{}' in \"{}\"", misspelling.word, misspelling.context);
+                        problems.push((metadata.path.clone(), message));
+                    }
+                }
+            }
+        }
+
+        // Sort by page, keeping the problems on each page in order
+        problems.sort_by(|a, b| a.0.cmp(&b.0));
+        for (path, message) in &problems {
+            eprintln!("page '{}': {}", path, message);
+        }
+        Ok(problems.len())
     }
 
     //
-    // Read all templates (.sgpage files) from disk, and populate the `groups` and `tags` maps.
+    // Read all templates (.sgpage files) from disk, and populate the `groups` map and the `site`.
     //
-    // The `groups` map contains Pages grouped by their metadata group, while the `tags` map
+    // The `groups` map contains Pages grouped by their metadata group, while the site's `tags` map
     // contains lists of pages associated with each tag.
     //
     // For performance reasons, and while we are traversing the directory tree anyway, we also
@@ -107,18 +399,50 @@ impl SiteGen {
             if is_file_with_extension(&entry, ".sgpage") {
                 // This is a page template file.
 
-                // Read the entire file and process its metadata (if any).
+                // Read the entire file and process its metadata (if any). Files that aren't text,
+                // e.g. binary files with a .sgpage extension, are left alone like other assets.
                 let path = entry.path().to_path_buf();
-                let mut page = Page::new(&self.root, &path, &TemplateSource::File(), &self.current_year);
-                page.process_metadata().with_context(|| format!("Unable to read page template file '{}'", &path.display()))?;
+                let bytes = std::fs::read(&path)
+                    .with_context(|| format!("Unable to read page template file '{}'", path.display()))?;
+                let Some(contents) = encoding::decode(&bytes) else {
+                    eprintln!("ignoring page template '{}', as it isn't a text file", path.display());
+                    continue;
+                };
+                let mut page = Page::new(&self.root, &path, &TemplateSource::Memory(contents), &self.current_year, &self.config)?;
+                page.process_metadata()?;
+
+                // Drafts are only generated in preview builds
+                if page.get_metadata().draft && !self.config.drafts {
+                    continue;
+                }
+
+                // Pages dated in the future are scheduled, and left out until their date, except
+                // in preview builds
+                let metadata = page.get_metadata();
+                if let Some(date) = metadata.date.filter(|date| *date > self.site.today && !self.config.include_future) {
+                    // The page is published, or loses its draft badge, on its date
+                    self.scheduled.push((date, metadata.path));
+                    if !self.config.drafts {
+                        continue;
+                    }
+                }
+
+                // Tombstones of removed pages and unlisted pages are generated, but don't appear
+                // anywhere else
+                if page.get_metadata().removed || page.get_metadata().unlisted {
+                    self.groups.entry(None).or_default().push(page);
+                    continue;
+                }
 
                 // If the page has tags in the metadata, add them to the `tags` map.
+                // Hidden tags are skipped, so they don't appear in tag clouds or get a tag page.
                 if let Some(tags) = page.get_metadata().tags {
-                    for tag in tags {
-                        let val = self.tags.entry(tag).or_default();
+                    for tag in tags.into_iter().filter(|tag| !self.config.is_hidden_tag(tag)) {
+                        let val = self.site.tags.entry(tag).or_default();
                         val.meta.push(page.get_metadata());
                     }
                 }
+                self.site.pages.push(page.get_metadata());
 
                 // Store the Page instance in the `groups` map.
                 let group = page.get_metadata().group;
@@ -129,17 +453,26 @@ impl SiteGen {
                 // This is a tag template file.
 
                 // Read the file and store its contents for later use.
-                let file = File::open(entry.path())
-                    .with_context(|| format!("Unable to open tag template file '{}'", entry.path().display()))?;
-                let mut reader = BufReader::new(file);
-                let mut contents = String::new();
-                reader.read_to_string(&mut contents)
+                let contents = encoding::read_text(entry.path())
                     .with_context(|| format!("Unable to read tag template file '{}'", entry.path().display()))?;
 
                 self.tag_template = Some(contents);
                 self.tag_template_path = Some(entry.path().to_path_buf());
+            } else if is_file_with_extension(&entry, ".sggroup") && self.group_template.is_none() {
+                // This is a group template file, which is processed like the tag template.
+                let contents = encoding::read_text(entry.path())
+                    .with_context(|| format!("Unable to read group template file '{}'", entry.path().display()))?;
+
+                self.group_template = Some(contents);
+                self.group_template_path = Some(entry.path().to_path_buf());
+            } else if is_file_with_extension(&entry, ".sgindex") {
+                // This is an index template file, which is processed once all pages are known.
+                self.index_templates.push(entry.path().to_path_buf());
             }
         }
+
+        // Sort the site's pages, as the directory traversal order differs between platforms
+        self.site.pages.sort_by(|a, b| a.path.cmp(&b.path));
         Ok(())
     }
 
@@ -152,96 +485,309 @@ impl SiteGen {
             return Ok(());
         };
 
-        for tag in &mut self.tags {
-            // For each tag, sort associated pages by date in descending order (newest first)
+        let nodes = template::parse(tag_template).context("Invalid tag template")?;
+        let mut tag_pages: HashMap<String, String> = HashMap::new();
+        for tag in &mut self.site.tags {
+            // For each tag, sort associated pages with pinned pages first, by weight, then by date
+            // in descending order (newest first)
             let pages = &mut tag.1.meta;
-            pages.sort_by(|lhs, rhs| rhs.date.partial_cmp(&lhs.date).unwrap());
-
-            // Substitute { title } for tag name
-            let mut contents = RE_TITLE
-                .replace_all(tag_template, |_: &regex::Captures| {
-                    tag.0.clone()
-                })
-                .to_string();
-
-            // Process { pages '<a href="{ page.link }">{ page.title }</a><br>' }
-            // Note: Build the repeated block for all pages in one go
-            let repeated_block = if let Some(caps) = RE_PAGES.captures(&contents) {
-                let link_block = caps.name("link").unwrap().as_str();
-                let mut result = String::new();
-                for page in pages {
-                    let mut block = link_block.to_string();
-                    block = RE_PAGE_LINK.replace_all(&block, &page.path).to_string();
-                    block = RE_PAGE_TITLE.replace_all(&block, &page.title).to_string();
-                    result.push_str(&block);
-                }
-                result
-            } else {
-                String::new()
-            };
-            contents = RE_PAGES.replace_all(&contents, &repeated_block).to_string();
+            pages.sort_by(|lhs, rhs| index_order(lhs, rhs, &self.config));
 
             let mut template_path = self.tag_template_path.clone().unwrap();
             template_path.set_file_name(format!("{}.sgpage", tag.0.to_lowercase()));
 
-            // Create Page instance referring to the newly created template
-            let template_source = TemplateSource::Memory(contents);
-            let mut page = Page::new(&self.root, &template_path, &template_source, &self.current_year);
-            page.process_metadata()?;
+            // Create Page instances referring to the newly created template, one for each page of
+            // a paginated listing
+            let tag_page = index_pages(&self.root, &template_path, &nodes, tag.0, pages, &self.current_year, &self.config)?;
+            tag.1.path = tag_page[0].get_metadata().path;
 
-            tag.1.path = page.get_metadata().path;
+            // Tags that differ only in case, e.g. "Rust" and "rust", would share a tag page
+            if let Some(other) = tag_pages.insert(tag.1.path.to_lowercase(), tag.0.clone()) {
+                anyhow::bail!("Tags '{}' and '{}' differ only in case, so both would generate tag page '{}'", other, tag.0, tag.1.path);
+            }
 
             let val = self.groups.entry(None).or_default();
-            val.push(page);
+            val.extend(tag_page);
         }
 
         Ok(())
     }
 
-    fn generate_pages(&mut self) -> Result<()> {
-        for group in &mut self.groups {
-            let pages = group.1;
+    // Create an index page for each group from the group template, in the same way as tag pages.
+    // Each group page lists the pages in the group, as their primary group or another one. It's
+    // generated next to the template, e.g. /blog/trips.html for the group "Trips" and
+    // /blog/group.sggroup.
+    fn process_group_pages(&mut self) -> Result<()> {
+        let Some(group_template) = &self.group_template else {
+            return Ok(());
+        };
 
-            if group.0.is_none() {
-                // These are pages that do not belong to any group.
-                // They are generated without any grouping or prev/next links.
-                for page in pages {
-                    page.generate(None, None, &self.tags);
-                    page.write()?;
-                }
+        let nodes = template::parse(group_template).context("Invalid group template")?;
+        let names: BTreeSet<&String> = self.site.pages.iter().flat_map(|page| page.group.iter().chain(&page.other_groups)).collect();
+        let mut group_pages: HashMap<String, &String> = HashMap::new();
+        for name in names {
+            let mut pages: Vec<Metadata> = self.site.pages.iter().filter(|page| page.in_group(name)).cloned().collect();
+            pages.sort_by(|lhs, rhs| index_order(lhs, rhs, &self.config));
+
+            let mut template_path = self.group_template_path.clone().unwrap();
+            template_path.set_file_name(format!("{}.sgpage", name.to_lowercase()));
+            let group_page = index_pages(&self.root, &template_path, &nodes, name, &pages, &self.current_year, &self.config)?;
+
+            let path = group_page[0].get_metadata().path;
+            if let Some(other) = group_pages.insert(path.to_lowercase(), name) {
+                anyhow::bail!("Groups '{}' and '{}' differ only in case, so both would generate group page '{}'", other, name, path);
+            }
+            self.groups.entry(None).or_default().extend(group_page);
+        }
+        Ok(())
+    }
+
+    // Create a page for each index template, processing its
+    // { pages [by=group|folder] [before='<markup>'] [after='<markup>'] '<markup>' } blocks, which list
+    // every content page. The page is generated at the location of the template, e.g.
+    // /sitemap.html for /sitemap.sgindex.
+    fn process_indexes(&mut self) -> Result<()> {
+        for template_path in &self.index_templates {
+            let contents = encoding::read_text(template_path)
+                .with_context(|| format!("Unable to read index template file '{}'", template_path.display()))?;
+            let nodes = template::parse(&contents)
+                .with_context(|| format!("Invalid index template '{}'", template_path.display()))?;
+
+            let contents = template::render(&nodes, |block: &Block| match (block.name.as_str(), block.values().as_slice()) {
+                ("pages", [markup]) => index_listing(&self.site.pages, block, markup, &self.config).map(Some),
+                _ => Ok(None),
+            }).with_context(|| format!("Invalid index template '{}'", template_path.display()))?;
+
+            let template_source = TemplateSource::Memory(contents);
+            let mut page = Page::new(&self.root, template_path, &template_source, &self.current_year, &self.config)?;
+            page.process_metadata()?;
+            self.groups.entry(None).or_default().push(page);
+        }
+        Ok(())
+    }
+
+    // Create a minimal "Gone" page at the path of each removed page in the configuration, unless
+    // a page is generated at that path.
+    fn process_removed(&mut self) -> Result<()> {
+        for removed in &self.config.removed {
+            let mut path = format!("/{}", removed.trim_start_matches('/'));
+            if path.ends_with('/') {
+                path.push_str("index.html");
+            }
+            if self.groups.values().flatten().any(|page| page.get_metadata().path == path) {
+                eprintln!("ignoring removed page '{}', as a page is generated at that path", removed);
                 continue;
             }
 
-            // For pages that belong to a group, we need to generate prev/next links.
-            // Sort pages by date in ascending order (oldest first) to establish the correct order
-            // for prev/next links.
-            pages.sort_by(|a, b| a.get_metadata().date.partial_cmp(&b.get_metadata().date).unwrap());
+            let template_path = self.root.join(path.trim_start_matches('/'));
+            let template_source = TemplateSource::Memory(String::from(GONE_PAGE_TEMPLATE));
+            let mut page = Page::new(&self.root, &template_path, &template_source, &self.current_year, &self.config)?;
+            page.process_metadata()?;
+            self.groups.entry(None).or_default().push(page);
+        }
+        Ok(())
+    }
 
-            for i in 0..pages.len() {
+    // Create a redirect page at each alias of a page, i.e. a path the page used to be at, which
+    // sends visitors on to the page. A page already generated at an alias's path is reported by
+    // check_output_paths.
+    fn process_aliases(&mut self) -> Result<()> {
+        let aliased: Vec<Metadata> = self.groups.values().flatten()
+            .map(Page::get_metadata)
+            .filter(|metadata| !metadata.aliases.is_empty())
+            .collect();
+        for metadata in aliased {
+            for alias in &metadata.aliases {
+                let template_path = self.root.join(alias.trim_start_matches('/'));
+                let url = escape_html_characters(&make_relative_link(&metadata.path, &template_path, &self.root, &self.config));
+                let template_source = TemplateSource::Memory(REDIRECT_PAGE_TEMPLATE.replace("$URL", &url));
+                let mut page = Page::new(&self.root, &template_path, &template_source, &self.current_year, &self.config)?;
+                page.process_metadata()?;
+                self.groups.entry(None).or_default().push(page);
+                self.site.redirects.insert(alias.clone(), metadata.path.clone());
+            }
+        }
+        Ok(())
+    }
 
-                // Get metadata for the previous page, if any
-                let prev = if i > 0 {
-                    Some(pages[i - 1].get_metadata())
-                } else {
-                    None
-                };
+    // Check that no two pages are generated at the same path, or at paths that differ only in
+    // case, e.g. /About.html and /about.html, as one would overwrite the other on case-insensitive
+    // file systems such as those of macOS and Windows.
+    fn check_output_paths(&self) -> Result<()> {
+        let mut paths: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for page in self.groups.values().flatten() {
+            let path = page.get_metadata().path;
+            paths.entry(path.to_lowercase()).or_default().push(path);
+        }
 
-                // Get metadata for the next page, if any
-                let next = if i + 1 < pages.len() {
-                    Some(pages[i + 1].get_metadata())
-                } else {
-                    None
+        // Pages at the same path, e.g. as one has the other's name as its slug or output path
+        for paths in paths.values_mut() {
+            paths.sort();
+            if let Some(pair) = paths.windows(2).find(|pair| pair[0] == pair[1]) {
+                anyhow::bail!("More than one page would be generated at '{}', do their slugs or output paths clash?", pair[0]);
+            }
+        }
+
+        let collisions: Vec<String> = paths.into_values()
+            .filter(|paths| paths.len() > 1)
+            .map(|paths| paths.iter().map(|path| format!("'{}'", path)).collect::<Vec<_>>().join(" and "))
+            .collect();
+        if !collisions.is_empty() {
+            anyhow::bail!("Pages would overwrite each other on case-insensitive file systems: {}", collisions.join(", "));
+        }
+        Ok(())
+    }
+
+    // Sort pages that belong to a group by weight, then by date in ascending order (oldest first)
+    // to establish the correct order for prev/next links.
+    fn sort_groups(&mut self) {
+        for (group, pages) in &mut self.groups {
+            if group.is_some() {
+                pages.sort_by(|lhs, rhs| {
+                    let (lhs, rhs) = (lhs.get_metadata(), rhs.get_metadata());
+                    weight_order(&lhs).cmp(&weight_order(&rhs)).then(lhs.date.cmp(&rhs.date)).then_with(|| tiebreak(&lhs, &rhs, &self.config))
+                });
+            }
+        }
+    }
+
+    // Capture the state of the site, which is compared to the state of the previous build
+    // to determine the pages affected by changes in incremental builds.
+    fn build_state(&self) -> BuildState {
+        let mut pages = BTreeMap::new();
+        for (group, group_pages) in &self.groups {
+            for i in 0..group_pages.len() {
+                let context = group_context(group, group_pages, i);
+                let state = PageState {
+                    hash: group_pages[i].source_hash(),
+                    prev: context.prev.map(|prev| prev.path),
+                    next: context.next.map(|next| next.path),
+                    first: context.first.map(|first| first.path),
+                    last: context.last.map(|last| last.path),
+                    index: context.index,
+                    references: group_pages[i].referenced_pages(&self.site),
                 };
+                pages.insert(group_pages[i].get_metadata().path, state);
+            }
+        }
+
+        // Tag usage and group sizes affect the tag clouds and group placeholders on every page
+        let tags: Vec<_> = self.site.tags.iter()
+            .map(|(name, tag)| (name, tag.meta.len(), tag.meta.iter().filter_map(|meta| meta.date).max()))
+            .collect();
+        let groups: BTreeMap<_, _> = self.groups.iter().map(|(group, pages)| (group, pages.len())).collect();
+        // In preview builds, the build date determines which pages have a draft badge
+        let today = if self.config.drafts { self.site.today.to_string() } else { String::new() };
+        // The year of the oldest page starts the range given by { copyright_years }
+        let first_year = self.site.pages.iter().filter_map(|page| page.date).min().map(|date| date.year());
+        // The critical stylesheet is inlined into every page
+        let critical_css = self.config.critical_css.as_ref()
+            .and_then(|path| std::fs::read(self.root.join(path.trim_start_matches('/'))).ok())
+            .map(|css| content_hash(&css));
+        let site = format!("{:?}{}{}{}{:?}{}{:?}{:?}{}{:?}{:?}{:?}",
+            self.config, self.current_year, self.site.random_seed, today, (&self.tag_template, &self.group_template),
+            incremental::include_files_hash(&self.root), tags, groups, self.site.pages.len(), self.site.redirects, critical_css, first_year);
+
+        BuildState {
+            site_hash: content_hash(site.as_bytes()),
+            pages,
+        }
+    }
+
+    // Check the metadata of the content pages, if quality checks are configured.
+    fn quality_warnings(&self) -> Vec<(String, String)> {
+        match &self.config.quality {
+            Some(quality) => quality::check(&self.content_pages(), quality),
+            None => Vec::new(),
+        }
+    }
+
+    // Check the metadata of the content pages against the configured rules.
+    fn validation_errors(&self) -> Vec<(String, String)> {
+        if self.config.validate.is_empty() {
+            return Vec::new();
+        }
+        validation::check(&self.content_pages(), &self.config.validate)
+    }
+
+    // The metadata of the content pages, other than tombstones of removed pages. This must be
+    // called once the templates have been read, before tag, index and "Gone" pages are added.
+    fn content_pages(&self) -> Vec<Metadata> {
+        self.groups.values().flatten()
+            .map(Page::get_metadata)
+            .filter(|metadata| !metadata.removed)
+            .collect()
+    }
+
+    // Generate the pages and write the HTML files to disk. If a set of affected pages is given,
+    // only those pages are generated. A page that fails to generate doesn't stop the build;
+    // the failure is recorded in the returned summary instead.
+    fn generate_pages(&mut self, affected: Option<&HashSet<String>>) -> BuildSummary {
+        let mut summary = BuildSummary::default();
+        for (group, pages) in &mut self.groups {
+            // Pages that do not belong to any group are generated without prev/next links.
+            for i in 0..pages.len() {
+                if affected.is_some_and(|affected| !affected.contains(&pages[i].get_metadata().path)) {
+                    continue;
+                }
 
                 // Generate the page and write the HTML file to disk
-                pages[i].generate(prev, next, &self.tags);
-                pages[i].write()?;
+                let context = group_context(group, pages, i);
+                let start = std::time::Instant::now();
+                match pages[i].generate(&context, &self.site).and_then(|_| pages[i].write()) {
+                    Ok(()) => {
+                        summary.generated += 1;
+                        for warning in pages[i].get_warnings() {
+                            eprintln!("page '{}': {}", pages[i].get_metadata().path, warning);
+                        }
+                        let (path, duration) = (pages[i].get_metadata().path, start.elapsed());
+                        if let Some(budget) = &self.config.budget {
+                            let warnings = budget::check(&self.root, &path, pages[i].get_contents(), duration, &self.config, budget);
+                            summary.budget.extend(warnings.into_iter().map(|warning| (path.clone(), warning)));
+                        }
+                        if self.config.report {
+                            summary.pages.push(PageStats::new(&path, duration, pages[i].get_contents(), &self.config));
+                        }
+                    }
+                    Err(error) => summary.failures.push((pages[i].get_metadata().path, error)),
+                }
             }
         }
-        Ok(())
+        summary
     }
 }
 
+// Helper function to get the group navigation context for the page at the given index in a
+// list of pages sorted by date. Pages that don't belong to a group get an empty context.
+fn group_context(group: &Option<String>, pages: &[Page], i: usize) -> GroupContext {
+    if group.is_none() {
+        return GroupContext::default();
+    }
+
+    GroupContext {
+        // Get metadata for the previous and next pages, if any
+        prev: if i > 0 { Some(pages[i - 1].get_metadata()) } else { None },
+        next: pages.get(i + 1).map(Page::get_metadata),
+        first: pages.first().map(Page::get_metadata),
+        last: pages.last().map(Page::get_metadata),
+        page_count: pages.len(),
+        index: i + 1,
+    }
+}
+
+// Helper function to compute a hex encoded SHA-256 hash of some data
+fn content_hash(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Helper function to pick a pseudo-random index below `len`, derived from a seed and a key.
+// The same seed and key always result in the same index.
+fn seeded_index(seed: &str, key: &str, len: usize) -> usize {
+    let hash = Sha256::digest(format!("{}\n{}", seed, key).as_bytes());
+    let value = u64::from_le_bytes(hash[..8].try_into().unwrap());
+    (value % len as u64) as usize
+}
+
 // Helper function to check if a directory entry is a file with the specified extension
 fn is_file_with_extension(entry: &DirEntry, extension: &str) -> bool {
     if !entry.file_type().is_file() {
@@ -258,8 +804,365 @@ fn is_file_with_extension(entry: &DirEntry, extension: &str) -> bool {
     true
 }
 
+// Helper function to get a page's summary for listings. Template blocks other than { raw } are
+// removed from the summary, as they would be processed for the listing page rather than the
+// summarised page.
+fn listing_summary(page: &Metadata) -> String {
+    let summary = page.summary.as_deref().unwrap_or_default();
+    template::parse(summary)
+        .and_then(|nodes| template::render(&nodes, |block: &Block| Ok((block.name != "raw").then(String::new))))
+        .map_or(String::new(), |summary| summary.trim().to_string())
+}
+
+// Helper function to render an entry of a { pages } listing, resolving { page.link }, { page.title },
+// { page.summary }, { page.excerpt }, { page.date ["<format>"] }, { page.updated ["<format>"] },
+// { page.author }, { page.author.<field> }, { page.pinned ['<markup>'] } and { page.meta.<key> }
+fn listing_entry(markup: &[template::Node], page: &Metadata, config: &Config) -> Result<String> {
+    template::render(markup, |block: &Block| {
+        let (value, escape) = match (block.name.as_str(), block.values().as_slice()) {
+            ("page.link", []) => (page.path.clone(), false),
+            ("page.title", []) => (page.title.clone(), true),
+            ("page.summary", []) => (listing_summary(page), false),
+            ("page.excerpt", []) => (page.excerpt.clone(), false),
+            // The formatted date is already escaped
+            ("page.date" | "page.updated", [] | [_]) => {
+                let format = block.values().first().copied().unwrap_or(config.date_format());
+                let date = if block.name == "page.date" { page.date } else { page.updated };
+                (date.map(|date| format_date(date, format)).transpose()?.unwrap_or_default(), false)
+            }
+            ("page.author", []) => (page.author.name.clone(), true),
+            ("page.description", []) => (page.description.clone(), true),
+            // "pinned" for pinned pages, e.g. for a class, or the given markup
+            ("page.pinned", []) => (if page.pinned { String::from("pinned") } else { String::new() }, false),
+            ("page.pinned", [markup]) => (if page.pinned { markup.to_string() } else { String::new() }, false),
+            (name, []) => match (name.strip_prefix("page.meta."), name.strip_prefix("page.author.").and_then(|field| page.author.field(field))) {
+                (Some(key), _) => (page.custom.get(key).cloned().unwrap_or_default(), true),
+                (None, Some(value)) => (value.to_string(), true),
+                (None, None) => return Ok(None),
+            },
+            _ => return Ok(None),
+        };
+        filtered(&value, &block.filters, escape).map(Some)
+    })
+}
+
+// Helper function to select the pages listed by a { pages } block: `group` and `tag` keep the
+// pages in that group or with that tag, `sort` orders them by weight and date (`desc` for newest
+// first, or `asc`) or by `title`, and `limit` keeps the first pages. Without `sort`, the pages are
+// ordered by weight, and otherwise keep their order, e.g. newest first on tag pages. Pinned pages
+// come first in any order.
+fn select_pages<'p>(pages: impl IntoIterator<Item = &'p Metadata>, block: &Block, config: &Config) -> Result<Vec<&'p Metadata>> {
+    let tag = block.arg("tag").map(str::to_lowercase);
+    let mut pages: Vec<&Metadata> = pages.into_iter()
+        .filter(|page| block.arg("group").is_none_or(|group| page.in_group(group)))
+        .filter(|page| tag.as_ref().is_none_or(|tag| page.tags.iter().flatten().any(|page_tag| page_tag.to_lowercase() == *tag)))
+        .collect();
+
+    // The sorts are stable, so pages with the same title keep their order
+    match block.arg("sort") {
+        None => pages.sort_by_key(|page| listing_order(page)),
+        Some("desc") => pages.sort_by(|lhs, rhs| listing_order(lhs).cmp(&listing_order(rhs)).then(rhs.date.cmp(&lhs.date)).then_with(|| tiebreak(lhs, rhs, config))),
+        Some("asc") => pages.sort_by(|lhs, rhs| listing_order(lhs).cmp(&listing_order(rhs)).then(lhs.date.cmp(&rhs.date)).then_with(|| tiebreak(lhs, rhs, config))),
+        Some("title") => pages.sort_by_key(|page| (!page.pinned, page.title.to_lowercase())),
+        Some(sort) => anyhow::bail!("Invalid value '{}' for 'sort' in {{ pages }} block, expected desc, asc or title", sort),
+    }
+    if let Some(limit) = block.arg("limit") {
+        let limit = limit.parse()
+            .with_context(|| format!("Invalid value '{}' for 'limit' in {{ pages }} block", limit))?;
+        pages.truncate(limit);
+    }
+    Ok(pages)
+}
+
+// Helper function to find the parts of a page's series, i.e. the content pages in the same
+// series, in order of weight and then date, oldest first. Empty if the page isn't in a series.
+fn series_parts<'p>(page: &Metadata, pages: &'p [Metadata], config: &Config) -> Vec<&'p Metadata> {
+    let Some(series) = &page.series else {
+        return Vec::new();
+    };
+    let mut parts: Vec<&Metadata> = pages.iter().filter(|part| part.series.as_ref() == Some(series)).collect();
+    parts.sort_by(|lhs, rhs| weight_order(lhs).cmp(&weight_order(rhs)).then(lhs.date.cmp(&rhs.date)).then_with(|| tiebreak(lhs, rhs, config)));
+    parts
+}
+
+// The order of the pages on tag and group pages: pinned pages first, then by weight, then by date
+// in descending order (newest first).
+fn index_order(lhs: &Metadata, rhs: &Metadata, config: &Config) -> std::cmp::Ordering {
+    listing_order(lhs).cmp(&listing_order(rhs)).then(rhs.date.cmp(&lhs.date)).then_with(|| tiebreak(lhs, rhs, config))
+}
+
+// Create the tag or group page that a template generates at the template path, listing the given
+// pages. If the listing is paginated, there's a page for each part of it, and the first page is
+// followed by e.g. /tags/rust/page/2.html and /tags/rust/page/3.html for /tags/rust.html.
+fn index_pages(root: &Path, template_path: &Path, nodes: &[Node], title: &str, pages: &[Metadata], current_year: &str, config: &Rc<Config>) -> Result<Vec<Page>> {
+    let path = Page::get_page_path(root, &template_path.with_extension("html"))?;
+    let mut index_pages = Vec::new();
+    for (path, contents) in render_index_pages(nodes, title, pages, &path, config)? {
+        let template_path = root.join(path.trim_start_matches('/')).with_extension("sgpage");
+        let mut page = Page::new(root, &template_path, &TemplateSource::Memory(contents), current_year, config)?;
+        page.process_metadata()?;
+        index_pages.push(page);
+    }
+    Ok(index_pages)
+}
+
+// Render the template of a tag or group page at `path`, substituting { title } for the name of
+// the tag or group, and processing
+// { pages [sep="<separator>"] [empty='<markup>'] [group="<group>"] [sort=desc|asc|title] [limit=<n>] [per_page=<n>] '<a href="{ page.link }">{ page.title }</a><br>' }
+// blocks, which list its pages. A listing with per_page is split into pages of that many entries,
+// which are linked with { pagination.prev ['<markup>'] }, { pagination.next ['<markup>'] } and
+// { pagination.pages [sep="<separator>"] [current='<markup>'] '<markup>' } blocks. Returns the
+// path and contents of each page, starting with the page at `path`.
+fn render_index_pages(nodes: &[Node], title: &str, pages: &[Metadata], path: &str, config: &Config) -> Result<Vec<(String, String)>> {
+    let per_page = |block: &Block| block.arg("per_page")
+        .map(|per_page| per_page.parse().ok().filter(|per_page| *per_page > 0)
+            .with_context(|| format!("Invalid per_page value '{}' in {{ pages }} block, expected a positive whole number", per_page)))
+        .transpose();
+
+    // The number of pages is given by the first paginated listing
+    let mut count = 1;
+    if let Some(block) = template::find_blocks(nodes, "pages").into_iter().find(|block| block.arg("per_page").is_some()) {
+        count = select_pages(pages, block, config)?.len().div_ceil(per_page(block)?.unwrap_or(1)).max(1);
+    }
+    let paths: Vec<String> = (1..=count)
+        .map(|number| if number == 1 { path.to_string() } else { format!("{}/page/{}.html", path.trim_end_matches(".html"), number) })
+        .collect();
+
+    let mut index_pages = Vec::new();
+    for number in 1..=count {
+        // Markup for a page of the listing, with { page.link } and { page.number }
+        let link = |markup: &str, number: usize| template::parse(markup).and_then(|nodes| {
+            template::render(&nodes, |block: &Block| Ok(match block.name.as_str() {
+                "page.link" => Some(paths[number - 1].clone()),
+                "page.number" => Some(number.to_string()),
+                _ => None,
+            }))
+        });
+        let contents = template::render(nodes, |block: &Block| match (block.name.as_str(), block.values().as_slice()) {
+            ("title", []) => filtered(title, &block.filters, true).map(Some),
+            ("pages", [markup]) => {
+                let markup = template::parse(markup)?;
+                let selected = select_pages(pages, block, config)?;
+                let entries = match per_page(block)? {
+                    Some(per_page) => selected.into_iter().skip((number - 1) * per_page).take(per_page).collect(),
+                    None => selected,
+                };
+                let entries = entries.into_iter().map(|page| listing_entry(&markup, page, config)).collect::<Result<_>>()?;
+                Ok(Some(join_listing(entries, block)))
+            }
+            ("pagination.prev", []) => Ok(Some(if number > 1 { paths[number - 2].clone() } else { String::new() })),
+            ("pagination.next", []) => Ok(Some(paths.get(number).cloned().unwrap_or_default())),
+            ("pagination.prev", [markup]) if number > 1 => link(markup, number - 1).map(Some),
+            ("pagination.next", [markup]) if number < count => link(markup, number + 1).map(Some),
+            ("pagination.prev" | "pagination.next", [_]) => Ok(Some(String::new())),
+            ("pagination.number", []) => Ok(Some(number.to_string())),
+            ("pagination.count", []) => Ok(Some(count.to_string())),
+            ("pagination.pages", [markup]) => {
+                let entries = (1..=count)
+                    .map(|other| link(block.arg("current").filter(|_| other == number).unwrap_or(markup), other))
+                    .collect::<Result<_>>()?;
+                Ok(Some(join_listing(entries, block)))
+            }
+            _ => Ok(None),
+        })?;
+        index_pages.push((paths[number - 1].clone(), contents));
+    }
+    Ok(index_pages)
+}
+
+// Helper function to order pages by their weight, lowest first, with pages without a weight last.
+fn weight_order(page: &Metadata) -> (bool, Option<i64>) {
+    (page.weight.is_none(), page.weight)
+}
+
+// Helper function to order pages in listings and on tag pages: pinned pages first, and then by
+// weight, like groups.
+fn listing_order(page: &Metadata) -> (bool, (bool, Option<i64>)) {
+    (!page.pinned, weight_order(page))
+}
+
+// Helper function to order pages with the same weight and date, as configured. Pages are
+// finally ordered by path, so their order doesn't depend on the order the templates were read in.
+fn tiebreak(lhs: &Metadata, rhs: &Metadata, config: &Config) -> std::cmp::Ordering {
+    let titles = match config.tiebreaker {
+        Tiebreaker::Filename => std::cmp::Ordering::Equal,
+        Tiebreaker::Title => lhs.title.to_lowercase().cmp(&rhs.title.to_lowercase()),
+    };
+    titles.then_with(|| lhs.path.cmp(&rhs.path))
+}
+
+// Helper function to list the content pages for a { pages } block in an index template or a page.
+// With `by=group` or `by=folder`, the pages are listed in sections, each of which starts with the
+// block's `before` markup and ends with its `after` markup. { heading } in the markup is the
+// group name, or the folder, e.g. "/posts". Pages without a group are listed last, under the
+// heading given by the `ungrouped` argument.
+fn index_listing(pages: &[Metadata], block: &Block, markup: &str, config: &Config) -> Result<String> {
+    let pages = select_pages(pages, block, config)?;
+    let markup = template::parse(markup)?;
+    let entries = |pages: &[&Metadata]| pages.iter().map(|page| listing_entry(&markup, page, config)).collect::<Result<Vec<_>>>();
+    let sections: Vec<(String, Vec<&Metadata>)> = match block.arg("by") {
+        None => return Ok(join_listing(entries(&pages)?, block)),
+        Some("group") => {
+            let mut groups: BTreeMap<(bool, &str), Vec<&Metadata>> = BTreeMap::new();
+            // Pages in several groups are listed in each of them
+            for page in pages {
+                let names = page.group.iter().chain(&page.other_groups).map(String::as_str);
+                for name in names {
+                    groups.entry((false, name)).or_default().push(page);
+                }
+                if page.group.is_none() {
+                    groups.entry((true, "")).or_default().push(page);
+                }
+            }
+            let ungrouped = block.arg("ungrouped").unwrap_or("Other pages");
+            groups.into_iter()
+                .map(|((is_ungrouped, group), pages)| ((if is_ungrouped { ungrouped } else { group }).to_string(), pages))
+                .collect()
+        }
+        Some("folder") => {
+            let mut folders: BTreeMap<&str, Vec<&Metadata>> = BTreeMap::new();
+            for page in pages {
+                let folder = page.path.rsplit_once('/').map_or("", |(folder, _)| folder);
+                folders.entry(folder).or_default().push(page);
+            }
+            folders.into_iter()
+                .map(|(folder, pages)| (if folder.is_empty() { String::from("/") } else { folder.to_string() }, pages))
+                .collect()
+        }
+        Some(by) => anyhow::bail!("Invalid value '{}' for 'by' in {{ pages }} block, expected group or folder", by),
+    };
+    if sections.is_empty() {
+        return Ok(block.arg("empty").unwrap_or_default().to_string());
+    }
+
+    let before = template::parse(block.arg("before").unwrap_or_default())?;
+    let after = template::parse(block.arg("after").unwrap_or_default())?;
+    let mut html = String::new();
+    for (heading, pages) in sections {
+        let resolve_heading = |block: &Block| match block.name.as_str() {
+            "heading" => filtered(&heading, &block.filters, true).map(Some),
+            _ => Ok(None),
+        };
+        html.push_str(&template::render(&before, resolve_heading)?);
+        html.push_str(&entries(&pages)?.join(block.arg("sep").unwrap_or_default()));
+        html.push_str(&template::render(&after, resolve_heading)?);
+    }
+    Ok(html)
+}
+
+// Helper function to list the content pages for a { sitemap_html ['<markup>'] } block, a site
+// map like that of a { pages by=group } block, with markup for the entries and headings unless
+// the block gives its own.
+fn sitemap_listing(pages: &[Metadata], block: &Block, markup: Option<&str>, config: &Config) -> Result<String> {
+    let mut block = block.clone();
+    for (name, value) in SITEMAP_HTML_ARGS {
+        if block.arg(name).is_none() {
+            block.args.push(template::Arg { name: Some(name.to_string()), value: value.to_string() });
+        }
+    }
+    index_listing(pages, &block, markup.unwrap_or(SITEMAP_HTML_MARKUP), config)
+}
+
+// Helper function to join the entries of a listing block such as { tags } or { pages }, using the
+// block's `sep` argument as separator, or to render its `empty` argument if there are no entries
+fn join_listing(entries: Vec<String>, block: &Block) -> String {
+    if entries.is_empty() {
+        return block.arg("empty").unwrap_or_default().to_string();
+    }
+    entries.join(block.arg("sep").unwrap_or_default())
+}
+
+// Helper function to convert text to a form that can be used in ids and file names: lowercase
+// letters and digits, with words separated by hyphens, e.g. "whats-new" for "What's New?"
+fn slugify(text: &str) -> String {
+    RE_ENTITY.replace_all(text, " ")
+        .replace(['\'', '\u{2019}'], "")
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+// Resolve a link in a page to a path relative to the web root, e.g. "/photos/a.jpg"
+// for "../photos/a.jpg" in "/posts/italy.html". External links, fragments, and links that point
+// outside of the web root give None.
+fn resolve_link(page_path: &str, link: &str, config: &Config) -> Option<String> {
+    let external = ["http:", "https:", "//", "#", "mailto:", "tel:", "data:", "javascript:"];
+    if external.iter().any(|prefix| link.starts_with(prefix)) {
+        return None;
+    }
+
+    let link = link.split(['?', '#']).next().unwrap_or_default();
+    if link.is_empty() {
+        return None;
+    }
+    let link = percent_decode_str(link).decode_utf8_lossy();
+
+    let mut components: Vec<&str> = if link.starts_with('/') {
+        Vec::new()
+    } else {
+        // Relative to the folder of the page
+        let mut folder: Vec<&str> = page_path.split('/').filter(|c| !c.is_empty()).collect();
+        folder.pop();
+        folder
+    };
+    for component in config.strip_base_prefix(&link).split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop()?;
+            }
+            component => components.push(component),
+        }
+    }
+
+    let mut path = format!("/{}", components.join("/"));
+    if link.ends_with('/') {
+        path.push_str(if path == "/" { "index.html" } else { "/index.html" });
+    }
+    Some(path)
+}
+
+// Make the relative links in markup taken from another page, e.g. the summary embedded by an
+// { excerpt } block, root-relative, so they point to the same files from the embedding page.
+// Links containing template blocks are left as they are.
+fn rebase_links(html: &str, source_path: &str, config: &Config) -> String {
+    [&*RE_LINK_HREF, &*RE_LINK_SRC].into_iter().fold(html.to_string(), |html, re| {
+        re.replace_all(&html, |caps: &regex::Captures| {
+            let url = &caps[2];
+            let suffix = url.find(['?', '#']).map_or("", |start| &url[start..]);
+            match resolve_link(source_path, url, config) {
+                Some(path) if !url.starts_with('/') && !url.contains('{') => format!("{}{}{}{}", &caps[1], path, suffix, &caps[3]),
+                _ => caps[0].to_string(),
+            }
+        }).to_string()
+    })
+}
+
+// Point the links to redirected paths at the pages they redirect to, so visitors don't go through
+// the redirect pages. The links are written root-relative, and made relative to the page along
+// with the page's other links.
+fn resolve_redirects(html: &str, page_path: &str, redirects: &BTreeMap<String, String>, config: &Config) -> String {
+    RE_LINK_HREF.replace_all(html, |caps: &regex::Captures| {
+        let url = &caps[2];
+        let suffix = url.find(['?', '#']).map_or("", |start| &url[start..]);
+        match resolve_link(page_path, url, config).and_then(|path| redirects.get(&path)) {
+            Some(target) => format!("{}{}{}{}", &caps[1], target, suffix, &caps[3]),
+            None => caps[0].to_string(),
+        }
+    }).to_string()
+}
+
 // Helper function to make links relative to the current document and perform URI escaping as per the specification.
-fn make_relative_link(target_path: &str, current_doc_path: &Path, root_path: &Path) -> String {
+fn make_relative_link(target_path: &str, current_doc_path: &Path, root_path: &Path, config: &Config) -> String {
+    // With a base path, root-relative links are written as absolute paths below the base path
+    // instead. Links that already include the base path are left as they are.
+    let target_path = config.strip_base_prefix(target_path);
+    if let (Some(prefix), true) = (config.base_prefix(), target_path.starts_with('/')) {
+        return format!("{}{}", prefix, url_encode_path(target_path));
+    }
+
     let target = if let Some(stripped) = target_path.strip_prefix('/') {
         root_path.join(stripped)
     } else {
@@ -289,8 +1192,31 @@ fn url_encode_path(path: &str) -> String {
         .join("/")
 }
 
+// Helper function to compute a short content hash for a local asset, used as a cache-busting
+// query string. Returns None for pages, links with an existing query or fragment, and files
+// that do not exist on disk.
+fn asset_version(target_path: &str, current_doc_path: &Path, root_path: &Path) -> Option<String> {
+    if target_path.contains(['?', '#']) {
+        return None;
+    }
+
+    let target = if let Some(stripped) = target_path.strip_prefix('/') {
+        root_path.join(stripped)
+    } else {
+        current_doc_path.parent().unwrap_or(Path::new("")).join(target_path)
+    };
+
+    let extension = target.extension().and_then(|ext| ext.to_str()).unwrap_or_default();
+    if extension.eq_ignore_ascii_case("html") || extension.eq_ignore_ascii_case("htm") {
+        return None;
+    }
+
+    let contents = std::fs::read(&target).ok()?;
+    Some(content_hash(&contents)[..8].to_string())
+}
+
 // Function to rewrite local links in HTML content
-fn rewrite_local_links(html: &str, current_doc_path: &Path, root_path: &Path) -> String {
+fn rewrite_local_links(html: &str, current_doc_path: &Path, root_path: &Path, config: &Config) -> String {
     let mut result = html.to_string();
     
     // Helper closure to check if URL should be rewritten
@@ -300,6 +1226,17 @@ fn rewrite_local_links(html: &str, current_doc_path: &Path, root_path: &Path) ->
         !url.starts_with("mailto:") && !url.starts_with("tel:") && 
         !url.starts_with("data:") && !url.starts_with("javascript:")
     };
+
+    // Helper closure to make a URL relative to the current document, appending a
+    // ?v=<hash> query string to local assets if cache busting is enabled
+    let relative_link = |url: &str| -> String {
+        let relative_url = make_relative_link(url, current_doc_path, root_path, config);
+        let url = config.strip_base_prefix(url);
+        match config.cache_bust.then(|| asset_version(url, current_doc_path, root_path)).flatten() {
+            Some(version) => format!("{}?v={}", relative_url, version),
+            None => relative_url,
+        }
+    };
     
     // Rewrite href attributes
     result = RE_LINK_HREF.replace_all(&result, |caps: &regex::Captures| {
@@ -308,7 +1245,7 @@ fn rewrite_local_links(html: &str, current_doc_path: &Path, root_path: &Path) ->
         let after = &caps[3];
         
         if should_rewrite(url) {
-            let relative_url = make_relative_link(url, current_doc_path, root_path);
+            let relative_url = relative_link(url);
             format!("{}{}{}", before, relative_url, after)
         } else {
             caps[0].to_string()
@@ -322,7 +1259,7 @@ fn rewrite_local_links(html: &str, current_doc_path: &Path, root_path: &Path) ->
         let after = &caps[3];
         
         if should_rewrite(url) {
-            let relative_url = make_relative_link(url, current_doc_path, root_path);
+            let relative_url = relative_link(url);
             format!("{}{}{}", before, relative_url, after)
         } else {
             caps[0].to_string()
@@ -336,7 +1273,7 @@ fn rewrite_local_links(html: &str, current_doc_path: &Path, root_path: &Path) ->
         let after = &caps[3];
         
         if should_rewrite(url) {
-            let relative_url = make_relative_link(url, current_doc_path, root_path);
+            let relative_url = relative_link(url);
             format!("{}{}{}", before, relative_url, after)
         } else {
             caps[0].to_string()
@@ -350,7 +1287,7 @@ fn rewrite_local_links(html: &str, current_doc_path: &Path, root_path: &Path) ->
         let after = &caps[3];
         
         if should_rewrite(url) {
-            let relative_url = make_relative_link(url, current_doc_path, root_path);
+            let relative_url = relative_link(url);
             format!("{}{}{}", before, relative_url, after)
         } else {
             caps[0].to_string()
@@ -364,7 +1301,7 @@ fn rewrite_local_links(html: &str, current_doc_path: &Path, root_path: &Path) ->
         let after = &caps[3];
         
         if should_rewrite(url) {
-            let relative_url = make_relative_link(url, current_doc_path, root_path);
+            let relative_url = relative_link(url);
             format!("{}{}{}", before, relative_url, after)
         } else {
             caps[0].to_string()
@@ -383,7 +1320,7 @@ fn rewrite_local_links(html: &str, current_doc_path: &Path, root_path: &Path) ->
                 let parts: Vec<&str> = entry.split_whitespace().collect();
                 if let Some(url) = parts.first() {
                     if should_rewrite(url) {
-                        let relative_url = make_relative_link(url, current_doc_path, root_path);
+                        let relative_url = relative_link(url);
                         if parts.len() > 1 {
                             format!("{} {}", relative_url, parts[1..].join(" "))
                         } else {
@@ -404,3 +1341,342 @@ fn rewrite_local_links(html: &str, current_doc_path: &Path, root_path: &Path) ->
     
     result
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sitegen::testing::TempSite;
+
+    // Test that pages at the same path, and pages and tag pages whose paths differ only in case,
+    // are an error. The templates of the pages can't differ only in case themselves, so the tests
+    // work on any file system.
+    #[test]
+    fn test_case_collisions() {
+        let lint = |files: &[(&str, &str)], removed: &[&str]| {
+            let root = TempSite::new("case");
+            for (path, contents) in files {
+                root.write(path, contents);
+            }
+            let config = Config { quiet: true, removed: removed.iter().map(|path| path.to_string()).collect(), ..Config::default() };
+            SiteGen::new(&root, config).and_then(|mut sitegen| sitegen.lint())
+        };
+
+        let pages = lint(&[("about.sgpage", "<p>About</p>"), ("contact.sgpage", "")], &["/About.html"]);
+        let slugs = lint(&[("hello.sgpage", ""), ("post.sgpage", "--\nslug: hello\n--\n")], &[]);
+        let tags = lint(&[
+            ("tags/tag.sgtag", "{ pages '{ page.title }' }"),
+            ("rust.sgpage", "--\ntitle: Rust\ntags: Rust\n--\n"),
+            ("ferris.sgpage", "--\ntitle: Ferris\ntags: rust\n--\n"),
+        ], &[]);
+
+        assert_eq!(pages.unwrap_err().to_string(),
+            "Pages would overwrite each other on case-insensitive file systems: '/About.html' and '/about.html'");
+        assert_eq!(tags.unwrap_err().to_string(),
+            "Tags 'Rust' and 'rust' differ only in case, so both would generate tag page '/tags/rust.html'");
+        assert_eq!(slugs.unwrap_err().to_string(), "More than one page would be generated at '/hello.html', do their slugs or output paths clash?");
+    }
+
+    // Test that the group template generates a page for each group, listing the pages in the group
+    // as their primary group or another one, newest first, and that groups that differ only in
+    // case are an error.
+    #[test]
+    fn test_group_pages() {
+        let group_pages = |files: &[(&str, &str)]| {
+            let root = TempSite::new("group-pages");
+            root.write("blog/group.sggroup", "--\ntitle: { title }\n--\n{ pages sep=', ' '{ page.title }' }");
+            for (path, metadata) in files {
+                root.write(path, format!("--\n{}\n--\n", metadata));
+            }
+            let mut sitegen = SiteGen::new(&root, Config { quiet: true, ..Config::default() }).unwrap();
+            sitegen.read_templates().unwrap();
+            sitegen.process_group_pages().map(|_| {
+                sitegen.groups[&None].iter()
+                    .map(|page| (page.get_metadata().path, page.get_metadata().title, page.get_contents().to_string()))
+                    .collect::<Vec<_>>()
+            })
+        };
+
+        let mut pages = group_pages(&[
+            ("a.sgpage", "title: Apples\ngroup: Trips\ndate: 2024-01-01"),
+            ("b.sgpage", "title: Bananas\ngroup: Trips\ndate: 2024-03-01"),
+            ("c.sgpage", "title: Cherries\ngroup: Notes, Trips\ndate: 2024-02-01"),
+            ("d.sgpage", "title: Dates"),
+        ]).unwrap();
+        pages.sort();
+        let clash = group_pages(&[("a.sgpage", "group: Trips"), ("b.sgpage", "group: trips")]);
+
+        assert_eq!(pages, [
+            (String::from("/blog/notes.html"), String::from("Notes"), String::from("Cherries")),
+            (String::from("/blog/trips.html"), String::from("Trips"), String::from("Bananas, Cherries, Apples")),
+            (String::from("/d.html"), String::from("Dates"), String::new()),
+        ]);
+        assert_eq!(clash.unwrap_err().to_string(), "Groups 'Trips' and 'trips' differ only in case, so both would generate group page '/blog/trips.html'");
+    }
+
+    // Test that a tag page listing with per_page is split into pages, which are linked with the
+    // pagination blocks, and that an invalid per_page value is an error.
+    #[test]
+    fn test_pagination() {
+        let tag_pages = |template: &str| {
+            let root = TempSite::new("pagination");
+            root.write("tags/tag.sgtag", template);
+            for day in 1..=5 {
+                root.write(&format!("{}.sgpage", day), format!("--\ntitle: Day {}\ndate: 2024-05-0{}\ntags: News\n--\n", day, day));
+            }
+            let mut sitegen = SiteGen::new(&root, Config { quiet: true, ..Config::default() }).unwrap();
+            sitegen.read_templates().unwrap();
+            sitegen.process_tags().map(|_| {
+                let pages: Vec<_> = sitegen.groups[&None].iter()
+                    .map(|page| (page.get_metadata().path, page.get_contents().to_string()))
+                    .filter(|(path, _)| path.starts_with("/tags/"))
+                    .collect();
+                (sitegen.site.tags["News"].path.clone(), pages)
+            })
+        };
+
+        let (path, pages) = tag_pages("{ pages per_page=2 sep=', ' '{ page.title }' } | { pagination.prev } | \
+            { pagination.next '<a href=\"{ page.link }\">Older</a>' } | \
+            { pagination.pages sep=' ' current='[{ page.number }]' '<a href=\"{ page.link }\">{ page.number }</a>' } | \
+            { pagination.number }/{ pagination.count }").unwrap();
+        let invalid = tag_pages("{ pages per_page=none '{ page.title }' }");
+
+        assert_eq!(path, "/tags/news.html");
+        assert_eq!(pages, [
+            (String::from("/tags/news.html"), String::from("Day 5, Day 4 |  | <a href=\"/tags/news/page/2.html\">Older</a> | \
+                [1] <a href=\"/tags/news/page/2.html\">2</a> <a href=\"/tags/news/page/3.html\">3</a> | 1/3")),
+            (String::from("/tags/news/page/2.html"), String::from("Day 3, Day 2 | /tags/news.html | <a href=\"/tags/news/page/3.html\">Older</a> | \
+                <a href=\"/tags/news.html\">1</a> [2] <a href=\"/tags/news/page/3.html\">3</a> | 2/3")),
+            (String::from("/tags/news/page/3.html"), String::from("Day 1 | /tags/news/page/2.html |  | \
+                <a href=\"/tags/news.html\">1</a> <a href=\"/tags/news/page/2.html\">2</a> [3] | 3/3")),
+        ]);
+        assert_eq!(invalid.unwrap_err().to_string(), "Invalid per_page value 'none' in { pages } block, expected a positive whole number");
+    }
+
+    // Test that pages dated in the future are left out, unless future pages are included or the
+    // build is a preview.
+    #[test]
+    fn test_future_pages() {
+        let root = TempSite::new("future");
+        let today = chrono::Local::now().date_naive();
+        for (path, date) in [("today.sgpage", today), ("tomorrow.sgpage", today + chrono::Days::new(1))] {
+            root.write(path, format!("--\ndate: {}\ntags: news\n--\n", date));
+        }
+
+        let pages = |config: Config| {
+            let mut sitegen = SiteGen::new(&root, Config { quiet: true, ..config }).unwrap();
+            sitegen.read_templates().unwrap();
+            let tagged = sitegen.site.tags["news"].meta.len();
+            (sitegen.site.pages.into_iter().map(|page| page.path).collect::<Vec<_>>(), tagged)
+        };
+        let scheduled = pages(Config::default());
+        let included = pages(Config { include_future: true, ..Config::default() });
+        let preview = pages(Config { drafts: true, ..Config::default() });
+
+        assert_eq!(scheduled, (vec![String::from("/today.html")], 1));
+        assert_eq!(included.1, 2);
+        assert_eq!(preview.1, 2);
+    }
+
+    // Test that unlisted pages are generated, but left out of the site's pages, tags and groups.
+    #[test]
+    fn test_unlisted_pages() {
+        let root = TempSite::new("unlisted");
+        root.write("post.sgpage", "--\ngroup: blog\ntags: news\n--\n");
+        root.write("offer.sgpage", "--\ngroup: blog\ntags: news\nunlisted: true\n--\n");
+
+        let mut sitegen = SiteGen::new(&root, Config { quiet: true, ..Config::default() }).unwrap();
+        let summary = sitegen.run();
+        let generated = root.join("offer.html").exists();
+
+        assert_eq!(summary.unwrap().generated, 2);
+        assert!(generated);
+        assert_eq!(sitegen.site.pages.iter().map(|page| page.path.as_str()).collect::<Vec<_>>(), ["/post.html"]);
+        assert_eq!(sitegen.site.tags["news"].meta.len(), 1);
+        assert_eq!(sitegen.groups[&Some(String::from("blog"))].len(), 1);
+    }
+
+    // Test that weights order groups, tag pages and listings before dates, with pages without a
+    // weight last.
+    #[test]
+    fn test_page_weights() {
+        let root = TempSite::new("weights");
+        for (path, metadata) in [
+            ("install.sgpage", "weight: 10\ndate: 2024-05-01"),
+            ("usage.sgpage", "weight: 20\ndate: 2024-01-01"),
+            ("intro.sgpage", "weight: 10\ndate: 2024-02-01"),
+            ("faq.sgpage", "date: 2023-01-01"),
+            ("changes.sgpage", ""),
+        ] {
+            root.write(path, format!("--\ngroup: manual\ntags: docs\n{}\n--\n", metadata));
+        }
+
+        let mut sitegen = SiteGen::new(&root, Config { quiet: true, ..Config::default() }).unwrap();
+        sitegen.read_templates().unwrap();
+        sitegen.sort_groups();
+
+        let group: Vec<_> = sitegen.groups[&Some(String::from("manual"))].iter().map(|page| page.get_metadata().path).collect();
+        assert_eq!(group, ["/intro.html", "/install.html", "/usage.html", "/changes.html", "/faq.html"]);
+
+        let block = |source: &str| template::parse(source).unwrap().into_iter().find_map(|node| match node {
+            Node::Block(block) => Some(block),
+            Node::Text(_) => None,
+        }).unwrap();
+        let listed = |source: &str| select_pages(&sitegen.site.pages, &block(source), &sitegen.config).unwrap().iter().map(|page| page.path.as_str()).collect::<Vec<_>>();
+        assert_eq!(listed("{ pages '' }"), ["/install.html", "/intro.html", "/usage.html", "/changes.html", "/faq.html"]);
+        assert_eq!(listed("{ pages sort=desc '' }"), ["/install.html", "/intro.html", "/usage.html", "/faq.html", "/changes.html"]);
+    }
+
+    // Test that pinned pages come first in listings, whatever their date, weight or title, but
+    // keep their place in groups, and that listings can mark them.
+    #[test]
+    fn test_pinned_pages() {
+        let root = TempSite::new("pinned");
+        for (path, metadata) in [
+            ("a.sgpage", "title: Apples\ndate: 2024-05-01\nweight: 1"),
+            ("b.sgpage", "title: Bananas\ndate: 2024-06-01"),
+            ("z.sgpage", "title: Zucchini\ndate: 2020-01-01\npinned: true"),
+        ] {
+            root.write(path, format!("--\ngroup: food\n{}\n--\n", metadata));
+        }
+
+        let mut sitegen = SiteGen::new(&root, Config { quiet: true, ..Config::default() }).unwrap();
+        sitegen.read_templates().unwrap();
+        sitegen.sort_groups();
+
+        let group: Vec<_> = sitegen.groups[&Some(String::from("food"))].iter().map(|page| page.get_metadata().path).collect();
+        assert_eq!(group, ["/a.html", "/z.html", "/b.html"]);
+
+        let Some(Node::Block(block)) = template::parse("{ pages sort=title '' }").unwrap().pop() else { unreachable!() };
+        let pages = |source: &str| {
+            let Some(Node::Block(block)) = template::parse(source).unwrap().pop() else { unreachable!() };
+            select_pages(&sitegen.site.pages, &block, &sitegen.config).unwrap().iter().map(|page| page.path.as_str()).collect::<Vec<_>>()
+        };
+        assert_eq!(pages("{ pages '' }"), ["/z.html", "/a.html", "/b.html"]);
+        assert_eq!(pages("{ pages sort=desc '' }"), ["/z.html", "/a.html", "/b.html"]);
+        assert_eq!(pages("{ pages sort=title '' }"), ["/z.html", "/a.html", "/b.html"]);
+
+        let markup = template::parse("<li class=\"{ page.pinned }\">{ page.pinned '&#128204; ' }{ page.title }</li>").unwrap();
+        let entries: Vec<_> = select_pages(&sitegen.site.pages, &block, &sitegen.config).unwrap().iter()
+            .map(|page| listing_entry(&markup, page, &sitegen.config).unwrap())
+            .collect();
+        assert_eq!(entries, ["<li class=\"pinned\">&#128204; Zucchini</li>", "<li class=\"\">Apples</li>", "<li class=\"\">Bananas</li>"]);
+    }
+
+    // Test that pages with the same date are ordered by path, or by title if configured, in
+    // groups, on tag pages and in listings.
+    #[test]
+    fn test_tiebreaker() {
+        let root = TempSite::new("tiebreaker");
+        for (path, title) in [("b.sgpage", "Apples"), ("c.sgpage", "Bananas"), ("a.sgpage", "Cherries")] {
+            root.write(path, format!("--\ntitle: {}\ngroup: fruit\ntags: food\ndate: 2024-05-01\n--\n", title));
+        }
+
+        let order = |tiebreaker: Tiebreaker| {
+            let mut sitegen = SiteGen::new(&root, Config { quiet: true, tiebreaker, ..Config::default() }).unwrap();
+            sitegen.read_templates().unwrap();
+            sitegen.sort_groups();
+            let group: Vec<_> = sitegen.groups[&Some(String::from("fruit"))].iter().map(|page| page.get_metadata().path).collect();
+            let Some(Node::Block(block)) = template::parse("{ pages sort=desc '' }").unwrap().pop() else { unreachable!() };
+            let listed: Vec<_> = select_pages(&sitegen.site.pages, &block, &sitegen.config).unwrap().iter().map(|page| page.path.clone()).collect();
+            (group, listed)
+        };
+        let by_filename = order(Tiebreaker::Filename);
+        let by_title = order(Tiebreaker::Title);
+
+        assert_eq!(by_filename.0, ["/a.html", "/b.html", "/c.html"]);
+        assert_eq!(by_filename.1, by_filename.0);
+        assert_eq!(by_title.0, ["/b.html", "/c.html", "/a.html"]);
+        assert_eq!(by_title.1, by_title.0);
+    }
+
+    // Test that a page in several groups is in its primary group's prev/next chain only, but is
+    // listed in each of its groups.
+    #[test]
+    fn test_multiple_groups() {
+        let root = TempSite::new("groups");
+        for (path, group) in [("post.sgpage", "blog"), ("release.sgpage", "blog, news"), ("outage.sgpage", "news")] {
+            root.write(path, format!("--\ntitle: {}\ngroup: {}\n--\n", path, group));
+        }
+
+        let mut sitegen = SiteGen::new(&root, Config { quiet: true, ..Config::default() }).unwrap();
+        sitegen.read_templates().unwrap();
+
+        let members = |group: &str| {
+            let mut paths: Vec<_> = sitegen.groups[&Some(group.to_string())].iter().map(|page| page.get_metadata().path).collect();
+            paths.sort();
+            paths
+        };
+        assert_eq!(members("blog"), ["/post.html", "/release.html"]);
+        assert_eq!(members("news"), ["/outage.html"]);
+
+        let listing = |source: &str| {
+            let Some(Node::Block(block)) = template::parse(source).unwrap().pop() else { unreachable!() };
+            index_listing(&sitegen.site.pages, &block, block.values()[0], &sitegen.config).unwrap()
+        };
+        assert_eq!(listing("{ pages group=\"news\" sep=\" \" '{ page.title }' }"), "outage.sgpage release.sgpage");
+        assert_eq!(listing("{ pages by=group before='{ heading }:' after=' ' '{ page.title }' }"),
+            "blog:post.sgpagerelease.sgpage news:outage.sgpagerelease.sgpage ");
+    }
+
+    // Test that redirect pages are generated at the aliases of a page.
+    #[test]
+    fn test_aliases() {
+        let root = TempSite::new("aliases");
+        root.write("posts/new.sgpage", "--\ntitle: New\naliases: /old-post.html, 2019/old/\n--\n<p>Moved</p>");
+
+        let mut sitegen = SiteGen::new(&root, Config { quiet: true, ..Config::default() }).unwrap();
+        let summary = sitegen.run();
+        let (old_post, old) = (root.read("old-post.html"), root.read("2019/old/index.html"));
+
+        assert_eq!(summary.unwrap().generated, 3);
+        assert!(old_post.contains("<meta http-equiv=\"refresh\" content=\"0; url=posts/new.html\">"));
+        assert!(old_post.contains("<meta name=\"robots\" content=\"noindex\">"));
+        assert!(old.contains("<link rel=\"canonical\" href=\"../../posts/new.html\">"));
+        assert_eq!(sitegen.site.redirects["/2019/old/index.html"], "/posts/new.html");
+        assert_eq!(sitegen.site.pages.len(), 1);
+    }
+
+    // Test that cache busting appends a hash of their contents to local assets, but leaves
+    // external URLs, URLs with a query string, missing files and pages alone.
+    #[test]
+    fn test_cache_busting() {
+        let root = TempSite::new("cache-bust");
+        root.write("css/site.css", "body { margin: 0 }");
+        root.write("img/a.png", [0x89, 0x50, 0x4e, 0x47]);
+        root.write("about.html", "");
+        root.write("posts/a.html", "");
+        let html = "<link href=\"/css/site.css\"><img src=\"../img/a.png\"><img src=\"https://example.com/b.png\">\
+            <link href=\"/css/site.css?media=print\"><img src=\"/img/missing.png\"><a href=\"/about.html\">About</a>";
+
+        let rewrite = |cache_bust: bool| rewrite_local_links(html, &root.join("posts/a.html"), &root, &Config { cache_bust, ..Config::default() });
+        let (busted, plain) = (rewrite(true), rewrite(false));
+
+        let version = |contents: &[u8]| content_hash(contents)[..8].to_string();
+        assert_eq!(busted, format!("<link href=\"../css/site.css?v={}\"><img src=\"../img/a.png?v={}\"><img src=\"https://example.com/b.png\">\
+            <link href=\"../css/site.css?media=print\"><img src=\"../img/missing.png\"><a href=\"../about.html\">About</a>",
+            version(b"body { margin: 0 }"), version(&[0x89, 0x50, 0x4e, 0x47])));
+        assert_eq!(plain, "<link href=\"../css/site.css\"><img src=\"../img/a.png\"><img src=\"https://example.com/b.png\">\
+            <link href=\"../css/site.css?media=print\"><img src=\"../img/missing.png\"><a href=\"../about.html\">About</a>");
+    }
+
+    // Test that bundles are written by builds, but not by lint, and that with cache busting the
+    // link to a bundle has the version of the bundle that is written.
+    #[test]
+    fn test_bundles_written_by_builds() {
+        let root = TempSite::new("build-bundles");
+        root.write("css/a.css", "a { color: red }");
+        root.write("index.sgpage", "{ bundle_css \"css/*.css\" }");
+
+        let config = Config { quiet: true, cache_bust: true, ..Config::default() };
+        let problems = SiteGen::new(&root, config.clone()).unwrap().lint();
+        let linted = root.join("css/bundle.css").exists();
+        let summary = SiteGen::new(&root, config).unwrap().run();
+        let bundle = std::fs::read(root.join("css/bundle.css")).unwrap_or_default();
+        let index = root.read("index.html");
+
+        assert_eq!(problems.unwrap(), 0);
+        assert!(!linted, "lint wrote the bundle");
+        assert_eq!(summary.unwrap().generated, 1);
+        assert_eq!(index, format!("<link rel=\"stylesheet\" href=\"css/bundle.css?v={}\">", &content_hash(&bundle)[..8]));
+    }
+}
@@ -1,20 +1,70 @@
 use anyhow::{Context, Result};
-use chrono::NaiveDate;
-use std::collections::BTreeMap;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Datelike, NaiveDate};
+use std::borrow::Cow;
+use std::collections::HashSet;
 use std::fmt::Debug;
-use std::fs::{write, File};
+use std::fs::write;
 use std::io::{prelude::*, BufReader};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
+use crate::sitegen::bundles;
+use crate::sitegen::config::{Author, Config, LineEndings, SocialProfile};
+use crate::sitegen::encoding;
+use crate::sitegen::error::PageError;
+use crate::sitegen::filters;
+use crate::sitegen::footnotes;
+use crate::sitegen::gallery::{self, GalleryImage};
+use crate::sitegen::links;
+use crate::sitegen::math;
 use crate::sitegen::metadata::Metadata;
+use crate::sitegen::postprocess;
 use crate::sitegen::regexes::*;
-pub use crate::sitegen::TagPage;
+use crate::sitegen::template::{self, Block, Filter, Node};
+use crate::sitegen::toc;
+use crate::sitegen::replacements;
+use crate::sitegen::typography;
+use crate::sitegen::video;
+pub use crate::sitegen::{GroupContext, Site, TagPage};
 
-// TemplateSource enum defines the source of the template content,
-// either from a file on disk or from a string in memory.
+// Languages that are written from right to left, by their primary language subtag.
+const RTL_LANGUAGES: [&str; 15] = ["ar", "arc", "ckb", "dv", "fa", "he", "iw", "ks", "ku", "nqo", "ps", "sd", "syr", "ug", "ur"];
+
+// Built-in metadata keys, which pages may use whether or not strict_metadata is set.
+const METADATA_KEYS: [&str; 31] = [
+    "title", "group", "tags", "date", "updated", "weight", "author", "author.name", "author.email", "author.url",
+    "author.avatar", "description", "keywords", "excerpt", "lang", "smart_typography", "math", "replace", "draft",
+    "removed", "unlisted", "pinned", "series", "canonical", "layout", "template", "slug", "output", "alternates",
+    "aliases", "spelling_ignore",
+];
+
+// Inline style of the draft badge in preview builds, so the badge doesn't depend on the site's stylesheets.
+const DRAFT_BADGE_STYLE: &str = "position: fixed; top: 0; right: 0; z-index: 2147483647; padding: 6px 12px; \
+    background: #c00; color: #fff; font: bold 14px/1 sans-serif; letter-spacing: 0.1em; pointer-events: none;";
+
+// Inline styles of the { debug } table in preview builds.
+const DEBUG_TABLE_STYLE: &str = "border-collapse: collapse; margin: 1em 0; background: #ffd; color: #000; font: 13px/1.4 monospace;";
+const DEBUG_CELL_STYLE: &str = "border: 1px solid #cc9; padding: 2px 8px; text-align: left; vertical-align: top;";
+
+// First of the private use characters that stand in for { raw } blocks while the page is generated.
+const RAW_PLACEHOLDER_BASE: u32 = 0xF0000;
+
+// First of the private use characters that stand in for { math }, { toc } and { toc_sidebar }
+// blocks until the rest of the page has been generated.
+const DEFERRED_PLACEHOLDER_BASE: u32 = 0x100000;
+
+// Number of placeholder characters for each kind of block that is set aside.
+const PLACEHOLDER_COUNT: u32 = 0x10000;
+
+// Maximum nesting depth of include files, so an include file that includes itself is an error.
+const MAX_NESTING_DEPTH: usize = 32;
+
+// TemplateSource enum defines the source of the template content. Template files are read and
+// decoded before the page is created, see encoding::read_text, and generated templates, e.g. of
+// tag pages, are strings in memory.
 #[derive(Clone, Debug)]
 pub enum TemplateSource {
-    File(),
     Memory(String),
 }
 
@@ -29,84 +79,164 @@ pub struct Page {
     // The current year, used for { current_year } replacements in templates.
     current_year: String,
 
+    // Build configuration shared by all pages.
+    config: Rc<Config>,
+
     // Page title, author, date, etc.
     metadata: Metadata,
 
     // The contents of the page, which will change as we process the template.
     // This will contain the final HTML content of the page.
     contents: String,
+
+    // Problems found while generating the page that don't stop it from being generated, such
+    // as links to pages that don't exist.
+    warnings: Vec<String>,
 }
 
 impl Page {
-    pub fn new(root_path: &std::path::Path, template_path: &PathBuf, template_source: &TemplateSource, current_year: &str) -> Page {
+    pub fn new(root_path: &std::path::Path, template_path: &Path, template_source: &TemplateSource, current_year: &str, config: &Rc<Config>) -> Result<Page, PageError> {
         // The generated HTML document will be stored in the same folder as the template,
         // but with the file extension changed to .html.
         let mut output_path_local = template_path.to_path_buf();
         output_path_local.set_extension("html");
 
-        let mut template_contents = String::new();
-
-        match template_source {
-            TemplateSource::File() => {
-                // Read the template file from disk
-                let mut file = File::open(template_path).unwrap();
-                file.read_to_string(&mut template_contents).unwrap();
-            },
-            TemplateSource::Memory(contents) => {
-                // Use the provided string as the template contents
-                template_contents = contents.clone();
-            }
-        }
+        // Use the provided string as the template contents
+        let TemplateSource::Memory(template_contents) = template_source;
 
-        Page {
+        Ok(Page {
             root_path: root_path.to_path_buf(),
-            output_path: output_path_local.clone(),
-            metadata: Metadata::new(&Page::get_page_path(root_path, output_path_local.clone())),
+            metadata: Metadata {
+                template: Page::get_page_path(root_path, template_path)?,
+                ..Metadata::new(&Page::get_page_path(root_path, &output_path_local)?)
+            },
+            output_path: output_path_local,
             contents: template_contents.clone(),
             current_year: current_year.to_string(),
-        }
+            config: Rc::clone(config),
+            warnings: Vec::new(),
+        })
     }
 
-    fn get_page_path(root_path: &std::path::Path, output_path_local: PathBuf) -> String {
-        let output_path_site = output_path_local.clone().strip_prefix(root_path).unwrap().to_path_buf();
-        let mut page_path = output_path_site.to_str().unwrap().to_string();
+    // The path of a file relative to the web root, e.g. "/posts/italy.html"
+    pub fn get_page_path(root_path: &std::path::Path, path: &Path) -> Result<String, PageError> {
+        let site_path = path.strip_prefix(root_path)
+            .map_err(|_| PageError::OutsideRoot { path: path.to_path_buf(), root: root_path.to_path_buf() })?;
+        let mut page_path = site_path.to_str().ok_or_else(|| PageError::InvalidPath(path.to_path_buf()))?.to_string();
         if !page_path.starts_with('/') {
             page_path.insert(0, '/');
         }
-        page_path
+        Ok(page_path)
     }
 
     // Process the .sgpage template and extract metadata (title, group, tags, date, author) from it.
-    pub fn process_metadata(&mut self) -> Result<()> {
+    pub fn process_metadata(&mut self) -> Result<(), PageError> {
         let mut reader = BufReader::new(self.contents.as_bytes());
 
         let mut reading_metadata = false;
+        let mut reading_vars = false;
+        let mut vars_source = String::new();
+        let mut slug = None;
+        let mut output = None;
+        let mut layout_name = None;
+        let mut author_fields = Vec::new();
         let mut line = String::new();
         let mut processed_contents = String::new();
 
         while reader.read_line(&mut line).is_ok() {
-            if line.eq("\n") || line.eq("\r\n") {
+            if line.is_empty() {
+                // The end of the template
+                if reading_metadata {
+                    return Err(PageError::UnclosedMetadata { template: self.metadata.template.clone() });
+                }
+                break;
+            } else if line.eq("\n") || line.eq("\r\n") {
                 // Ignore empty lines
             } else if line.starts_with("--") {
                 reading_metadata = !reading_metadata;
             } else if !reading_metadata {
                 processed_contents.push_str(&line);
                 break;
+            } else if reading_vars {
+                // Everything after [vars] is TOML, which is parsed at the end of the metadata
+                vars_source.push_str(&line);
+            } else if line.trim() == "[vars]" {
+                reading_vars = true;
             } else if let Some(keyval) = line.split_once(':') {
                 let key = keyval.0.trim();
                 let val = keyval.1.trim().to_string();
                 match key {
                     "title" => self.metadata.title = val,
-                    "group" => self.metadata.group = Some(val),
+                    "group" => {
+                        let mut groups = val.split(',').map(str::trim).filter(|group| !group.is_empty()).map(str::to_string);
+                        self.metadata.group = groups.next();
+                        self.metadata.other_groups = groups.collect();
+                    }
                     "tags" => {
                         self.metadata.tags =
                             Some(val.split(',').map(str::trim).map(str::to_string).collect())
                     }
-                    "date" => {
-                        self.metadata.date = Some(NaiveDate::parse_from_str(&val, "%Y-%m-%d").unwrap())
+                    "date" | "updated" => {
+                        let formats = self.config.metadata_date_formats();
+                        let date = parse_date(&val, &formats).ok_or_else(|| PageError::InvalidDate {
+                            template: self.metadata.template.clone(),
+                            value: val.clone(),
+                            formats: format!("{} or RFC 3339", formats.join(", ")),
+                        })?;
+                        if key == "date" {
+                            self.metadata.date = Some(date);
+                        } else {
+                            self.metadata.updated = Some(date);
+                        }
+                    }
+                    "weight" => {
+                        let weight = val.parse()
+                            .map_err(|source| PageError::InvalidWeight { template: self.metadata.template.clone(), value: val.clone(), source })?;
+                        self.metadata.weight = Some(weight);
+                    }
+                    "author" => self.metadata.author = self.config.author(&val),
+                    "author.name" | "author.email" | "author.url" | "author.avatar" => author_fields.push((key.to_string(), val)),
+                    // Also kept as custom metadata, for templates written before it was a field
+                    "description" => {
+                        self.metadata.description = val.clone();
+                        self.metadata.custom.insert(key.to_string(), val);
+                    }
+                    "keywords" => {
+                        self.metadata.keywords = val.split(',').map(str::trim).filter(|keyword| !keyword.is_empty()).map(str::to_string).collect()
+                    }
+                    "excerpt" => self.metadata.excerpt = escape_html_characters(&val),
+                    "lang" => self.metadata.lang = Some(val),
+                    "smart_typography" => self.metadata.smart_typography = Some(self.parse_flag(key, &val)?),
+                    "math" => self.metadata.math = Some(self.parse_flag(key, &val)?),
+                    "replace" => self.metadata.replace = Some(self.parse_flag(key, &val)?),
+                    "draft" => self.metadata.draft = self.parse_flag(key, &val)?,
+                    "removed" => self.metadata.removed = self.parse_flag(key, &val)?,
+                    "unlisted" => self.metadata.unlisted = self.parse_flag(key, &val)?,
+                    "pinned" => self.metadata.pinned = self.parse_flag(key, &val)?,
+                    "series" => self.metadata.series = Some(val),
+                    "canonical" => self.metadata.canonical = Some(val),
+                    "layout" => self.metadata.layout = Some(val),
+                    "template" => layout_name = Some(val),
+                    "slug" => slug = Some(val),
+                    "output" => output = Some(val),
+                    "alternates" => {
+                        self.metadata.alternates = val.split(',').map(str::trim).map(str::to_string).collect()
+                    }
+                    "aliases" => {
+                        for alias in val.split(',').map(str::trim).filter(|alias| !alias.is_empty()) {
+                            self.metadata.aliases.push(parse_alias(alias).ok_or_else(|| PageError::InvalidAlias {
+                                template: self.metadata.template.clone(),
+                                alias: alias.to_string(),
+                            })?);
+                        }
+                    }
+                    "spelling_ignore" => {
+                        self.metadata.spelling_ignore = val.split(',').map(str::trim).map(str::to_string).collect()
+                    }
+                    _ if !self.config.allows_custom_metadata(key) => return Err(self.unknown_metadata(key)),
+                    _ => {
+                        self.metadata.custom.insert(key.to_string(), val);
                     }
-                    "author" => self.metadata.author = val,
-                    _ => eprintln!("ignoring unknown key '{}'", key),
                 }
             }
             line.clear();
@@ -114,145 +244,444 @@ impl Page {
 
         // Store the remaining contents after metadata processing
         // This will be the actual HTML content of the page.
-        reader.read_to_string(&mut processed_contents)
-            .context("Unable to read template contents")?;
+        // Reading from a string can't fail
+        let _ = reader.read_to_string(&mut processed_contents);
         self.contents = processed_contents;
 
+        // The content above the excerpt separator is the page's summary, starting at the first
+        // paragraph, so headings and includes at the top of the page are left out
+        let separator = self.config.excerpt_separator();
+        if !separator.is_empty() {
+            if let Some((summary, _)) = self.contents.split_once(separator) {
+                let start = RE_PARAGRAPH_START.find(summary).map_or(0, |paragraph| paragraph.start());
+                self.metadata.summary = Some(summary[start..].trim().to_string());
+            }
+        }
+
+        // Without an excerpt in the metadata, the excerpt is taken from the summary or the first paragraph
+        if self.metadata.excerpt.is_empty() {
+            self.metadata.excerpt = automatic_excerpt(self.metadata.summary.as_deref(), &self.contents, self.config.excerpt_words());
+        }
+
+        // Sections that other pages can embed with { excerpt } blocks. A template that can't be
+        // parsed has no sections; the error is reported when the page is generated.
+        let nodes = template::parse(&self.contents).unwrap_or_default();
+        for block in template::find_blocks(&nodes, "section") {
+            if let [name] = block.values()[..] {
+                let body = template::render(&block.body, |_| Ok(None)).unwrap_or_default();
+                self.metadata.sections.entry(name.to_string()).or_insert(body);
+            }
+        }
+
+        // Fields of the author given as author.<field> keys override those of the author's id,
+        // wherever they are in the metadata
+        for (key, value) in author_fields {
+            let author = &mut self.metadata.author;
+            match key.as_str() {
+                "author.name" => author.name = value,
+                "author.email" => author.email = value,
+                "author.url" => author.url = value,
+                _ => author.avatar = value,
+            }
+        }
+
+        self.metadata.vars = toml::from_str(&vars_source)
+            .map_err(|source| PageError::InvalidVars { template: self.metadata.template.clone(), source })?;
+
+        // A slug replaces the file name of the generated page, e.g. "/posts/hello.html" for
+        // "slug: hello" in posts/2024-05-01-hello.sgpage
+        if let Some(slug) = slug {
+            if slug.is_empty() || slug.contains(['/', '\\']) || slug.starts_with('.') {
+                return Err(PageError::InvalidSlug { template: self.metadata.template.clone(), slug });
+            }
+            self.output_path.set_file_name(format!("{}.html", slug));
+            self.metadata.path = Page::get_page_path(&self.root_path, &self.output_path)?;
+        }
+
+        // An output path puts the generated page anywhere in the web root, e.g.
+        // "/downloads/index.html" for "output: /downloads/" in content/downloads.sgpage
+        if let Some(output) = output {
+            let path = parse_alias(&output).ok_or_else(|| PageError::InvalidOutput { template: self.metadata.template.clone(), output })?;
+            self.output_path = self.root_path.join(path.trim_start_matches('/'));
+            self.metadata.path = path;
+        }
+
+        // A template selects one of the configured layouts by name, e.g. "template: landing",
+        // unless the page gives the path of its layout
+        if let Some(name) = layout_name.filter(|_| self.metadata.layout.is_none()) {
+            let Some(layout) = self.config.layouts.get(&name) else {
+                let names = self.config.layouts.keys().map(|name| format!("'{}'", name)).collect::<Vec<_>>().join(", ");
+                return Err(PageError::UnknownTemplate { template: self.metadata.template.clone(), name, names });
+            };
+            self.metadata.layout = Some(layout.clone());
+        }
+
         Ok(())
     }
 
+    // The error for a metadata key that isn't built in or allowed as custom metadata, suggesting
+    // the closest allowed key, as the key is most likely misspelled.
+    fn unknown_metadata(&self, key: &str) -> PageError {
+        let allowed = METADATA_KEYS.into_iter().chain(self.config.custom_metadata.iter().map(String::as_str));
+        let suggestion = match links::closest(key, allowed) {
+            Some(suggestion) => format!(" (did you mean '{}'?)", suggestion),
+            None => String::from(", expected a built-in key or one listed in custom_metadata"),
+        };
+        PageError::UnknownMetadata { template: self.metadata.template.clone(), key: key.to_string(), suggestion }
+    }
+
+    // Parse a metadata value that is either true or false
+    fn parse_flag(&self, key: &str, val: &str) -> Result<bool, PageError> {
+        val.parse().map_err(|_| PageError::InvalidFlag { template: self.metadata.template.clone(), key: key.to_string(), value: val.to_string() })
+    }
+
     pub fn get_metadata(&self) -> Metadata {
         self.metadata.clone()
     }
 
-    pub fn generate(&mut self, prev: Option<Metadata>, next: Option<Metadata>, tags: &BTreeMap<String, TagPage>) {
-        println!("generating page '{}'", &self.metadata.path);
+    // The contents of the page, which is the generated HTML once the page has been generated.
+    pub fn get_contents(&self) -> &str {
+        &self.contents
+    }
 
-        // Process { include "<path>" } blocks
-        self.process_includes();
+    // Problems found while generating the page that didn't stop it from being generated.
+    pub fn get_warnings(&self) -> &[String] {
+        &self.warnings
+    }
 
-        // Process { title }
-        let escaped_title = escape_html_characters(&self.metadata.title);
-        self.contents = self.replace_all(&RE_TITLE, &escaped_title);
+    // Hash of the page's metadata and template contents, used to detect changes between builds.
+    // This must be called before the page is generated. The images shown by { gallery } blocks
+    // and their modification times are included, so adding or changing an image affects the page.
+    pub fn source_hash(&self) -> String {
+        let nodes = template::parse(&self.contents).unwrap_or_default();
+        let images: Vec<_> = template::find_blocks(&nodes, "gallery").iter()
+            .flat_map(|block| block.values().first().map_or(Ok(Vec::new()), |pattern| gallery::find_images(&self.root_path, pattern)).unwrap_or_default())
+            .map(|path| {
+                let modified = std::fs::metadata(&path).and_then(|metadata| metadata.modified()).ok();
+                (path, modified)
+            })
+            .collect();
+        super::content_hash(format!("{:?}{}{:?}", self.metadata, self.contents, images).as_bytes())
+    }
 
-        // Process { date }
-        let date_string = self.metadata.date.map_or(
-            String::new(), |date| date.format("%Y-%m-%d").to_string());
-        self.contents = self.replace_all(&RE_DATE, &date_string);
+    // Paths of the pages that this page refers to with { excerpt } and { link } blocks, and the
+    // other parts of its series, or every page if it lists pages with a { pages } block. The
+    // blocks can be in the page's template, its layout, or the files they include.
+    pub fn referenced_pages(&self, site: &Site) -> Vec<String> {
+        let templates = self.templates(site);
+        let blocks = |name: &str| templates.iter().flat_map(|nodes| template::find_blocks(nodes, name)).collect::<Vec<_>>();
+        if ["pages", "sitemap_html"].iter().any(|name| !blocks(name).is_empty()) {
+            return site.pages.iter().map(|page| page.path.clone()).filter(|path| *path != self.metadata.path).collect();
+        }
+        let parts = super::series_parts(&self.metadata, &site.pages, &self.config).into_iter()
+            .map(|part| part.path.clone())
+            .filter(|path| *path != self.metadata.path);
+        ["excerpt", "link"].iter()
+            .flat_map(|name| blocks(name))
+            .filter_map(|block| block.values().first().and_then(|target| self.page_reference(target, site)))
+            .map(|(path, _)| path)
+            .chain(parts)
+            .collect()
+    }
 
-        // Process { author }
-        let escaped_author = escape_html_characters(&self.metadata.author);
-        self.contents = self.replace_all(&RE_AUTHOR, &escaped_author);
+    // The parsed templates the page is generated from: its own template, its layout, and the
+    // files included by { include } and { include_all } blocks in them, each once. Files that
+    // can't be read are left out, as they fail the page when it is generated.
+    fn templates(&self, site: &Site) -> Vec<Rc<Vec<Node>>> {
+        let mut templates = vec![Rc::new(template::parse(&self.contents).unwrap_or_default())];
+        let mut included: HashSet<String> = self.metadata.layout.iter().cloned().collect();
+        templates.extend(self.metadata.layout.as_ref().and_then(|layout| self.parse_include(layout, site).ok()));
+        let mut i = 0;
+        while i < templates.len() {
+            let mut paths: Vec<String> = template::find_blocks(&templates[i], "include").iter()
+                .filter_map(|block| block.values().first().map(|path| path.to_string()))
+                .collect();
+            for block in template::find_blocks(&templates[i], "include_all") {
+                if let Some(pattern) = block.values().first() {
+                    paths.extend(include_paths(&self.root_path, pattern).unwrap_or_default());
+                }
+            }
+            for path in paths {
+                if included.insert(path.clone()) {
+                    templates.extend(self.parse_include(&path, site).ok());
+                }
+            }
+            i += 1;
+        }
+        templates
+    }
 
-        // Process { current_year }
-        self.contents = self.replace_all(&RE_CURRENT_YEAR, &self.current_year);
+    pub fn generate(&mut self, group: &GroupContext, site: &Site) -> Result<()> {
+        if !self.config.quiet {
+            println!("generating page '{}'", &self.metadata.path);
+        }
 
-        // Process { group "<path>" } conditional includes
-        self.contents = RE_GROUP_NAV
-            .replace_all(&self.contents, |caps: &regex::Captures| {
-                match &self.metadata.group {
-                    Some(_) => {
-                        // Page is part of a group, include the file at the specified path
-                        let path = self.get_local_include_path(caps.name("path").unwrap().as_str());
-                        std::fs::read_to_string(path).unwrap()
-                    }
-                    // No group specified, return empty string to remove the { group ... } block
-                    None => String::new()
+        // Remove the excerpt separator
+        let separator = self.config.excerpt_separator();
+        if !separator.is_empty() {
+            self.contents = self.contents.replace(separator, "");
+        }
+
+        // Render the page's template, or the layout that the page's contents are inserted into.
+        // { raw } blocks, and blocks that are processed once the rest of the page has been
+        // generated, are set aside and replaced with placeholder characters.
+        let template = match &self.metadata.layout {
+            Some(layout) => {
+                let nodes = self.parse_include(layout, site)?;
+                if template::find_blocks(&nodes, "content").is_empty() {
+                    anyhow::bail!("Layout file '{}' has no {{ content }} block", layout);
                 }
-            })
-            .to_string();
-
-        // Create previous page link(s)
-        let (prev_title, prev_path) = match prev {
-            Some(prev) => {
-                // Previous page exists, return its title and path
-                let relative_path = self.make_relative_link(&prev.path);
-                ( prev.title, relative_path )
-            },
-            None => {
-                // No previous page
-                // Title: empty string; link href: "#" (as per spec, to avoid empty hrefs)
-                ( String::new(), String::from("#") )
-            },
+                nodes
+            }
+            None => Rc::new(template::parse(&self.contents)?),
         };
-        self.contents = self.replace_all(&RE_PREV_TITLE, &prev_title);
-        self.contents = self.replace_all(&RE_PREV_PATH, &prev_path);
+        let mut renderer = Renderer { page: self, group, site, raw_blocks: Vec::new(), deferred: Vec::new(), sections: HashSet::new(), random_pages: 0, depth: 0 };
+        let contents = renderer.render(&template, &[])?;
+        let Renderer { mut raw_blocks, deferred, .. } = renderer;
+        self.contents = contents;
 
-        // Create next page link(s)
-        let (next_title, next_path) = match next {
-            Some(next) => {
-                // Next page exists, return its title and path
-                let relative_path = self.make_relative_link(&next.path);
-                ( next.title, relative_path )
-            },
-            None => {
-                // No next page
-                // Title: empty string; link href: "#" (as per spec, to avoid empty hrefs)
-                ( String::new(), String::from("#") )
-            },
-        };
-        self.contents = self.replace_all(&RE_NEXT_TITLE, &next_title);
-        self.contents = self.replace_all(&RE_NEXT_PATH, &next_path);
+        // Add <link rel="alternate"> tags for the page's alternate representations to the <head>
+        if !self.metadata.alternates.is_empty() {
+            if let Some(head_end) = RE_HEAD_END.find(&self.contents) {
+                let links: String = self.metadata.alternates.iter()
+                    .map(|alternate| {
+                        let (path, mime_type, title) = parse_alternate(alternate);
+                        format!("<link rel=\"alternate\" type=\"{}\" href=\"{}\" title=\"{}\">\n",
+                            mime_type, path, escape_html_characters(title))
+                    })
+                    .collect();
+                self.contents.insert_str(head_end.start(), &links);
+            }
+        }
 
-        // Process { tags '<markup>' } to create tag clouds
-        self.contents = RE_TAGS
-            .replace_all(&self.contents, |caps: &regex::Captures| {
-                // Repeat tag markup for each tag (we will perform replacements further below)
-                caps.name("markup").unwrap().as_str().repeat(tags.len())
-            })
-            .to_string();
+        // Add <link rel="me"> tags for the configured social profiles to the <head>, so the
+        // profiles can verify that they belong to the owner of the site
+        if !self.config.social.is_empty() {
+            if let Some(head_end) = RE_HEAD_END.find(&self.contents) {
+                let links: String = self.config.social.iter()
+                    .map(|profile| format!("<link rel=\"me\" href=\"{}\">\n", escape_html_characters(&profile.url)))
+                    .collect();
+                self.contents.insert_str(head_end.start(), &links);
+            }
+        }
+
+        // Add a <meta name="description"> tag for the page's description to the <head>, unless
+        // the template has one already
+        if !self.metadata.description.is_empty() && !RE_META_DESCRIPTION.is_match(&self.contents) {
+            if let Some(head_end) = RE_HEAD_END.find(&self.contents) {
+                let tag = format!("<meta name=\"description\" content=\"{}\">\n", escape_html_characters(&self.metadata.description));
+                self.contents.insert_str(head_end.start(), &tag);
+            }
+        }
 
-        // For each tag in the tag cloud, substitute the link to the tag page,
-        // the font size used for the link, and the page title (tag name)
-        for tag in tags {
-            let relative_tag_path = self.make_relative_link(&tag.1.path);
-            self.contents = self.replace(&RE_TAG_PAGE_LINK, &relative_tag_path);
+        // Likewise for the page's keywords
+        if !self.metadata.keywords.is_empty() && !RE_META_KEYWORDS.is_match(&self.contents) {
+            if let Some(head_end) = RE_HEAD_END.find(&self.contents) {
+                let tag = format!("<meta name=\"keywords\" content=\"{}\">\n", escape_html_characters(&self.metadata.keywords.join(", ")));
+                self.contents.insert_str(head_end.start(), &tag);
+            }
+        }
 
-            // Font size is 11 + the number of pages with this tag, with an upper limit of 18
-            let link_size = 11 + tag.1.meta.len().min(7);
-            self.contents = self.replace(&RE_TAG_PAGE_LINK_SIZE, &link_size.to_string());
+        // Inline the critical stylesheet into the <head>, and load the other stylesheets without
+        // blocking the first paint. The styles are set aside like a { raw } block, so they aren't
+        // changed by text replacements or smart typography.
+        if let Some(critical_css) = &self.config.critical_css {
+            if let Some(head_end) = RE_HEAD_END.find(&self.contents) {
+                let path = format!("/{}", critical_css.trim_start_matches('/'));
+                let css = std::fs::read_to_string(self.root_path.join(path.trim_start_matches('/')))
+                    .with_context(|| format!("Unable to read critical stylesheet '{}'", critical_css))?;
+                let css = bundles::rebase_css_urls(&css, &path, &self.metadata.path, &self.output_path, &self.root_path, &self.config);
+                let placeholder = set_aside(&mut raw_blocks, RAW_PLACEHOLDER_BASE, format!("<style>\n{}\n</style>\n", css.trim()))?;
+                self.contents.insert_str(head_end.start(), &placeholder);
+                self.contents = defer_stylesheets(&self.contents);
+            }
+        }
+
+        // Ask search engines to drop removed pages from their index
+        if self.metadata.removed {
+            if let Some(head_end) = RE_HEAD_END.find(&self.contents) {
+                self.contents.insert_str(head_end.start(), "<meta name=\"robots\" content=\"noindex\">\n");
+            }
+        }
+
+        // Set the page's language, and its text direction, on the <html> element
+        if let Some(lang) = self.metadata.lang.as_ref().or(self.config.lang.as_ref()) {
+            self.contents = set_html_language(&self.contents, lang);
+        }
+
+        // In preview builds, mark drafts and future-dated pages with a badge at the start of the <body>
+        if self.config.drafts {
+            let label = if self.metadata.draft {
+                Some(String::from("DRAFT"))
+            } else {
+                self.metadata.date.filter(|date| *date > site.today).map(|date| format!("DRAFT &middot; scheduled for {}", date))
+            };
+            if let (Some(label), Some(body_start)) = (label, RE_BODY_START.find(&self.contents)) {
+                self.contents.insert_str(body_start.end(), &format!("\n<div class=\"sitewinder-draft\" style=\"{}\">{}</div>", DRAFT_BADGE_STYLE, label));
+            }
+        }
+
+        // Process [^label] footnote markers and [^label]: definitions
+        self.contents = footnotes::process_footnotes(&self.contents, &self.metadata.path);
+
+        // Apply the configured find/replace rules, unless the page opts out
+        if self.metadata.replace.unwrap_or(true) && !self.config.replace.is_empty() {
+            self.contents = replacements::apply(&self.contents, &self.config.replace);
+        }
+
+        // Process { math [display] '<tex>' } blocks
+        self.contents = replace_placeholders(&self.contents, DEFERRED_PLACEHOLDER_BASE, &deferred, |block: &Block| {
+            match (block.name.as_str(), block.values().as_slice()) {
+                ("math", ["display", tex]) => math::render(tex, true).map(Some),
+                ("math", [tex]) => math::render(tex, false).map(Some),
+                _ => Ok(None),
+            }
+        })?;
+
+        // Render math between $...$ and $$...$$, if enabled for this page
+        if self.metadata.math.unwrap_or(self.config.math) {
+            self.contents = math::render_delimited(&self.contents, &self.metadata.path);
+        }
+
+        // Apply smart typography, if enabled for this page
+        if self.metadata.smart_typography.unwrap_or(self.config.smart_typography) {
+            self.contents = typography::smarten(&self.contents);
+        }
+
+        // Process { toc [levels="<first>-<last>"] } and { toc_sidebar [levels="<first>-<last>"] },
+        // giving the page's headings ids to link to
+        if deferred.iter().any(|block| block.name == "toc" || block.name == "toc_sidebar") {
+            let (contents, headings) = toc::anchor_headings(&self.contents);
+            self.contents = replace_placeholders(&contents, DEFERRED_PLACEHOLDER_BASE, &deferred, |block: &Block| {
+                let default_levels = if block.name == "toc" { "1-6" } else { "2-3" };
+                let levels = block.arg("levels").unwrap_or(default_levels);
+                let (first, last) = parse_levels(levels)
+                    .with_context(|| format!("Invalid levels '{}' in {{ {} }} block, expected e.g. \"2-3\"", levels, block.name))?;
+                Ok(Some(if block.name == "toc" { toc::list(&headings, first, last) } else { toc::sidebar(&headings, first, last) }))
+            })?;
+        }
+
+        // Check root-relative links, e.g. "/blog/post_2.html", as written, then rewrite all links
+        // and references to be relative to this document
+        if !site.redirects.is_empty() {
+            self.contents = super::resolve_redirects(&self.contents, &self.metadata.path, &site.redirects, &self.config);
+        }
+        self.warnings = if site.link_targets.is_empty() {
+            Vec::new()
+        } else {
+            links::check(&self.contents, &self.metadata.path, &site.link_targets, &self.config)
+        };
+        self.contents = super::rewrite_local_links(&self.contents, &self.output_path, &self.root_path, &self.config);
 
-            self.contents = self.replace(&RE_TAG_PAGE_TITLE, tag.0);
+        // Put the contents of { raw } blocks back, exactly as they were written
+        self.contents = replace_placeholders(&self.contents, RAW_PLACEHOLDER_BASE, &raw_blocks, |raw: &String| Ok(Some(raw.clone())))?;
+
+        // Pass the finished page through the configured post-processing command, if any
+        if !self.config.post_process.is_empty() {
+            self.contents = postprocess::run(&self.config.post_process, &self.contents, &self.root_path, &self.metadata.path)?;
         }
 
-        // Rewrite all links and references to be relative to this document
-        self.contents = super::rewrite_local_links(&self.contents, &self.output_path, &self.root_path);
+        Ok(())
     }
 
     pub fn write(&self) -> Result<(), anyhow::Error> {
-        // Write the processed contents to the output HTML file
-        write(&self.output_path, &self.contents)
+        // Pages that aren't generated from a template file, such as the pages of removed content,
+        // may be in a folder that doesn't exist (anymore)
+        if let Some(folder) = self.output_path.parent() {
+            std::fs::create_dir_all(folder)
+                .with_context(|| format!("Unable to create folder '{}'", folder.display()))?;
+        }
+
+        // Write the processed contents to the output HTML file, with the configured line endings
+        let contents = match self.config.line_endings {
+            LineEndings::Lf => Cow::Borrowed(self.contents.as_str()),
+            LineEndings::Crlf => Cow::Owned(self.contents.replace("\r\n", "\n").replace('\n', "\r\n")),
+        };
+        write(&self.output_path, contents.as_bytes())
             .with_context(|| format!("Unable to write output HTML file '{}'", &self.output_path.display()))
     }
 
     // Helper method to make links relative to this page
     fn make_relative_link(&self, target_path: &str) -> String {
-        super::make_relative_link(target_path, &self.output_path, &self.root_path)
+        super::make_relative_link(target_path, &self.output_path, &self.root_path, &self.config)
     }
 
-    // Process { include "<path>" } blocks
-    fn process_includes(&mut self) {
-        // Repeat until there are no more { include ... } matches
-        loop {
-            let new_contents = RE_INCLUDE.replace_all(&self.contents, |caps: &regex::Captures| {
-                let path = self.get_local_include_path(caps.name("path").unwrap().as_str());
-                std::fs::read_to_string(path).unwrap()
-            }).to_string();
+    // Helper method to get the title and relative path of a group navigation target
+    fn navigation_link(&self, target: &Option<Metadata>) -> (String, String) {
+        match target {
+            // The target page exists, return its title and path
+            Some(target) => (target.title.clone(), self.make_relative_link(&target.path)),
+            // No target page
+            // Title: empty string; link href: "#" (as per spec, to avoid empty hrefs)
+            None => (String::new(), String::from("#")),
+        }
+    }
 
-            if new_contents == self.contents {
-                break;
-            }
+    // Helper method to get the relative path to the web root, e.g. "../.." for a page two
+    // folders deep, or "." for pages in the web root itself. With a base path, this is the
+    // base path instead, e.g. "/myproject".
+    fn get_relative_root(&self) -> String {
+        if let Some(prefix) = self.config.base_prefix() {
+            return prefix;
+        }
 
-            self.contents = new_contents;
+        let depth = self.output_path
+            .parent()
+            .and_then(|dir| dir.strip_prefix(&self.root_path).ok())
+            .map_or(0, |dir| dir.components().count());
+
+        if depth == 0 {
+            String::from(".")
+        } else {
+            vec![".."; depth].join("/")
         }
     }
 
-    fn replace(&self, regex: &regex::Regex, replacement: &str) -> String {
-        regex.replace(&self.contents, replacement).to_string()
+    // The path of the page that an { excerpt } or { link } block refers to, and the section if any, e.g.
+    // ("/posts/italy.html", Some("route")) for "posts/italy.sgpage#route" in "/index.html".
+    // Paths are relative to this page, or to the web root if they start with '/'.
+    fn page_reference<'t>(&self, target: &'t str, site: &Site) -> Option<(String, Option<&'t str>)> {
+        let (path, section) = match target.split_once('#') {
+            Some((path, section)) => (path, Some(section)),
+            None => (target, None),
+        };
+        // Templates are looked up by path, as the page of a template with a slug has another name
+        let path = super::resolve_link(&self.metadata.path, path, &self.config)?;
+        let path = match path.strip_suffix(".sgpage") {
+            Some(stem) => site.pages.iter().find(|page| page.template == path).map_or(format!("{}.html", stem), |page| page.path.clone()),
+            None => path,
+        };
+        Some((path, section))
+    }
+
+    // Read the contents of an include file, given its path relative to the web root. Include
+    // files must be inside the web root, unless the configuration allows otherwise, so a path
+    // such as "/../../etc/passwd" can't include unrelated files by accident.
+    fn read_include(&self, filename: &str) -> Result<String> {
+        let path = self.get_local_include_path(filename);
+        if !self.config.allow_outside_root {
+            let resolved = path.canonicalize()
+                .with_context(|| format!("Unable to read include file '{}'", path.display()))?;
+            let root = self.root_path.canonicalize().unwrap_or_else(|_| self.root_path.clone());
+            if !resolved.starts_with(&root) {
+                anyhow::bail!("Include file '{}' is outside the web root, use --allow-outside-root to include it anyway", filename);
+            }
+        }
+        encoding::read_text(&path)
+            .with_context(|| format!("Unable to read include file '{}'", path.display()))
     }
 
-    fn replace_all(&self, regex: &regex::Regex, replacement: &str) -> String {
-        regex.replace_all(&self.contents, replacement).to_string()
+    // Read and parse an include file, given its path relative to the web root. Include files are
+    // parsed once per build, and shared by all pages that include them.
+    fn parse_include(&self, filename: &str, site: &Site) -> Result<Rc<Vec<Node>>> {
+        let path = self.get_local_include_path(filename);
+        if let Some(nodes) = site.includes.borrow().get(&path) {
+            return Ok(Rc::clone(nodes));
+        }
+        let nodes = Rc::new(template::parse(&self.read_include(filename)?)
+            .with_context(|| format!("Invalid template in include file '{}'", filename))?);
+        site.includes.borrow_mut().insert(path, Rc::clone(&nodes));
+        Ok(nodes)
     }
 
     fn get_local_include_path(&self, filename: &str) -> PathBuf {
@@ -263,32 +692,1738 @@ impl Page {
     }
 }
 
-fn escape_html_characters(input: &str) -> String {
-    input.replace('<', "&lt;").replace('>', "&gt;")
+// Renders the template of a page, keeping track of the blocks that are set aside.
+struct Renderer<'r> {
+    page: &'r Page,
+    group: &'r GroupContext,
+    site: &'r Site,
+    // Contents of the { raw } blocks, which are put back once the page has been generated.
+    raw_blocks: Vec<String>,
+    // { math }, { toc } and { toc_sidebar } blocks, which are processed once the rest of the page
+    // has been generated.
+    deferred: Vec<Block>,
+    // Names of the sections so far, which must be unique as they are used as ids.
+    sections: HashSet<String>,
+    // Number of { random_page } blocks so far, as each block selects a page independently.
+    random_pages: usize,
+    // Nesting depth of include files, layouts and summaries.
+    depth: usize,
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::path::Path;
+impl Renderer<'_> {
+    fn render(&mut self, nodes: &[Node], scopes: &[Scope]) -> Result<String> {
+        template::render(nodes, |block: &Block| self.resolve(block, scopes))
+    }
 
-    // Test that reserved characters '<' and '>' in the title and author metadata are escaped correctly.
-    #[test]
-    fn test_title_author_metadata_escaped() {
-        let template = TemplateSource::Memory(String::from(
-            "--\ntitle: <>\nauthor: ><\n--\n\
-            <!DOCTYPE html><html><head><title>{title}</title></head><body>{author}</body></html>"
-        ));
+    // Render a template that is included in the page, such as an include file
+    fn render_nested(&mut self, nodes: &[Node], scopes: &[Scope]) -> Result<String> {
+        if self.depth >= MAX_NESTING_DEPTH {
+            anyhow::bail!("Include files nested too deeply, does an include file include itself?");
+        }
+        self.depth += 1;
+        let html = self.render(nodes, scopes);
+        self.depth -= 1;
+        html
+    }
 
-        let escaped_contents = String::from(
-            "<!DOCTYPE html><html><head><title>&lt;&gt;</title></head><body>&gt;&lt;</body></html>"
-        );
+    // Render an include file. Include files that give the same markup on every page, e.g. a
+    // footer without page placeholders, are rendered once per build, and shared by all pages.
+    fn render_include(&mut self, filename: &str, scopes: &[Scope]) -> Result<String> {
+        let (page, site) = (self.page, self.site);
+        let path = page.get_local_include_path(filename);
+        if let Some(html) = site.rendered_includes.borrow().get(&path) {
+            return Ok(html.clone());
+        }
+        let nodes = page.parse_include(filename, site)?;
+        let html = self.render_nested(&nodes, scopes)?;
+        if scopes.is_empty() && same_on_every_page(&nodes, page, site, 0) {
+            site.rendered_includes.borrow_mut().insert(path, html.clone());
+        }
+        Ok(html)
+    }
 
-        let mut page = Page::new(Path::new(""), &PathBuf::new(), &template, "");
+    // Render a block, or give None for blocks that are left as they are
+    fn resolve(&mut self, block: &Block, scopes: &[Scope]) -> Result<Option<String>> {
+        let (page, site) = (self.page, self.site);
+        let metadata = &page.metadata;
 
-        page.process_metadata().unwrap();
-        page.generate(None, None, &BTreeMap::new());
+        // Placeholders, e.g. { title }, or { item } in the markup of an { each } block
+        if block.args.is_empty() {
+            let value = scopes.iter().rev()
+                .find_map(|scope| scope.value(&block.name, page))
+                .or_else(|| self.value(&block.name));
+            if let Some((value, escape)) = value {
+                return filtered(&value, &block.filters, escape).map(Some);
+            }
+        }
 
-        assert_eq!(page.contents, escaped_contents);
+        let html = match (block.name.as_str(), block.values().as_slice()) {
+            // Process { include "<path>" } blocks
+            ("include", [path]) => self.render_include(path, scopes)?,
+
+            // Process { include_all "<pattern>" } blocks, including each file that matches a glob
+            // pattern, in order of path
+            ("include_all", [pattern]) => {
+                let mut html = String::new();
+                for path in include_paths(&page.root_path, pattern)? {
+                    html.push_str(&self.render_include(&path, scopes)?);
+                }
+                html
+            }
+
+            // Process { bundle_css "<pattern>" [out="<path>"] [minify] } and
+            // { bundle_js "<pattern>" [out="<path>"] } blocks, bundling the matching files and
+            // linking to the bundle. The bundle is written once all pages have been generated, so
+            // with cache busting, its version is given here, from its contents.
+            ("bundle_css", [_] | [_, "minify"]) | ("bundle_js", [_]) => match bundles::bundle(&page.root_path, block, &page.config)? {
+                Some((path, contents)) => {
+                    let mut link = page.make_relative_link(&path);
+                    if page.config.cache_bust {
+                        link = format!("{}?v={}", link, &super::content_hash(contents.as_bytes())[..8]);
+                    }
+                    site.bundles.borrow_mut().insert(path, contents);
+                    match block.name.as_str() {
+                        "bundle_css" => format!("<link rel=\"stylesheet\" href=\"{}\">", link),
+                        _ => format!("<script src=\"{}\"></script>", link),
+                    }
+                }
+                None => String::new(),
+            },
+
+            // Process { include_raw "<path>" } blocks, inserting the file as text rather than markup.
+            // The text is set aside like a { raw } block, so it isn't changed by link rewriting or
+            // smart typography either.
+            ("include_raw", [path]) => {
+                let text = page.read_include(path)?.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;");
+                set_aside(&mut self.raw_blocks, RAW_PLACEHOLDER_BASE, text)?
+            }
+
+            // Process { content }, the page's contents inserted into its layout
+            ("content", []) if metadata.layout.is_some() => self.render_nested(&template::parse(&page.contents)?, scopes)?,
+
+            // Process { summary }, which is processed like the rest of the page
+            ("summary", []) => match &metadata.summary {
+                Some(summary) => self.render_nested(&template::parse(summary)?, scopes)?,
+                None => String::new(),
+            },
+
+            // Process { excerpt "<path>" } and { excerpt "<path>#<section>" }, embedding the summary or
+            // a section of another page, which are processed like the rest of this page
+            ("excerpt", [target]) => {
+                let (path, section) = page.page_reference(target, site)
+                    .with_context(|| format!("Invalid page '{}' in {{ excerpt }} block", target))?;
+                let source = site.pages.iter().find(|other| other.path == path)
+                    .with_context(|| format!("Unknown page '{}' in {{ excerpt }} block", target))?;
+                let excerpt = match section {
+                    Some(section) => source.sections.get(section)
+                        .with_context(|| format!("Page '{}' has no section '{}'", path, section))?,
+                    None => source.summary.as_ref()
+                        .with_context(|| format!("Page '{}' has no summary to excerpt", path))?,
+                };
+                let excerpt = super::rebase_links(excerpt, &source.path, &page.config);
+                self.render_nested(&template::parse(&excerpt)?, scopes)?
+            }
+
+            // Process { link "<path>" } and { link "<path>#<section>" }, linking to another page or
+            // a section of it
+            ("link", [target]) => {
+                let (path, section) = page.page_reference(target, site)
+                    .with_context(|| format!("Invalid page '{}' in {{ link }} block", target))?;
+                let linked = site.pages.iter().find(|other| other.path == path)
+                    .with_context(|| format!("Unknown page '{}' in {{ link }} block", target))?;
+                match section {
+                    Some(section) if !linked.sections.contains_key(section) => {
+                        anyhow::bail!("Page '{}' has no section '{}'", path, section)
+                    }
+                    Some(section) => format!("{}#{}", page.make_relative_link(&path), section),
+                    None => page.make_relative_link(&path),
+                }
+            }
+
+            // Process { section "<name>" } ... { endsection }, which other pages can link to and embed
+            ("section", [name]) => {
+                if !self.sections.insert(name.to_string()) {
+                    anyhow::bail!("Duplicate section '{}'", name);
+                }
+                let id = escape_html_characters(name);
+                format!("<section id=\"{}\">{}</section>", id, self.render(&block.body, scopes)?)
+            }
+
+            // Set aside { raw } ... { endraw } blocks
+            ("raw", []) => set_aside(&mut self.raw_blocks, RAW_PLACEHOLDER_BASE, template::render(&block.body, |_| Ok(None))?)?,
+
+            // Process { for <name> in meta.<key> } ... { endfor } and { for <name> in vars.<name> } ... { endfor }
+            // loops, repeating their body for each item of a comma-separated metadata value or a list
+            ("for", [variable, "in", list]) => {
+                let items: Vec<toml::Value> = match list.split_once('.') {
+                    Some(("meta", key)) => metadata.custom.get(key)
+                        .map(|list| list.split(',').map(str::trim).filter(|item| !item.is_empty())
+                            .map(|item| toml::Value::String(item.to_string())).collect())
+                        .unwrap_or_default(),
+                    Some(("vars", name)) => list_items(metadata.vars.get(name)),
+                    _ => anyhow::bail!("Invalid list '{}' in {{ for }} block, expected meta.<key> or vars.<name>", list),
+                };
+                items.iter()
+                    .map(|item| self.render(&block.body, &[scopes, &[Scope::Item(variable, item)]].concat()))
+                    .collect::<Result<String>>()?
+            }
+
+            // Process { if tag "<tag>" } ... { endif } and { if group "<group>" } ... { endif }, with an
+            // optional { else } part, rendering the part for whether the page has the tag
+            // (case-insensitive) or belongs to the group
+            ("if", [condition, value]) => {
+                let holds = match *condition {
+                    "tag" => metadata.tags.iter().flatten().any(|tag| tag.to_lowercase() == value.to_lowercase()),
+                    "group" => metadata.in_group(value),
+                    _ => anyhow::bail!("Invalid condition '{}' in {{ if }} block, expected tag or group", condition),
+                };
+                let (then, otherwise) = template::split_else(&block.body);
+                self.render(if holds { then } else { otherwise }, scopes)?
+            }
+
+            // Process { date } and { date "<format>" }, and likewise { updated }
+            ("date" | "updated", [] | [_]) => {
+                let format = block.values().first().copied().unwrap_or(page.config.date_format());
+                let date = if block.name == "date" { metadata.date } else { metadata.updated };
+                let date = date.map(|date| format_date(date, format)).transpose()?.unwrap_or_default();
+                filters::apply(&date, &block.filters)?
+            }
+
+            // Process { env "<name>" }, inserting the value of an environment variable
+            ("env", [name]) => {
+                let value = std::env::var(name)
+                    .with_context(|| format!("Environment variable '{}' in {{ env }} block is not set", name))?;
+                filtered(&value, &block.filters, true)?
+            }
+
+            // Process { debug }, listing the placeholders available to the page in preview builds.
+            // The table is set aside like a { raw } block, so it shows the values as they are.
+            ("debug", []) if page.config.drafts => {
+                let table = self.debug_table();
+                set_aside(&mut self.raw_blocks, RAW_PLACEHOLDER_BASE, table)?
+            }
+            ("debug", []) => String::new(),
+
+            // Process { group ["<name>"] "<path>" [else "<path>"] } conditional includes
+            ("group", values) => {
+                let Some((name, path, else_path)) = group_nav_args(values) else {
+                    return Ok(None);
+                };
+                // If a group name is given, the page must belong to that group,
+                // otherwise it is sufficient that the page belongs to any group
+                let in_group = match (name, &metadata.group) {
+                    (Some(name), Some(group)) => name == group,
+                    (None, Some(_)) => true,
+                    (_, None) => false,
+                };
+
+                match if in_group { Some(path) } else { else_path } {
+                    // Include the file at the selected path
+                    Some(path) => self.render_nested(&page.parse_include(path, site)?, scopes)?,
+                    // No file for this case, remove the { group ... } block
+                    None => String::new(),
+                }
+            }
+
+            // Process { each vars.<name> [sep="<separator>"] [empty='<markup>'] '<markup>' } blocks,
+            // repeating the markup for each item of a list, or each entry of a map, in the page's [vars]
+            ("each", [list, markup]) if list.starts_with("vars.") => {
+                let markup = template::parse(markup)?;
+                let entries = list_items(metadata.vars.get(&list["vars.".len()..])).iter()
+                    .map(|item| self.render(&markup, &[scopes, &[Scope::Item("item", item)]].concat()))
+                    .collect::<Result<_>>()?;
+                super::join_listing(entries, block)
+            }
+
+            // Process { gallery "<pattern>" [sep="<separator>"] [empty='<markup>'] '<markup>' } blocks,
+            // repeating the markup for each image matching the pattern, and generating thumbnails
+            ("gallery", [pattern, markup]) => {
+                let thumbnail_size = page.config.thumbnail_size.unwrap_or(gallery::DEFAULT_THUMBNAIL_SIZE);
+                let markup = template::parse(markup)?;
+                let entries = gallery::gallery_images(&page.root_path, pattern, thumbnail_size)?.iter()
+                    .map(|image| self.render(&markup, &[scopes, &[Scope::Image(image)]].concat()))
+                    .collect::<Result<_>>()?;
+                super::join_listing(entries, block)
+            }
+
+            // Process { video "<provider>:<id>" [title="<title>"] [poster="<path>"] } embeds
+            ("video", [video]) => {
+                let Some((provider, id)) = video.split_once(':') else {
+                    return Ok(None);
+                };
+                let title = block.arg("title").unwrap_or("Video");
+                // The poster is shown inside the player's frame, where links aren't rewritten
+                let poster = block.arg("poster").map(|poster| page.make_relative_link(poster));
+                let config = page.config.video_config(provider);
+                video::embed(provider, id, title, poster.as_deref(), &config)?
+            }
+
+            // Process { alternates [sep="<separator>"] [empty='<markup>'] '<markup>' } blocks, listing
+            // the page's alternate representations
+            ("alternates", [markup]) => {
+                let markup = template::parse(markup)?;
+                let entries = metadata.alternates.iter()
+                    .map(|alternate| self.render(&markup, &[scopes, &[Scope::Alternate(alternate)]].concat()))
+                    .collect::<Result<_>>()?;
+                super::join_listing(entries, block)
+            }
+
+            // Process { social [sep="<separator>"] [empty='<markup>'] '<markup>' } blocks, listing the
+            // configured social profiles, e.g. in the footer
+            ("social", [markup]) => {
+                let markup = template::parse(markup)?;
+                let entries = page.config.social.iter()
+                    .map(|profile| self.render(&markup, &[scopes, &[Scope::Profile(profile)]].concat()))
+                    .collect::<Result<_>>()?;
+                super::join_listing(entries, block)
+            }
+
+            // Process { random_page '<markup>' } blocks, linking to a pseudo-random page other than
+            // this one. The selection is seeded, so a build with the same seed gives the same output.
+            ("random_page", [markup]) => {
+                let candidates: Vec<&Metadata> = site.pages.iter()
+                    .filter(|candidate| candidate.path != metadata.path)
+                    .collect();
+                if candidates.is_empty() {
+                    return Ok(Some(String::new()));
+                }
+
+                // Each block on the page selects a page independently
+                self.random_pages += 1;
+                let key = format!("{}#{}", metadata.path, self.random_pages);
+                let selected = candidates[super::seeded_index(&site.random_seed, &key, candidates.len())];
+
+                self.render(&template::parse(markup)?, &[scopes, &[Scope::Page(selected)]].concat())?
+            }
+
+            // Process { series_nav [sep="<separator>"] [empty='<markup>'] [current='<markup>'] '<markup>' }
+            // blocks, listing the parts of the page's series in order. The page itself is listed
+            // with the `current` markup, if given.
+            ("series_nav", [markup]) => {
+                let markup = template::parse(markup)?;
+                let current = block.arg("current").map(template::parse).transpose()?;
+                let entries = super::series_parts(metadata, &site.pages, &page.config).into_iter().enumerate()
+                    .map(|(i, part)| {
+                        let markup = current.as_ref().filter(|_| part.path == metadata.path).unwrap_or(&markup);
+                        self.render(markup, &[scopes, &[Scope::Part(i + 1, part)]].concat())
+                    })
+                    .collect::<Result<_>>()?;
+                super::join_listing(entries, block)
+            }
+
+            // Process { pages [group="<group>"] [tag="<tag>"] [sort=desc|asc|title] [limit=<n>] '<markup>' } blocks,
+            // listing content pages, e.g. the most recent posts on the front page
+            ("pages", [markup]) => super::index_listing(&site.pages, block, markup, &page.config)?,
+
+            // Process { sitemap_html ['<markup>'] } blocks, listing all content pages by group for a
+            // human-readable site map
+            ("sitemap_html", []) => super::sitemap_listing(&site.pages, block, None, &page.config)?,
+            ("sitemap_html", [markup]) => super::sitemap_listing(&site.pages, block, Some(markup), &page.config)?,
+
+            // Process { tags [sep="<separator>"] [empty='<markup>'] [order=name|count|recent] [limit=<n>] '<markup>' }
+            // to create tag clouds
+            ("tags", [markup]) => {
+                // Tags are in alphabetical order by default; the sorts below are stable, so tags
+                // with the same count or date remain in alphabetical order
+                let mut tags: Vec<(&String, &TagPage)> = site.tags.iter().collect();
+                match block.arg("order") {
+                    None | Some("name") => {}
+                    // Most used tags first
+                    Some("count") => tags.sort_by_key(|tag| std::cmp::Reverse(tag.1.meta.len())),
+                    // Most recently used tags first, based on the newest page with each tag
+                    Some("recent") => tags.sort_by(|lhs, rhs| {
+                        let newest = |tag: &TagPage| tag.meta.iter().filter_map(|meta| meta.date).max();
+                        newest(rhs.1).cmp(&newest(lhs.1))
+                    }),
+                    Some(order) => anyhow::bail!("Invalid value '{}' for 'order' in {{ tags }} block, expected name, count or recent", order),
+                }
+                if let Some(limit) = block.arg("limit") {
+                    let limit = limit.parse()
+                        .with_context(|| format!("Invalid value '{}' for 'limit' in {{ tags }} block", limit))?;
+                    tags.truncate(limit);
+                }
+
+                let markup = template::parse(markup)?;
+                let entries = tags.into_iter()
+                    .map(|(name, tag)| self.render(&markup, &[scopes, &[Scope::Tag(name, tag)]].concat()))
+                    .collect::<Result<_>>()?;
+                super::join_listing(entries, block)
+            }
+
+            // Set aside { math [display] '<tex>' }, { toc } and { toc_sidebar } blocks
+            ("math", [_] | ["display", _]) | ("toc" | "toc_sidebar", []) => {
+                set_aside(&mut self.deferred, DEFERRED_PLACEHOLDER_BASE, block.clone())?
+            }
+
+            _ => return Ok(None),
+        };
+        Ok(Some(html))
+    }
+
+    // Value of a placeholder of the page, and whether it needs to be escaped for HTML
+    fn value(&self, name: &str) -> Option<(String, bool)> {
+        let page = self.page;
+        let metadata = &page.metadata;
+
+        let value = match name {
+            "title" => (metadata.title.clone(), true),
+            "description" => (metadata.description.clone(), true),
+            "keywords" => (metadata.keywords.join(", "), true),
+            // The page's canonical URL from its metadata, or its own URL: absolute below the
+            // site's base_url and base path, or a link to the page itself without a base_url
+            "canonical" => match (&metadata.canonical, page.config.absolute_url(&super::url_encode_path(&metadata.path))) {
+                (Some(canonical), _) => (canonical.clone(), true),
+                (None, Some(url)) => (url, true),
+                (None, None) => (page.make_relative_link(&metadata.path), false),
+            },
+            // The site's author from the configuration, unless the page has an author of its own
+            "author" => (self.author().name, true),
+            "excerpt" => (metadata.excerpt.clone(), false),
+            "current_year" => (page.current_year.clone(), false),
+            // The years from the configured start year, or the year of the oldest page, to the
+            // current year, e.g. "2018–2025", or just the current year
+            "copyright_years" => {
+                let start = page.config.copyright_start
+                    .or_else(|| self.site.pages.iter().filter_map(|page| page.date).min().map(|date| date.year()));
+                match start.filter(|start| page.current_year.parse().is_ok_and(|year: i32| *start < year)) {
+                    Some(start) => (format!("{}\u{2013}{}", start, page.current_year), false),
+                    None => (page.current_year.clone(), false),
+                }
+            }
+            "group.name" => (metadata.group.clone().unwrap_or_default(), true),
+            // The link to this page itself
+            "page.path" => (page.make_relative_link(&metadata.path), false),
+            // The relative path from this page to the web root
+            "root" => (page.get_relative_root(), false),
+            "site.page_count" => (self.site.pages.len().to_string(), false),
+            "site.tag_count" => (self.site.tags.len().to_string(), false),
+            "group.page_count" | "group.size" => (self.group.page_count.to_string(), false),
+            // The page's position in its group, e.g. for "Part 3 of 7"
+            "page.index" => (self.group.index.to_string(), false),
+            // The page's series, its position in the series and the number of parts, e.g. for
+            // "Part 3 of 5". The position is 0 for pages that aren't in the series' listings.
+            "series.title" => (metadata.series.clone().unwrap_or_default(), true),
+            "series.index" | "series.count" => {
+                let parts = super::series_parts(metadata, &self.site.pages, &page.config);
+                let value = match name {
+                    "series.index" => parts.iter().position(|part| part.path == metadata.path).map_or(0, |i| i + 1),
+                    _ => parts.len(),
+                };
+                (value.to_string(), false)
+            }
+            // <link rel="prev"> and <link rel="next"> tags
+            "head.prevnext" => {
+                let mut head_links = String::new();
+                if let Some(prev) = &self.group.prev {
+                    head_links.push_str(&format!("<link rel=\"prev\" href=\"{}\">", page.make_relative_link(&prev.path)));
+                }
+                if let Some(next) = &self.group.next {
+                    head_links.push_str(&format!("<link rel=\"next\" href=\"{}\">", page.make_relative_link(&next.path)));
+                }
+                (head_links, false)
+            }
+            _ => match name.split_once('.') {
+                // Custom metadata values and variables
+                Some(("meta", key)) => (metadata.custom.get(key).cloned().unwrap_or_default(), true),
+                Some(("vars", name)) => (metadata.vars.get(name).map(value_text).unwrap_or_default(), true),
+                // Site-wide values from the configuration, which the page's own metadata overrides
+                Some(("site", key)) => {
+                    let own = match key {
+                        "author" => Some(self.author().name),
+                        key => metadata.custom.get(key).cloned(),
+                    };
+                    (own.or_else(|| page.config.site.get(key).map(value_text)).unwrap_or_default(), true)
+                }
+                // Details of the page's author, or of the site's author
+                Some(("author", field)) => (self.author().field(field)?.to_string(), true),
+                // Build variables from the configuration or the command line
+                Some(("var", key)) => (page.config.define.get(key).cloned().unwrap_or_default(), true),
+                // Links to the previous, next, first (oldest) and last (newest) pages in the group
+                Some((target @ ("prev" | "next" | "first" | "last"), field @ ("title" | "path"))) => {
+                    let target = match target {
+                        "prev" => &self.group.prev,
+                        "next" => &self.group.next,
+                        "first" => &self.group.first,
+                        _ => &self.group.last,
+                    };
+                    let (title, path) = page.navigation_link(target);
+                    if field == "title" { (title, true) } else { (path, false) }
+                }
+                _ => return None,
+            },
+        };
+        Some(value)
+    }
+
+    // The page's author, or the site's author from the configuration if the page has none
+    fn author(&self) -> Author {
+        let author = &self.page.metadata.author;
+        match self.page.config.site.get("author").and_then(toml::Value::as_str) {
+            Some(site_author) if *author == Author::default() => self.page.config.author(site_author),
+            _ => author.clone(),
+        }
+    }
+
+    // A table of the placeholders available to the page and their values, followed by the
+    // page's other metadata and the tags of the site, for { debug } blocks
+    fn debug_table(&self) -> String {
+        let metadata = &self.page.metadata;
+        let mut names: Vec<String> = [
+            "title", "author", "author.url", "author.email", "author.avatar", "description", "keywords", "excerpt", "current_year", "copyright_years", "root", "page.path", "page.index",
+            "canonical", "group.name", "group.page_count", "site.page_count", "site.tag_count", "series.title", "series.index", "series.count",
+        ].iter().map(|name| name.to_string()).collect();
+        names.extend(["prev", "next", "first", "last"].iter().flat_map(|target| [format!("{}.title", target), format!("{}.path", target)]));
+        names.extend(metadata.custom.keys().map(|key| format!("meta.{}", key)));
+        names.extend(self.page.config.site.keys().map(|key| format!("site.{}", key)));
+        names.extend(self.page.config.define.keys().map(|key| format!("var.{}", key)));
+
+        let mut rows: Vec<(String, String)> = names.iter()
+            .map(|name| (format!("{{ {} }}", name), self.value(name).map(|(value, _)| value).unwrap_or_default()))
+            .collect();
+        rows.push((String::from("{ date }"), metadata.date.map(|date| date.to_string()).unwrap_or_default()));
+        rows.push((String::from("{ updated }"), metadata.updated.map(|date| date.to_string()).unwrap_or_default()));
+        rows.extend(metadata.vars.iter().map(|(name, value)| (format!("{{ vars.{} }}", name), value.to_string())));
+        rows.push((String::from("tags"), metadata.tags.as_deref().unwrap_or_default().join(", ")));
+        rows.push((String::from("layout"), metadata.layout.clone().unwrap_or_default()));
+        rows.push((String::from("sections"), metadata.sections.keys().cloned().collect::<Vec<_>>().join(", ")));
+        rows.push((String::from("site tags"), self.site.tags.iter()
+            .map(|(name, tag)| format!("{} ({})", name, tag.meta.len()))
+            .collect::<Vec<_>>().join(", ")));
+
+        let escape = |text: &str| escape_html_characters(&text.replace('&', "&amp;"));
+        let mut html = format!("<table class=\"sitewinder-debug\" style=\"{}\">\n", DEBUG_TABLE_STYLE);
+        for (name, value) in rows {
+            html.push_str(&format!("<tr><th style=\"{}\">{}</th><td style=\"{}\">{}</td></tr>\n",
+                DEBUG_CELL_STYLE, escape(&name), DEBUG_CELL_STYLE, escape(&value)));
+        }
+        html.push_str("</table>");
+        html
+    }
+}
+
+// Placeholders that listing blocks and loops provide to the markup they repeat.
+#[derive(Clone)]
+enum Scope<'s> {
+    // A { for } loop variable or the item of an { each } block: { <name> } and { <name>.<field> }
+    Item(&'s str, &'s toml::Value),
+    // The page selected by a { random_page } block: { page.link } and { page.title }
+    Page(&'s Metadata),
+    // A tag in a { tags } cloud: { tag.page.link }, { tag.page.link_size } and { tag.page.title }
+    Tag(&'s str, &'s TagPage),
+    // An image in a { gallery }: { image.src }, { image.thumb } and { image.alt }
+    Image(&'s GalleryImage),
+    // An alternate representation: { alternate.link }, { alternate.type } and { alternate.title }
+    Alternate(&'s str),
+    // A part of the page's series in a { series_nav }: { page.link }, { page.title } and
+    // { page.index }, its position in the series
+    Part(usize, &'s Metadata),
+    // A configured social profile: { profile.name } and { profile.url }
+    Profile(&'s SocialProfile),
+}
+
+impl Scope<'_> {
+    // Value of a placeholder in this scope, and whether it needs to be escaped for HTML
+    fn value(&self, name: &str, page: &Page) -> Option<(String, bool)> {
+        let value = match (self, name) {
+            (Scope::Item(variable, item), name) => {
+                let value = if name == *variable {
+                    Some(*item)
+                } else {
+                    item.get(name.strip_prefix(variable)?.strip_prefix('.')?)
+                };
+                (value.map(value_text).unwrap_or_default(), true)
+            }
+            (Scope::Page(selected), "page.link") => (selected.path.clone(), false),
+            (Scope::Page(selected), "page.title") => (selected.title.clone(), true),
+            (Scope::Tag(_, tag), "tag.page.link") => (page.make_relative_link(&tag.path), false),
+            // Font size is 11 + the number of pages with this tag, with an upper limit of 18
+            (Scope::Tag(_, tag), "tag.page.link_size") => ((11 + tag.meta.len().min(7)).to_string(), false),
+            (Scope::Tag(name, _), "tag.page.title") => (name.to_string(), true),
+            (Scope::Image(image), "image.src") => (image.src.clone(), false),
+            (Scope::Image(image), "image.thumb") => (image.thumb.clone(), false),
+            (Scope::Image(image), "image.alt") => (image.alt.clone(), true),
+            (Scope::Part(_, part), "page.link") => (part.path.clone(), false),
+            (Scope::Part(_, part), "page.title") => (part.title.clone(), true),
+            (Scope::Part(index, _), "page.index") => (index.to_string(), false),
+            (Scope::Profile(profile), "profile.name") => (profile.name.clone(), true),
+            (Scope::Profile(profile), "profile.url") => (profile.url.clone(), true),
+            (Scope::Alternate(alternate), _) => {
+                let (path, mime_type, title) = parse_alternate(alternate);
+                match name {
+                    "alternate.link" => (path.to_string(), false),
+                    "alternate.type" => (mime_type.to_string(), false),
+                    "alternate.title" => (title.to_string(), true),
+                    _ => return None,
+                }
+            }
+            _ => return None,
+        };
+        Some(value)
+    }
+}
+
+// Format a date with a strftime format string, e.g. "%B %e, %Y"
+pub fn format_date(date: NaiveDate, format: &str) -> Result<String> {
+    let items: Vec<Item> = StrftimeItems::new(format).collect();
+    if items.contains(&Item::Error) {
+        anyhow::bail!("Invalid date format '{}'", format);
+    }
+    Ok(escape_html_characters(&date.format_with_items(items.into_iter()).to_string()))
+}
+
+// Split an alternate representation from the page's metadata ("<path>" or "<title>=<path>") into
+// its path, media type and title. The media type, and the title if none is given, are based on
+// the file extension.
+fn parse_alternate(alternate: &str) -> (&str, &'static str, &str) {
+    let (title, path) = match alternate.split_once('=') {
+        Some((title, path)) => (Some(title.trim()), path.trim()),
+        None => (None, alternate),
+    };
+    let extension = path.rsplit_once('.').map_or(String::new(), |(_, extension)| extension.to_lowercase());
+    let (mime_type, default_title) = match extension.as_str() {
+        "json" => ("application/json", "JSON"),
+        "txt" => ("text/plain", "Plain text"),
+        "md" => ("text/markdown", "Markdown"),
+        "pdf" => ("application/pdf", "PDF"),
+        "xml" => ("application/xml", "XML"),
+        "rss" => ("application/rss+xml", "RSS feed"),
+        "atom" => ("application/atom+xml", "Atom feed"),
+        "epub" => ("application/epub+zip", "EPUB"),
+        "html" | "htm" => ("text/html", "HTML"),
+        _ => ("application/octet-stream", "Download"),
+    };
+    (path, mime_type, title.unwrap_or(default_title))
+}
+
+// Set the lang attribute of a document's <html> element, replacing any existing value. The dir
+// attribute is set to "rtl" for right-to-left languages, and an existing dir attribute is set
+// to "ltr" for other languages, e.g. when an English page uses the layout of an Arabic site.
+fn set_html_language(html: &str, lang: &str) -> String {
+    let primary = lang.split(['-', '_']).next().unwrap_or_default().to_lowercase();
+    let rtl = RTL_LANGUAGES.contains(&primary.as_str());
+
+    RE_HTML_START
+        .replace(html, |caps: &regex::Captures| {
+            let attrs = RE_LANG_ATTRIBUTE.replace_all(&caps["attrs"], "");
+            let had_dir = RE_DIR_ATTRIBUTE.is_match(&attrs);
+            let attrs = RE_DIR_ATTRIBUTE.replace_all(&attrs, "");
+            let lang = escape_html_characters(lang);
+            match (rtl, had_dir) {
+                (true, _) => format!("<html lang=\"{}\" dir=\"rtl\"{}>", lang, attrs),
+                (false, true) => format!("<html lang=\"{}\" dir=\"ltr\"{}>", lang, attrs),
+                (false, false) => format!("<html lang=\"{}\"{}>", lang, attrs),
+            }
+        })
+        .to_string()
+}
+
+// The text of a page's summary, or of the first paragraph of its contents if it has no summary,
+// without tags, template blocks and footnote markers. The text is shortened to the given number
+// of words, ending with an ellipsis if it was shortened.
+fn automatic_excerpt(summary: Option<&str>, contents: &str, words: usize) -> String {
+    let html = match summary {
+        Some(summary) => summary,
+        None => {
+            let Some(start) = RE_PARAGRAPH_START.find(contents).map(|paragraph| paragraph.start()) else {
+                return String::new();
+            };
+            let end = contents[start..].to_ascii_lowercase().find("</p").map_or(contents.len(), |end| start + end);
+            &contents[start..end]
+        }
+    };
+
+    // Keep the text of { raw } blocks, as it is written to the page as it is
+    let text = template::parse(html)
+        .and_then(|nodes| template::render(&nodes, |block: &Block| match block.name.as_str() {
+            "raw" => template::render(&block.body, |_| Ok(None)).map(Some),
+            _ => Ok(Some(String::new())),
+        }))
+        .unwrap_or_default();
+    let text = RE_FOOTNOTE_REF.replace_all(&RE_TAG.replace_all(&text, " "), "").to_string();
+
+    let all_words: Vec<&str> = text.split_whitespace().collect();
+    if all_words.len() > words {
+        format!("{}\u{2026}", all_words[..words].join(" "))
+    } else {
+        all_words.join(" ")
+    }
+}
+
+// Parse a range of heading levels, e.g. "2-3", or a single level, e.g. "2".
+fn parse_levels(levels: &str) -> Option<(usize, usize)> {
+    let (first, last) = levels.split_once('-').unwrap_or((levels, levels));
+    let (first, last) = (first.trim().parse().ok()?, last.trim().parse().ok()?);
+    (1 <= first && first <= last && last <= 6).then_some((first, last))
+}
+
+// Parse a date in page metadata, in the first of the given formats that it matches, or in RFC
+// 3339 format with a time, e.g. "2024-01-02T09:30:00+01:00", whose date is taken as written.
+fn parse_date(value: &str, formats: &[&str]) -> Option<NaiveDate> {
+    formats.iter()
+        .find_map(|format| NaiveDate::parse_from_str(value, format).ok())
+        .or_else(|| DateTime::parse_from_rfc3339(value).ok().map(|datetime| datetime.date_naive()))
+}
+
+// Parse an alias or output path in page metadata into the path of a page relative to the web root,
+// e.g. "/2019/old.html", or "/old/index.html" for "old/". Returns None for paths that don't end
+// in ".html" or "/", or that go up from the web root.
+fn parse_alias(alias: &str) -> Option<String> {
+    let mut path = format!("/{}", alias.trim_start_matches('/'));
+    if path.ends_with('/') {
+        path.push_str("index.html");
+    }
+    let valid = path.ends_with(".html") && !path.contains('\\') && !path.split('/').any(|component| component == "..");
+    valid.then_some(path)
+}
+
+// Whether a template gives the same markup on every page of a build: its only blocks are
+// placeholders of build-wide values, { env } blocks, and includes of such templates.
+fn same_on_every_page(nodes: &[Node], page: &Page, site: &Site, depth: usize) -> bool {
+    nodes.iter().all(|node| match node {
+        Node::Text(_) => true,
+        Node::Block(block) => match (block.name.as_str(), block.values().as_slice()) {
+            ("include", [path]) => depth < MAX_NESTING_DEPTH
+                && page.parse_include(path, site).is_ok_and(|nodes| same_on_every_page(&nodes, page, site, depth + 1)),
+            ("env", [_]) => true,
+            (name, []) => block.args.is_empty()
+                && (["current_year", "site.page_count", "site.tag_count"].contains(&name) || name.starts_with("var.")),
+            _ => false,
+        },
+    })
+}
+
+// Escape text for use in HTML, both in the text of elements and in quoted attribute values.
+// Ampersands that start a character reference, e.g. "&amp;" or "&#8212;", are kept, so metadata
+// can contain them, and escaping text twice gives the same result as escaping it once.
+pub fn escape_html_characters(input: &str) -> String {
+    let mut escaped = String::with_capacity(input.len());
+    for (i, c) in input.char_indices() {
+        match c {
+            '&' if RE_ENTITY.find(&input[i..]).is_some_and(|entity| entity.start() == 0) => escaped.push('&'),
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Text of a custom variable value. Lists and maps have no text of their own, and must be
+// iterated with { each } instead.
+fn value_text(value: &toml::Value) -> String {
+    match value {
+        toml::Value::String(text) => text.clone(),
+        toml::Value::Array(_) | toml::Value::Table(_) => String::new(),
+        other => other.to_string(),
+    }
+}
+
+// Turn a map entry into an item with "key" and "value" fields, for { item.key } and { item.value }
+fn map_entry_item((key, value): (&String, &toml::Value)) -> toml::Value {
+    let mut item = toml::Table::new();
+    item.insert(String::from("key"), toml::Value::String(key.clone()));
+    item.insert(String::from("value"), value.clone());
+    toml::Value::Table(item)
+}
+
+// Items of a custom variable, for { each } blocks and { for } loops: the items of a list, the
+// entries of a map, or the value itself
+fn list_items(value: Option<&toml::Value>) -> Vec<toml::Value> {
+    match value {
+        Some(toml::Value::Array(items)) => items.clone(),
+        Some(toml::Value::Table(entries)) => entries.iter().map(map_entry_item).collect(),
+        Some(value) => vec![value.clone()],
+        None => Vec::new(),
+    }
+}
+
+// The paths of a { group ["<name>"] "<path>" [else "<path>"] } block: the group name, if any, the
+// file included for pages in the group, and the file included for other pages, if any
+pub fn group_nav_args<'v>(values: &[&'v str]) -> Option<(Option<&'v str>, &'v str, Option<&'v str>)> {
+    match *values {
+        [path] => Some((None, path, None)),
+        [path, "else", else_path] => Some((None, path, Some(else_path))),
+        [name, path] => Some((Some(name), path, None)),
+        [name, path, "else", else_path] => Some((Some(name), path, Some(else_path))),
+        _ => None,
+    }
+}
+
+// Load the stylesheets linked from a page without blocking its first paint: they're preloaded,
+// and applied once they've loaded. Browsers without JavaScript load them as before.
+fn defer_stylesheets(html: &str) -> String {
+    RE_STYLESHEET_LINK.replace_all(html, |caps: &regex::Captures| {
+        let preload = RE_REL_STYLESHEET.replace(&caps[0], "rel=\"preload\" as=\"style\" onload=\"this.onload=null;this.rel='stylesheet'\"");
+        format!("{}<noscript>{}</noscript>", preload, &caps[0])
+    }).to_string()
+}
+
+// The paths of the files matching the glob pattern of an { include_all "<pattern>" } block,
+// relative to the web root and sorted, e.g. "/faq/01-install.html"
+pub fn include_paths(root: &Path, pattern: &str) -> Result<Vec<String>> {
+    let full_pattern = format!("{}/{}", glob::Pattern::escape(&root.to_string_lossy()), pattern.trim_start_matches('/'));
+    let mut paths: Vec<String> = glob::glob(&full_pattern)
+        .with_context(|| format!("Invalid include pattern '{}'", pattern))?
+        .filter_map(|path| path.ok())
+        .filter(|path| path.is_file())
+        .map(|path| {
+            let relative = path.strip_prefix(root).unwrap_or(&path);
+            let components: Vec<_> = relative.components().map(|component| component.as_os_str().to_string_lossy()).collect();
+            format!("/{}", components.join("/"))
+        })
+        .collect();
+    paths.sort();
+    Ok(paths)
+}
+
+// Set text or a block aside until the page has been generated, giving the placeholder character
+// that stands in for it
+fn set_aside<T>(items: &mut Vec<T>, base: u32, item: T) -> Result<String> {
+    let placeholder = u32::try_from(items.len()).ok()
+        .filter(|&index| index < PLACEHOLDER_COUNT)
+        .and_then(|index| char::from_u32(base + index))
+        .context("Too many { raw }, { math }, { toc } or { toc_sidebar } blocks")?;
+    items.push(item);
+    Ok(placeholder.to_string())
+}
+
+// Replace the placeholder characters of the items set aside with the text given by `replace`.
+// Placeholders for which `replace` gives None are left in place.
+fn replace_placeholders<T>(text: &str, base: u32, items: &[T], mut replace: impl FnMut(&T) -> Result<Option<String>>) -> Result<String> {
+    if items.is_empty() {
+        return Ok(text.to_string());
+    }
+
+    let mut result = String::with_capacity(text.len());
+    for c in text.chars() {
+        let item = (c as u32).checked_sub(base).and_then(|index| items.get(index as usize));
+        match item.map(&mut replace).transpose()?.flatten() {
+            Some(replacement) => result.push_str(&replacement),
+            None => result.push(c),
+        }
+    }
+    Ok(result)
+}
+
+// Apply the filters of a placeholder to a value, and escape the result for HTML if `escape` is
+// set, unless the placeholder has a `raw` filter, e.g. { meta.note | raw } for metadata with markup
+pub fn filtered(value: &str, filters: &[Filter], escape: bool) -> Result<String> {
+    let value = filters::apply(value, filters)?;
+    let escape = escape && !filters.iter().any(|filter| filter.name == "raw");
+    Ok(if escape { escape_html_characters(&value) } else { value })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::path::Path;
+    use crate::sitegen::testing::TempSite;
+
+    // Test that reserved characters '<' and '>' in the title and author metadata are escaped correctly.
+    #[test]
+    fn test_title_author_metadata_escaped() {
+        let template = TemplateSource::Memory(String::from(
+            "--\ntitle: <>\nauthor: ><\n--\n\
+            <!DOCTYPE html><html><head><title>{title}</title></head><body>{author}</body></html>"
+        ));
+
+        let escaped_contents = String::from(
+            "<!DOCTYPE html><html><head><title>&lt;&gt;</title></head><body>&gt;&lt;</body></html>"
+        );
+
+        let mut page = Page::new(Path::new(""), &PathBuf::new(), &template, "", &Rc::default()).unwrap();
+
+        page.process_metadata().unwrap();
+        page.generate(&GroupContext::default(), &Site::default()).unwrap();
+
+        assert_eq!(page.contents, escaped_contents);
+    }
+
+    // Test that { root } expands to the relative path to the web root for pages at different depths.
+    #[test]
+    fn test_root_relative_to_page_depth() {
+        let template = TemplateSource::Memory(String::from("{ root }"));
+
+        for (template_path, expected) in [("/site/index.sgpage", "."), ("/site/a/b/page.sgpage", "../..")] {
+            let mut page = Page::new(Path::new("/site"), &PathBuf::from(template_path), &template, "", &Rc::default()).unwrap();
+            page.process_metadata().unwrap();
+            page.generate(&GroupContext::default(), &Site::default()).unwrap();
+            assert_eq!(page.contents, expected);
+        }
+    }
+
+    // Test that { each } iterates over lists, maps and lists of maps in the [vars] section.
+    #[test]
+    fn test_each_vars() {
+        let template = TemplateSource::Memory(String::from(
+            "--\ntitle: Gallery\n[vars]\nheading = \"<Photos>\"\ngallery = [\"a.jpg\", \"b.jpg\"]\n\
+            links = { home = \"/\" }\n[[people]]\nname = \"Ann\"\n--\n\
+            { vars.heading }\n{ each vars.gallery sep=\",\" '{ item }' }\n\
+            { each vars.links '{ item.key }={ item.value }' }\n{ each vars.people '{ item.name }' }\n\
+            { each vars.missing empty='none' '{ item }' }"
+        ));
+
+        let mut page = Page::new(Path::new(""), &PathBuf::new(), &template, "", &Rc::default()).unwrap();
+        page.process_metadata().unwrap();
+        page.generate(&GroupContext::default(), &Site::default()).unwrap();
+
+        assert_eq!(page.contents, "&lt;Photos&gt;\na.jpg,b.jpg\nhome=/\nAnn\nnone");
+    }
+
+    // Test that root-relative links are written below the base path, without prefixing links twice.
+    #[test]
+    fn test_links_below_base_path() {
+        let template = TemplateSource::Memory(String::from(
+            "<a href=\"/a/b.html\"></a><a href=\"/myproject/c.html\"></a><a href=\"d.html\"></a>{ root }"
+        ));
+        let config = Rc::new(Config { base_path: Some(String::from("myproject/")), ..Config::default() });
+
+        let mut page = Page::new(Path::new("/site"), &PathBuf::from("/site/a/page.sgpage"), &template, "", &config).unwrap();
+        page.process_metadata().unwrap();
+        page.generate(&GroupContext::default(), &Site::default()).unwrap();
+
+        assert_eq!(page.contents, "<a href=\"/myproject/a/b.html\"></a><a href=\"/myproject/c.html\"></a><a href=\"d.html\"></a>/myproject");
+    }
+
+    // Test that links to redirected paths are pointed at the pages they redirect to, keeping their
+    // query and fragment, and that other links are left as they are.
+    #[test]
+    fn test_redirected_links() {
+        let template = TemplateSource::Memory(String::from(
+            "<a href=\"../old-post.html#intro\">A</a> <a href=\"/2019/old/index.html?page=2\">B</a> <a href=\"/c.html\">C</a>"
+        ));
+        let site = Site {
+            redirects: BTreeMap::from([
+                (String::from("/old-post.html"), String::from("/posts/new.html")),
+                (String::from("/2019/old/index.html"), String::from("/posts/new.html")),
+            ]),
+            ..Site::default()
+        };
+
+        let mut page = Page::new(Path::new("/site"), &PathBuf::from("/site/a/page.sgpage"), &template, "", &Rc::default()).unwrap();
+        page.process_metadata().unwrap();
+        page.generate(&GroupContext::default(), &site).unwrap();
+
+        assert_eq!(page.contents, "<a href=\"../posts/new.html#intro\">A</a> <a href=\"../posts/new.html?page=2\">B</a> <a href=\"../c.html\">C</a>");
+    }
+
+    // Test that the contents of { raw } blocks are left untouched, including template syntax.
+    #[test]
+    fn test_raw_blocks_untouched() {
+        let template = TemplateSource::Memory(String::from(
+            "--\ntitle: Templates\n--\n<h1>{ title }</h1>{ raw }<code>{ title } { include \"/x.sginc\" }</code>{ endraw }"
+        ));
+
+        let mut page = Page::new(Path::new(""), &PathBuf::new(), &template, "", &Rc::default()).unwrap();
+        page.process_metadata().unwrap();
+        page.generate(&GroupContext::default(), &Site::default()).unwrap();
+
+        assert_eq!(page.contents, "<h1>Templates</h1><code>{ title } { include \"/x.sginc\" }</code>");
+    }
+
+    // Test that the content above the excerpt separator becomes the summary, and the separator is removed.
+    #[test]
+    fn test_summary_above_excerpt_separator() {
+        let template = TemplateSource::Memory(String::from(
+            "--\ntitle: Coffee\n--\n<h1>{ title }</h1>\n<p>Intro</p>\n<!-- more -->\n<p>Rest</p>\n{ summary }"
+        ));
+
+        let mut page = Page::new(Path::new(""), &PathBuf::new(), &template, "", &Rc::default()).unwrap();
+        page.process_metadata().unwrap();
+        page.generate(&GroupContext::default(), &Site::default()).unwrap();
+
+        assert_eq!(page.metadata.summary.as_deref(), Some("<p>Intro</p>"));
+        assert_eq!(page.contents, "<h1>Coffee</h1>\n<p>Intro</p>\n\n<p>Rest</p>\n<p>Intro</p>");
+    }
+
+    // Test that metadata is escaped wherever it is inserted, keeping character references, unless
+    // the placeholder has a `raw` filter.
+    #[test]
+    fn test_metadata_escaping() {
+        let template = TemplateSource::Memory(String::from(
+            "--\ntitle: Fish & \"Chips\" &mdash; <Leeds>\nnote: <em>New</em>\n--\n\
+            { title }|{ meta.note }|{ meta.note | raw }|{ prev.title }|{ tags '<a title=\"{ tag.page.title }\">{ tag.page.title | raw }</a>' }"
+        ));
+        let prev = Metadata { title: String::from("Salt & Vinegar"), ..Metadata::new("/salt.html") };
+        let tags = BTreeMap::from([(String::from("R&D"), TagPage::default())]);
+
+        let mut page = Page::new(Path::new(""), &PathBuf::new(), &template, "", &Rc::default()).unwrap();
+        page.process_metadata().unwrap();
+        page.generate(&GroupContext { prev: Some(prev), ..GroupContext::default() }, &Site { tags, ..Site::default() }).unwrap();
+
+        assert_eq!(page.contents, "Fish &amp; &quot;Chips&quot; &mdash; &lt;Leeds&gt;|&lt;em&gt;New&lt;/em&gt;|<em>New</em>|\
+            Salt &amp; Vinegar|<a title=\"R&amp;D\">R&D</a>");
+    }
+
+    // Test that tag clouds are ordered and limited as given, and that an unknown order or invalid
+    // limit is an error, as for { pages } blocks.
+    #[test]
+    fn test_tag_cloud_order() {
+        let tag = |count: usize| TagPage { meta: vec![Metadata::new("/a.html"); count], ..TagPage::default() };
+        let tags = BTreeMap::from([(String::from("Asia"), tag(1)), (String::from("Coffee"), tag(3)), (String::from("Food"), tag(2))]);
+        let generate = |source: &str| {
+            let template = TemplateSource::Memory(source.to_string());
+            let mut page = Page::new(Path::new(""), &PathBuf::new(), &template, "", &Rc::default()).unwrap();
+            page.process_metadata().unwrap();
+            page.generate(&GroupContext::default(), &Site { tags: tags.clone(), ..Site::default() }).map(|_| page.contents)
+        };
+
+        assert_eq!(generate("{ tags sep=',' '{ tag.page.title }' }").unwrap(), "Asia,Coffee,Food");
+        assert_eq!(generate("{ tags order=count limit=2 sep=',' '{ tag.page.title }' }").unwrap(), "Coffee,Food");
+        assert_eq!(generate("{ tags order=popular '{ tag.page.title }' }").unwrap_err().to_string(),
+            "Invalid value 'popular' for 'order' in { tags } block, expected name, count or recent");
+        assert!(generate("{ tags limit=ten '{ tag.page.title }' }").is_err());
+    }
+
+    // Test that placeholder filters are applied in order before escaping, and unknown filters fail.
+    #[test]
+    fn test_placeholder_filters() {
+        let template = TemplateSource::Memory(String::from(
+            "--\ntitle: Fish <and> Chips in Leeds\nsubtitle: A Review\n--\n\
+            { title | upper }|{ title | truncate 10 }|{ title|slugify }|{ meta.subtitle | lower | truncate 5 }|\
+            { author | default \"Anonymous <cook>\" | upper }|{ meta.subtitle | default \"None\" }|{ meta.rating | default \"\" }"
+        ));
+
+        let mut page = Page::new(Path::new(""), &PathBuf::new(), &template, "", &Rc::default()).unwrap();
+        page.process_metadata().unwrap();
+        page.generate(&GroupContext::default(), &Site::default()).unwrap();
+
+        assert_eq!(page.contents, "FISH &lt;AND&gt; CHIPS IN LEEDS|Fish &lt;and\u{2026}|fish-and-chips-in-leeds|a re\u{2026}|\
+            ANONYMOUS &lt;COOK&gt;|A Review|");
+
+        let template = TemplateSource::Memory(String::from("{ title | shout }"));
+        let mut page = Page::new(Path::new(""), &PathBuf::new(), &template, "", &Rc::default()).unwrap();
+        page.process_metadata().unwrap();
+        assert!(page.generate(&GroupContext::default(), &Site::default()).is_err());
+    }
+
+    // Test that the lang attribute is set or replaced, with dir="rtl" for right-to-left languages.
+    #[test]
+    fn test_html_language() {
+        assert_eq!(set_html_language("<html><body></body></html>", "en-GB"), "<html lang=\"en-GB\"><body></body></html>");
+        assert_eq!(set_html_language("<HTML class=\"x\" lang=en>", "ar-EG"), "<html lang=\"ar-EG\" dir=\"rtl\" class=\"x\">");
+        assert_eq!(set_html_language("<html dir='rtl' lang=\"fa\">", "en"), "<html lang=\"en\" dir=\"ltr\">");
+    }
+
+    // Test that { for } loops repeat their body for each item of a metadata list or [vars] list.
+    #[test]
+    fn test_for_loops() {
+        let template = TemplateSource::Memory(String::from(
+            "--\ntitle: Links\nlinks: a, <b>, c\n[vars]\npeople = [{ name = \"Ann\" }, { name = \"Bo\" }]\n--\n\
+            <ul>{ for link in meta.links }<li>{ link | upper }</li>{ endfor }</ul>\n\
+            { for person in vars.people }{ person.name } { endfor }{ for x in meta.missing }x{ endfor }"
+        ));
+
+        let mut page = Page::new(Path::new(""), &PathBuf::new(), &template, "", &Rc::default()).unwrap();
+        page.process_metadata().unwrap();
+        page.generate(&GroupContext::default(), &Site::default()).unwrap();
+
+        assert_eq!(page.contents, "<ul><li>A</li><li>&lt;B&gt;</li><li>C</li></ul>\nAnn Bo ");
+    }
+
+    // Test that { excerpt } blocks embed the summary or a section of another page, with relative
+    // links rebased, and that unknown sections fail.
+    #[test]
+    fn test_excerpts() {
+        let root = Path::new("/site");
+        let template = TemplateSource::Memory(String::from(
+            "--\ntitle: Italy\n--\n<p>Rome <img src=\"rome.jpg\"></p>\n<!-- more -->\n\
+            { section \"route\" }<p>By train to <a href=\"../milan.html#station\">Milan</a></p>{ endsection }"
+        ));
+        let mut italy = Page::new(root, &root.join("posts/italy.sgpage"), &template, "", &Rc::default()).unwrap();
+        italy.process_metadata().unwrap();
+        let site = Site { pages: vec![italy.get_metadata()], ..Site::default() };
+
+        italy.generate(&GroupContext::default(), &site).unwrap();
+        assert!(italy.contents.ends_with("<section id=\"route\"><p>By train to <a href=\"../milan.html#station\">Milan</a></p></section>"));
+
+        let template = TemplateSource::Memory(String::from(
+            "--\ntitle: Home\n--\n{ excerpt \"posts/italy.sgpage\" }\n{ excerpt \"/posts/italy.sgpage#route\" }"
+        ));
+        let mut home = Page::new(root, &root.join("index.sgpage"), &template, "", &Rc::default()).unwrap();
+        home.process_metadata().unwrap();
+        assert_eq!(home.referenced_pages(&site), ["/posts/italy.html", "/posts/italy.html"]);
+        home.generate(&GroupContext::default(), &site).unwrap();
+
+        assert_eq!(home.contents, "<p>Rome <img src=\"posts/rome.jpg\"></p>\n<p>By train to <a href=\"milan.html#station\">Milan</a></p>");
+
+        let template = TemplateSource::Memory(String::from("{ excerpt \"posts/italy.sgpage#tickets\" }"));
+        let mut page = Page::new(root, &root.join("index.sgpage"), &template, "", &Rc::default()).unwrap();
+        page.process_metadata().unwrap();
+        assert!(page.generate(&GroupContext::default(), &site).is_err());
+    }
+
+    // Test that the excerpt is taken from the metadata, the summary, or the first paragraph.
+    #[test]
+    fn test_excerpt_fallbacks() {
+        let excerpt = |template: &str, words: Option<usize>| {
+            let config = Rc::new(Config { excerpt_words: words, ..Config::default() });
+            let mut page = Page::new(Path::new(""), &PathBuf::new(), &TemplateSource::Memory(template.to_string()), "", &config).unwrap();
+            page.process_metadata().unwrap();
+            page.generate(&GroupContext::default(), &Site::default()).unwrap();
+            (page.metadata.excerpt, page.contents)
+        };
+
+        assert_eq!(excerpt("--\nexcerpt: Fish & <chips>\n--\n<p>Intro</p>{ excerpt }", None).1, "<p>Intro</p>Fish &amp; &lt;chips&gt;");
+        assert_eq!(
+            excerpt("<h1>{ title }</h1>\n<p class=\"lead\">Italian <em>coffee</em>[^1] culture\nis { raw }{ vibrant }{ endraw }.</p><p>More</p>", None).0,
+            "Italian coffee culture is { vibrant }."
+        );
+        assert_eq!(excerpt("<p>Intro</p>\n<p>One two three four</p>\n<!-- more -->\n<p>Rest</p>", Some(4)).0, "Intro One two three\u{2026}");
+        assert_eq!(excerpt("<h1>No paragraphs</h1>", None).0, "");
+    }
+
+    // Test that { link } blocks link to pages and their sections, and fail for missing sections.
+    #[test]
+    fn test_section_links() {
+        let root = Path::new("/site");
+        let page = |path: &str, template: &str, site: &Site| {
+            let template = TemplateSource::Memory(template.to_string());
+            let mut page = Page::new(root, &root.join(path), &template, "", &Rc::default()).unwrap();
+            page.process_metadata().unwrap();
+            page.generate(&GroupContext::default(), site).map(|_| page.contents)
+        };
+        let mut setup = Page::new(root, &root.join("docs/setup.sgpage"), &TemplateSource::Memory(
+            String::from("{ section \"install\" }<h2>Install</h2>{ endsection }")
+        ), "", &Rc::default()).unwrap();
+        setup.process_metadata().unwrap();
+        let site = Site { pages: vec![setup.get_metadata()], ..Site::default() };
+
+        assert_eq!(page("docs/setup.sgpage", "{ section \"install\" }<h2>Install</h2>{ endsection }", &site).unwrap(),
+            "<section id=\"install\"><h2>Install</h2></section>");
+        assert_eq!(page("index.sgpage", "<a href=\"{ link \"docs/setup.sgpage#install\" }\">Install</a>", &site).unwrap(),
+            "<a href=\"docs/setup.html#install\">Install</a>");
+        assert_eq!(page("docs/faq.sgpage", "<a href=\"{ link \"setup.sgpage\" }\">Setup</a>", &site).unwrap(),
+            "<a href=\"setup.html\">Setup</a>");
+
+        assert!(page("index.sgpage", "{ link \"docs/setup.sgpage#usage\" }", &site).is_err());
+        assert!(page("index.sgpage", "{ link \"docs/missing.sgpage\" }", &site).is_err());
+        assert!(page("index.sgpage", "{ section \"a\" }{ endsection }{ section \"a\" }{ endsection }", &site).is_err());
+    }
+
+    // Test that removed pages are marked as not to be indexed.
+    #[test]
+    fn test_removed_page_noindex() {
+        let template = TemplateSource::Memory(String::from(
+            "--\ntitle: Old News\nremoved: true\n--\n<html><head><title>{ title }</title></head><body>Gone</body></html>"
+        ));
+
+        let mut page = Page::new(Path::new(""), &PathBuf::new(), &template, "", &Rc::default()).unwrap();
+        page.process_metadata().unwrap();
+        page.generate(&GroupContext::default(), &Site::default()).unwrap();
+
+        assert_eq!(page.contents, "<html><head><title>Old News</title><meta name=\"robots\" content=\"noindex\">\n</head><body>Gone</body></html>");
+    }
+
+    // Test that a slug sets the file name of the generated page, and must be a plain file name,
+    // and that other pages still refer to the page by its template.
+    #[test]
+    fn test_slug() {
+        let page = |slug: &str| {
+            let template = TemplateSource::Memory(format!("--\ntitle: Hello\nslug: {}\n--\n<p>Hello</p>", slug));
+            let mut page = Page::new(Path::new("/site"), Path::new("/site/posts/2024-05-01-hello.sgpage"), &template, "", &Rc::default()).unwrap();
+            page.process_metadata().map(|_| page)
+        };
+        let hello = page("hello").unwrap();
+        assert_eq!(hello.get_metadata().path, "/posts/hello.html");
+        assert_eq!(hello.output_path, PathBuf::from("/site/posts/hello.html"));
+        assert!(page("../hello").is_err());
+        assert!(page("").is_err());
+
+        let site = Site { pages: vec![hello.get_metadata()], ..Site::default() };
+        let template = TemplateSource::Memory(String::from("{ link \"posts/2024-05-01-hello.sgpage\" }"));
+        let mut index = Page::new(Path::new("/site"), Path::new("/site/index.sgpage"), &template, "", &Rc::default()).unwrap();
+        index.process_metadata().unwrap();
+        index.generate(&GroupContext::default(), &site).unwrap();
+        assert_eq!(index.contents, "posts/hello.html");
+    }
+
+    // Test that an output path places the generated page anywhere in the web root, overriding
+    // a slug, and must stay in the web root.
+    #[test]
+    fn test_output_path() {
+        let page = |metadata: &str| {
+            let template = TemplateSource::Memory(format!("--\ntitle: Downloads\n{}\n--\n<a href=\"/index.html\">Home</a>", metadata));
+            let mut page = Page::new(Path::new("/site"), Path::new("/site/content/downloads.sgpage"), &template, "", &Rc::default()).unwrap();
+            page.process_metadata().map(|_| page)
+        };
+        let mut downloads = page("output: /downloads/").unwrap();
+        assert_eq!(downloads.get_metadata().path, "/downloads/index.html");
+        assert_eq!(downloads.output_path, PathBuf::from("/site/downloads/index.html"));
+        downloads.generate(&GroupContext::default(), &Site::default()).unwrap();
+        assert_eq!(downloads.contents, "<a href=\"../index.html\">Home</a>");
+
+        assert_eq!(page("slug: files\noutput: get/files.html").unwrap().get_metadata().path, "/get/files.html");
+        assert!(matches!(page("output: /../downloads.html"), Err(PageError::InvalidOutput { output, .. }) if output == "/../downloads.html"));
+        assert!(page("output: /downloads.php").is_err());
+    }
+
+    // Test that metadata dates are accepted in the default formats and RFC 3339, or in the
+    // configured formats instead.
+    #[test]
+    fn test_date_formats() {
+        let date = |value: &str, config: &Rc<Config>| {
+            let template = TemplateSource::Memory(format!("--\ndate: {}\n--\n", value));
+            let mut page = Page::new(Path::new(""), &PathBuf::new(), &template, "", config).unwrap();
+            page.process_metadata().map(|_| page.metadata.date.unwrap().to_string())
+        };
+        let defaults = Rc::default();
+        assert_eq!(date("2024-01-02", &defaults).unwrap(), "2024-01-02");
+        assert_eq!(date("02 Jan 2024", &defaults).unwrap(), "2024-01-02");
+        assert_eq!(date("2 January 2024", &defaults).unwrap(), "2024-01-02");
+        assert_eq!(date("2024-01-02T23:30:00-05:00", &defaults).unwrap(), "2024-01-02");
+        assert!(date("01/02/2024", &defaults).is_err());
+
+        let config = Rc::new(Config { metadata_date_formats: vec![String::from("%d.%m.%Y")], ..Config::default() });
+        assert_eq!(date("02.01.2024", &config).unwrap(), "2024-01-02");
+        assert_eq!(date("2024-01-02T09:30:00Z", &config).unwrap(), "2024-01-02");
+        assert!(date("2024-01-02", &config).is_err());
+    }
+
+    // Test that invalid metadata and paths outside the web root give errors naming the template,
+    // instead of panicking, and that an unclosed metadata block doesn't hang.
+    #[test]
+    fn test_metadata_errors() {
+        let error = |metadata: &str| {
+            let template = TemplateSource::Memory(format!("--\ntitle: Hello\n{}", metadata));
+            let mut page = Page::new(Path::new("/site"), Path::new("/site/posts/hello.sgpage"), &template, "", &Rc::default()).unwrap();
+            page.process_metadata().unwrap_err()
+        };
+        assert!(matches!(error("date: 2024-13-01\n--\n"), PageError::InvalidDate { value, .. } if value == "2024-13-01"));
+        assert!(matches!(error("updated: later\n--\n"), PageError::InvalidDate { value, .. } if value == "later"));
+        assert!(matches!(error("draft: yes\n--\n"), PageError::InvalidFlag { key, .. } if key == "draft"));
+        assert!(matches!(error("weight: first\n--\n"), PageError::InvalidWeight { .. }));
+        assert!(matches!(error("[vars]\nx = \n--\n"), PageError::InvalidVars { .. }));
+        assert!(matches!(error("aliases: /old.php\n--\n"), PageError::InvalidAlias { alias, .. } if alias == "/old.php"));
+        assert!(matches!(error("aliases: ../up.html\n--\n"), PageError::InvalidAlias { .. }));
+        assert!(matches!(error("author: Ann\n"), PageError::UnclosedMetadata { .. }));
+        assert!(matches!(error("[vars]\nx = 1\n"), PageError::UnclosedMetadata { .. }));
+        assert_eq!(error("date: soon\n--\n").to_string(),
+            "Invalid date 'soon' in page template '/posts/hello.sgpage', expected a date in one of the formats %Y-%m-%d, %d %b %Y, %d %B %Y or RFC 3339");
+
+        let template = TemplateSource::Memory(String::new());
+        let outside = Page::new(Path::new("/site"), Path::new("/elsewhere/hello.sgpage"), &template, "", &Rc::default());
+        assert!(matches!(outside, Err(PageError::OutsideRoot { .. })));
+    }
+
+    // Test that with strict_metadata, keys that aren't built in, listed in custom_metadata or set
+    // in [site] are errors suggesting the closest allowed key, and that they're custom metadata
+    // otherwise.
+    #[test]
+    fn test_strict_metadata() {
+        let process = |metadata: &str, strict: bool| {
+            let config = Rc::new(Config {
+                strict_metadata: strict,
+                custom_metadata: vec![String::from("hero_image")],
+                site: toml::from_str("name = \"Travel Notes\"").unwrap(),
+                ..Config::default()
+            });
+            let template = TemplateSource::Memory(format!("--\ntitle: Hello\n{}\n--\n", metadata));
+            let mut page = Page::new(Path::new("/site"), Path::new("/site/posts/hello.sgpage"), &template, "", &config).unwrap();
+            page.process_metadata().map(|_| page.metadata.custom)
+        };
+        let error = |metadata: &str| process(metadata, true).unwrap_err().to_string();
+
+        assert_eq!(process("Tags: travel", false).unwrap().get("Tags").map(String::as_str), Some("travel"));
+        assert!(process("tags: travel\nhero_image: /a.jpg\nname: Notes\nauthor.url: /ann/", true).is_ok());
+        assert_eq!(error("Tags: travel"), "Unknown metadata key 'Tags' in page template '/posts/hello.sgpage' (did you mean 'tags'?)");
+        assert_eq!(error("hero_img: /a.jpg"), "Unknown metadata key 'hero_img' in page template '/posts/hello.sgpage' (did you mean 'hero_image'?)");
+        assert_eq!(error("mood: sunny"),
+            "Unknown metadata key 'mood' in page template '/posts/hello.sgpage', expected a built-in key or one listed in custom_metadata");
+    }
+
+    // Test that { env } blocks insert environment variables, and fail for variables that aren't set.
+    #[test]
+    fn test_environment_variables() {
+        std::env::set_var("SITEWINDER_TEST_DEPLOY_URL", "https://staging.example.com/<x>");
+        let template = TemplateSource::Memory(String::from("<a href=\"{ env \"SITEWINDER_TEST_DEPLOY_URL\" | lower }\">"));
+        let mut page = Page::new(Path::new(""), &PathBuf::new(), &template, "", &Rc::default()).unwrap();
+        page.process_metadata().unwrap();
+        page.generate(&GroupContext::default(), &Site::default()).unwrap();
+        assert_eq!(page.contents, "<a href=\"https://staging.example.com/&lt;x&gt;\">");
+
+        let template = TemplateSource::Memory(String::from("{ env \"SITEWINDER_TEST_UNSET\" }"));
+        let mut page = Page::new(Path::new(""), &PathBuf::new(), &template, "", &Rc::default()).unwrap();
+        page.process_metadata().unwrap();
+        assert!(page.generate(&GroupContext::default(), &Site::default()).is_err());
+    }
+
+    // Test that { debug } lists the page's placeholders in preview builds only.
+    #[test]
+    fn test_debug_table() {
+        let generate = |drafts: bool| {
+            let template = TemplateSource::Memory(String::from("--\ntitle: Fish & <Chips>\ncity: Leeds\n--\n{ debug }"));
+            let define = BTreeMap::from([(String::from("env"), String::from("staging"))]);
+            let config = Rc::new(Config { drafts, define, ..Config::default() });
+            let mut page = Page::new(Path::new(""), &PathBuf::new(), &template, "", &config).unwrap();
+            page.process_metadata().unwrap();
+            page.generate(&GroupContext::default(), &Site::default()).unwrap();
+            page.contents
+        };
+
+        let table = generate(true);
+        assert!(table.starts_with("<table class=\"sitewinder-debug\""));
+        assert!(table.contains(&format!("{{ title }}</th><td style=\"{}\">Fish &amp; &lt;Chips&gt;</td>", DEBUG_CELL_STYLE)));
+        assert!(table.contains(&format!("{{ meta.city }}</th><td style=\"{}\">Leeds</td>", DEBUG_CELL_STYLE)));
+        assert!(table.contains(&format!("{{ var.env }}</th><td style=\"{}\">staging</td>", DEBUG_CELL_STYLE)));
+        assert_eq!(generate(false), "");
+    }
+
+    // Test that build variables are inserted by { var.<key> } placeholders, and unknown ones are empty.
+    #[test]
+    fn test_build_variables() {
+        let template = TemplateSource::Memory(String::from("<a href=\"{ var.api_url }\">{ var.flavour | upper }</a>{ var.missing }"));
+        let define = BTreeMap::from([
+            (String::from("api_url"), String::from("https://api.example.com")),
+            (String::from("flavour"), String::from("staging")),
+        ]);
+        let config = Rc::new(Config { define, ..Config::default() });
+
+        let mut page = Page::new(Path::new(""), &PathBuf::new(), &template, "", &config).unwrap();
+        page.process_metadata().unwrap();
+        page.generate(&GroupContext::default(), &Site::default()).unwrap();
+
+        assert_eq!(page.contents, "<a href=\"https://api.example.com\">STAGING</a>");
+    }
+
+    // Test that { site.<key> } placeholders give the values of the [site] table, unless the page
+    // overrides them, and that { author } defaults to the site's author.
+    #[test]
+    fn test_site_variables() {
+        let site = toml::from_str("name = \"Fish & Chips\"\nauthor = \"Ann\"\nfounded = 1998\nmotto = \"Fresh\"").unwrap();
+        let config = Rc::new(Config { site, ..Config::default() });
+        let generate = |template: &str| {
+            let template = TemplateSource::Memory(template.to_string());
+            let mut page = Page::new(Path::new(""), &PathBuf::new(), &template, "", &config).unwrap();
+            page.process_metadata().unwrap();
+            page.generate(&GroupContext::default(), &Site::default()).map(|_| page.contents)
+        };
+
+        let template = "{ site.name }|{ site.founded }|{ site.motto }|{ site.author }|{ author }|{ site.page_count }|{ site.missing }";
+        assert_eq!(generate(&format!("<p>\n{}", template)).unwrap(), "<p>\nFish &amp; Chips|1998|Fresh|Ann|Ann|0|");
+        assert_eq!(generate(&format!("--\nauthor: Bo\nmotto: Crispy\n--\n{}", template)).unwrap(), "Fish &amp; Chips|1998|Crispy|Bo|Bo|0|");
+    }
+
+    // Test that { copyright_years } ranges from the configured start year, or the year of the
+    // oldest page, to the current year.
+    #[test]
+    fn test_copyright_years() {
+        let generate = |copyright_start: Option<i32>, dates: &[(i32, u32, u32)]| {
+            let config = Rc::new(Config { copyright_start, ..Config::default() });
+            let pages = dates.iter().map(|(year, month, day)| Metadata { date: NaiveDate::from_ymd_opt(*year, *month, *day), ..Metadata::new("/a.html") }).collect();
+            let site = Site { pages, ..Site::default() };
+            let template = TemplateSource::Memory(String::from("&copy; { copyright_years }"));
+            let mut page = Page::new(Path::new(""), &PathBuf::new(), &template, "2025", &config).unwrap();
+            page.process_metadata().unwrap();
+            page.generate(&GroupContext::default(), &site).map(|_| page.contents).unwrap()
+        };
+        assert_eq!(generate(Some(2018), &[(2020, 1, 1)]), "&copy; 2018\u{2013}2025");
+        assert_eq!(generate(None, &[(2021, 6, 1), (2019, 3, 1)]), "&copy; 2019\u{2013}2025");
+        assert_eq!(generate(None, &[(2025, 1, 1)]), "&copy; 2025");
+        assert_eq!(generate(None, &[]), "&copy; 2025");
+    }
+
+    // Test that pages refer to configured authors by id, or give an author's details as
+    // author.<field> keys, and that pages without an author get the site's.
+    #[test]
+    fn test_authors() {
+        let config: Config = toml::from_str(r#"
+            [site]
+            author = "ann"
+            [authors.ann]
+            name = "Ann Smith"
+            url = "https://ann.example.com/"
+            avatar = "/img/ann.jpg"
+            [authors.bo]
+            email = "bo@example.com"
+        "#).unwrap();
+        let config = Rc::new(config);
+        let generate = |metadata: &str| {
+            let template = TemplateSource::Memory(format!("--\n{}\n--\n{{ author }}|{{ author.url }}|{{ author.email }}|{{ author.avatar }}|{{ site.author }}", metadata));
+            let mut page = Page::new(Path::new(""), &PathBuf::from("post.sgpage"), &template, "", &config).unwrap();
+            page.process_metadata().unwrap();
+            page.generate(&GroupContext::default(), &Site::default()).map(|_| page.contents).unwrap()
+        };
+
+        assert_eq!(generate("title: Hello"), "Ann Smith|https://ann.example.com/||/img/ann.jpg|Ann Smith");
+        assert_eq!(generate("author: bo"), "bo||bo@example.com||bo");
+        assert_eq!(generate("author.url: https://cy.example.com\nauthor: Cy <3"), "Cy &lt;3|https://cy.example.com|||Cy &lt;3");
+        assert_eq!(generate("author: ann\nauthor.avatar: /img/ann-2024.jpg"), "Ann Smith|https://ann.example.com/||/img/ann-2024.jpg|Ann Smith");
+    }
+
+    // Test that the critical stylesheet is inlined with its references rebased and its text left
+    // alone, and that the page's stylesheets are deferred.
+    #[test]
+    fn test_critical_css() {
+        let root = TempSite::new("critical");
+        root.write("css/critical.css", "body { background: url(bg.png) }\np::before { content: \"--\" }\n");
+        let generate = |critical_css: Option<&str>| {
+            let config = Rc::new(Config { critical_css: critical_css.map(str::to_string), smart_typography: true, ..Config::default() });
+            let template = TemplateSource::Memory(String::from("<head><link rel=\"stylesheet\" href=\"/css/site.css\">\n</head><p>Hi -- there</p>"));
+            let mut page = Page::new(&root, &root.join("blog/post.sgpage"), &template, "", &config).unwrap();
+            page.process_metadata().unwrap();
+            page.generate(&GroupContext::default(), &Site::default()).map(|_| page.contents)
+        };
+        let inlined = generate(Some("css/critical.css"));
+        let missing = generate(Some("/css/missing.css"));
+        let plain = generate(None);
+
+        let inlined = inlined.unwrap();
+        assert!(inlined.starts_with("<head><link rel=\"preload\" as=\"style\" onload=\"this.onload=null;this.rel='stylesheet'\" href=\"../css/site.css\">\
+            <noscript><link rel=\"stylesheet\" href=\"../css/site.css\"></noscript>\n\
+            <style>\nbody { background: url(../css/bg.png) }\np::before { content: \"--\" }\n</style>\n</head>"), "{}", inlined);
+        assert!(!inlined.contains("Hi -- there"));
+        assert!(missing.is_err());
+        assert!(plain.unwrap().starts_with("<head><link rel=\"stylesheet\" href=\"../css/site.css\">\n</head>"));
+    }
+
+    // Test that include files outside the web root are rejected, unless allowed, and that
+    // { include_raw } inserts files as escaped text.
+    #[test]
+    fn test_includes_outside_root() {
+        let folder = TempSite::new("includes");
+        folder.write("site/header.sginc", "<header>");
+        folder.write("site/config.toml", "title = \"Fish & <chips>\" # &amp; { title }");
+        folder.write("secret.sginc", "secret");
+        let root = folder.join("site");
+
+        let generate = |template: &str, allow_outside_root: bool| {
+            let config = Rc::new(Config { allow_outside_root, ..Config::default() });
+            let template = TemplateSource::Memory(template.to_string());
+            let mut page = Page::new(&root, &root.join("index.sgpage"), &template, "", &config).unwrap();
+            page.process_metadata().unwrap();
+            page.generate(&GroupContext::default(), &Site::default()).map(|_| page.contents)
+        };
+        let inside = generate("{ include \"/header.sginc\" }", false);
+        let outside = generate("{ include \"/../secret.sginc\" }", false);
+        let allowed = generate("{ include \"../secret.sginc\" }", true);
+        let raw = generate("<pre>{ include_raw \"config.toml\" }</pre>", false);
+        let raw_outside = generate("{ include_raw \"/../secret.sginc\" }", false);
+
+        assert_eq!(inside.unwrap(), "<header>");
+        assert!(outside.unwrap_err().to_string().contains("outside the web root"));
+        assert_eq!(allowed.unwrap(), "secret");
+        assert_eq!(raw.unwrap(), "<pre>title = \"Fish &amp; &lt;chips&gt;\" # &amp;amp; { title }</pre>");
+        assert!(raw_outside.unwrap_err().to_string().contains("outside the web root"));
+    }
+
+    // Test that { include_all } includes the files matching a pattern in order of path, and that
+    // include files are parsed once per build.
+    #[test]
+    fn test_include_all() {
+        let root = TempSite::new("include-all");
+        for (path, contents) in [("faq/02-update.html", "<h2>Update</h2>"), ("faq/01-install.html", "<h2>Install { title }</h2>"), ("faq/notes.txt", "notes")] {
+            root.write(path, contents);
+        }
+
+        let config = Rc::new(Config::default());
+        let generate = |site: &Site| {
+            let template = TemplateSource::Memory(String::from("--\ntitle: FAQ\n--\n{ include_all \"/faq/*.html\" }{ include_all \"none/*.html\" }"));
+            let mut page = Page::new(&root, &root.join("faq.sgpage"), &template, "", &config).unwrap();
+            page.process_metadata().unwrap();
+            page.generate(&GroupContext::default(), site).map(|_| page.contents)
+        };
+        let site = Site::default();
+        let first = generate(&site);
+        root.write("faq/02-update.html", "<h2>Upgrade</h2>");
+        let cached = generate(&site);
+        let next_build = generate(&Site::default());
+
+        assert_eq!(first.unwrap(), "<h2>Install FAQ</h2><h2>Update</h2>");
+        assert_eq!(cached.unwrap(), "<h2>Install FAQ</h2><h2>Update</h2>");
+        assert_eq!(next_build.unwrap(), "<h2>Install FAQ</h2><h2>Upgrade</h2>");
+    }
+
+    // Test that include files that give the same markup on every page are rendered once per
+    // build, and that include files with page placeholders are rendered for each page.
+    #[test]
+    fn test_rendered_includes() {
+        let root = TempSite::new("rendered-includes");
+        root.write("footer.sginc", "<footer>{ current_year } { var.env | upper }{ include \"nav.sginc\" }</footer>");
+        root.write("nav.sginc", "<nav>{ site.page_count }</nav>");
+        root.write("header.sginc", "<h1>{ title }</h1>");
+
+        let config = Rc::new(Config { define: BTreeMap::from([(String::from("env"), String::from("prod"))]), ..Config::default() });
+        let site = Site::default();
+        let generate = |title: &str| {
+            let template = TemplateSource::Memory(format!("--\ntitle: {}\n--\n{{ include \"header.sginc\" }}{{ include \"footer.sginc\" }}", title));
+            let mut page = Page::new(&root, &root.join(format!("{}.sgpage", title)), &template, "2025", &config).unwrap();
+            page.process_metadata().unwrap();
+            page.generate(&GroupContext::default(), &site).map(|_| page.contents)
+        };
+        let (a, b) = (generate("A"), generate("B"));
+        let mut rendered: Vec<_> = site.rendered_includes.borrow().keys().map(|path| path.strip_prefix(&root).unwrap().to_path_buf()).collect();
+        rendered.sort();
+
+        assert_eq!(a.unwrap(), "<h1>A</h1><footer>2025 PROD<nav>0</nav></footer>");
+        assert_eq!(b.unwrap(), "<h1>B</h1><footer>2025 PROD<nav>0</nav></footer>");
+        assert_eq!(rendered, [PathBuf::from("footer.sginc"), PathBuf::from("nav.sginc")]);
+    }
+
+    // Test that pages select a configured layout by name with their template, that a layout path
+    // takes precedence, and that unknown names are errors.
+    #[test]
+    fn test_named_layouts() {
+        let root = TempSite::new("named-layouts");
+        root.write("layouts/article.sginc", "<article>{ content }</article>");
+        root.write("layouts/landing.sginc", "<main>{ content }</main>");
+
+        let config: Config = toml::from_str("[layouts]\narticle = \"/layouts/article.sginc\"\nlanding = \"/layouts/landing.sginc\"").unwrap();
+        let config = Rc::new(config);
+        let generate = |metadata: &str| {
+            let template = TemplateSource::Memory(format!("--\n{}\n--\n<p>Hi</p>", metadata));
+            let mut page = Page::new(&root, &root.join("index.sgpage"), &template, "", &config).unwrap();
+            page.process_metadata()?;
+            page.generate(&GroupContext::default(), &Site::default()).unwrap();
+            Ok::<_, PageError>(page.contents)
+        };
+        let article = generate("template: article");
+        let layout = generate("template: article\nlayout: /layouts/landing.sginc");
+        let unknown = generate("template: gallery");
+
+        assert_eq!(article.unwrap(), "<article><p>Hi</p></article>");
+        assert_eq!(layout.unwrap(), "<main><p>Hi</p></main>");
+        assert_eq!(unknown.unwrap_err().to_string(),
+            "Unknown template 'gallery' in page template '/index.sgpage', expected one of the layouts configured in [layouts]: 'article', 'landing'");
+    }
+
+    // Test that pages are written with the configured line endings.
+    #[test]
+    fn test_line_endings() {
+        let root = TempSite::new("line-endings");
+        let write = |line_endings: LineEndings| {
+            let config = Rc::new(Config { line_endings, ..Config::default() });
+            let template = TemplateSource::Memory(String::from("--\ntitle: Notes\n--\n<h1>{ title }</h1>\n<p>Text</p>\n"));
+            let mut page = Page::new(&root, &root.join("notes.sgpage"), &template, "", &config).unwrap();
+            page.process_metadata().unwrap();
+            page.generate(&GroupContext::default(), &Site::default()).unwrap();
+            page.write().unwrap();
+            root.read("notes.html")
+        };
+        let lf = write(LineEndings::Lf);
+        let crlf = write(LineEndings::Crlf);
+
+        assert_eq!(lf, "<h1>Notes</h1>\n<p>Text</p>\n");
+        assert_eq!(crlf, "<h1>Notes</h1>\r\n<p>Text</p>\r\n");
+    }
+
+    // Test that { pages } listings are filtered by group and tag, sorted and limited, and that
+    // { sitemap_html } lists pages by group unless told otherwise.
+    #[test]
+    fn test_page_listings() {
+        let post = |path: &str, title: &str, group: Option<&str>, date: &str, tags: &[&str]| Metadata {
+            title: title.to_string(),
+            group: group.map(str::to_string),
+            date: NaiveDate::parse_from_str(date, "%Y-%m-%d").ok(),
+            tags: Some(tags.iter().map(|tag| tag.to_string()).collect()),
+            ..Metadata::new(path)
+        };
+        let site = Site {
+            pages: vec![
+                post("/about.html", "About", None, "", &[]),
+                post("/blog/alps.html", "Alps", Some("blog"), "2024-03-01", &["Hiking"]),
+                post("/blog/bruges.html", "Bruges", Some("blog"), "2024-05-12", &["Cities"]),
+                post("/blog/cadiz.html", "Cadiz", Some("blog"), "2023-11-20", &["Cities", "Beaches"]),
+            ],
+            ..Site::default()
+        };
+
+        let generate = |template: &str| {
+            let template = TemplateSource::Memory(template.to_string());
+            let mut page = Page::new(Path::new(""), &PathBuf::from("index.sgpage"), &template, "", &Rc::default()).unwrap();
+            page.process_metadata().unwrap();
+            page.generate(&GroupContext::default(), &site).map(|_| page.contents)
+        };
+        assert_eq!(generate("{ pages group=\"blog\" sort=desc limit=2 sep=\", \" '{ page.title }' }").unwrap(), "Bruges, Alps");
+        assert_eq!(generate("{ pages tag=\"cities\" sort=asc '<a href=\"{ page.link }\">{ page.title }</a>' }").unwrap(),
+            "<a href=\"blog/cadiz.html\">Cadiz</a><a href=\"blog/bruges.html\">Bruges</a>");
+        assert_eq!(generate("{ pages sort=title limit=3 '{ page.title } ' }").unwrap(), "About Alps Bruges ");
+        assert!(generate("{ pages sort=newest '{ page.title }' }").is_err());
+
+        assert_eq!(generate("{ sitemap_html group=\"blog\" sort=title }").unwrap(),
+            "<h2>blog</h2>\n<ul>\n<li><a href=\"blog/alps.html\">Alps</a></li>\n<li><a href=\"blog/bruges.html\">Bruges</a></li>\n\
+            <li><a href=\"blog/cadiz.html\">Cadiz</a></li>\n</ul>\n");
+        assert_eq!(generate("{ sitemap_html by=folder before='<h3>{ heading }</h3>' after='' sep=\" \" '{ page.title }' }").unwrap(),
+            "<h3>/</h3>About<h3>/blog</h3>Alps Bruges Cadiz");
+    }
+
+    // Test that the description is inserted by { description } and added to the <head>, unless
+    // the template has a description tag of its own.
+    #[test]
+    fn test_description() {
+        let generate = |template: &str| {
+            let template = TemplateSource::Memory(format!("--\ntitle: Chips\ndescription: Fish & \"chips\"\n--\n{}", template));
+            let mut page = Page::new(Path::new(""), &PathBuf::from("chips.sgpage"), &template, "", &Rc::default()).unwrap();
+            page.process_metadata().unwrap();
+            page.generate(&GroupContext::default(), &Site::default()).unwrap();
+            page.contents
+        };
+
+        assert_eq!(generate("<head>\n<title>{ title }</title>\n</head>\n<p>{ description }</p>"),
+            "<head>\n<title>Chips</title>\n<meta name=\"description\" content=\"Fish &amp; &quot;chips&quot;\">\n</head>\n<p>Fish &amp; &quot;chips&quot;</p>");
+        assert_eq!(generate("<head><meta name=\"description\" content=\"{ description | upper }\"></head>"),
+            "<head><meta name=\"description\" content=\"FISH &amp; &quot;CHIPS&quot;\"></head>");
+    }
+
+    // Test that the keywords are inserted by { keywords } as an escaped, comma-separated list, and
+    // added to the <head> unless the template has a keywords tag of its own.
+    #[test]
+    fn test_keywords() {
+        let generate = |template: &str| {
+            let template = TemplateSource::Memory(format!("--\nkeywords: fish, chips ,, \"salt\" & vinegar\n--\n{}", template));
+            let mut page = Page::new(Path::new(""), &PathBuf::from("chips.sgpage"), &template, "", &Rc::default()).unwrap();
+            page.process_metadata().unwrap();
+            page.generate(&GroupContext::default(), &Site::default()).unwrap();
+            page.contents
+        };
+
+        assert_eq!(generate("<head>\n</head>"), "<head>\n<meta name=\"keywords\" content=\"fish, chips, &quot;salt&quot; &amp; vinegar\">\n</head>");
+        assert_eq!(generate("<head><meta name=\"keywords\" content=\"{ keywords | upper }\"></head>"),
+            "<head><meta name=\"keywords\" content=\"FISH, CHIPS, &quot;SALT&quot; &amp; VINEGAR\"></head>");
+    }
+
+    // Test that { updated } and { page.updated } give the date of a page's last revision, and
+    // nothing for pages that weren't revised.
+    #[test]
+    fn test_updated() {
+        let page = |metadata: &str, body: &str, site: &Site| {
+            let template = TemplateSource::Memory(format!("--\ndate: 2023-05-01\n{}\n--\n{}", metadata, body));
+            let mut page = Page::new(Path::new(""), &PathBuf::from("post.sgpage"), &template, "", &Rc::default()).unwrap();
+            page.process_metadata().unwrap();
+            page.generate(&GroupContext::default(), site).unwrap();
+            page
+        };
+        let revised = page("updated: 2 February 2024", "{ date } { updated \"%d.%m.%Y\" }", &Site::default());
+        assert_eq!(revised.contents, "2023-05-01 02.02.2024");
+        assert_eq!(page("", "{ date }/{ updated }", &Site::default()).contents, "2023-05-01/");
+
+        let site = Site { pages: vec![revised.get_metadata()], ..Site::default() };
+        assert_eq!(page("", "{ pages '{ page.date } { page.updated \"%Y\" }' }", &site).contents, "2023-05-01 2024");
+    }
+
+    // Test that { if } blocks render their body, or their { else } part, depending on the page's
+    // tags and group, and can be nested.
+    #[test]
+    fn test_if_blocks() {
+        let generate = |metadata: &str, body: &str| {
+            let template = TemplateSource::Memory(format!("--\n{}\n--\n{}", metadata, body));
+            let mut page = Page::new(Path::new(""), &PathBuf::from("post.sgpage"), &template, "", &Rc::default()).unwrap();
+            page.process_metadata().unwrap();
+            page.generate(&GroupContext::default(), &Site::default()).map(|_| page.contents)
+        };
+        let banner = "{ if tag \"rust\" }<p>Rust series</p>{ else }<p>Archive</p>{ endif }";
+        assert_eq!(generate("tags: Rust, Tools", banner).unwrap(), "<p>Rust series</p>");
+        assert_eq!(generate("tags: Go", banner).unwrap(), "<p>Archive</p>");
+        assert_eq!(generate("title: Untagged", "a{ if group \"docs\" }b{ endif }c").unwrap(), "ac");
+
+        let nested = "{ if group \"blog\" }{ if tag \"rust\" }1{ else }2{ endif }{ else }3{ endif }";
+        assert_eq!(generate("group: blog\ntags: rust", nested).unwrap(), "1");
+        assert_eq!(generate("group: blog", nested).unwrap(), "2");
+        assert_eq!(generate("group: docs\ntags: rust", nested).unwrap(), "3");
+        assert!(generate("title: X", "{ if author \"Ann\" }x{ endif }").is_err());
+    }
+
+    // Test that the parts of a series are ordered by weight and date, with the page's position in
+    // the series and a { series_nav } listing them.
+    #[test]
+    fn test_series() {
+        let template = TemplateSource::Memory(String::from("--\ntitle: Setup\nseries: Rust 101\ndate: 2024-03-01\n--\n\
+            { series.title }, part { series.index } of { series.count }: \
+            { series_nav sep=\" \" current='[{ page.index }]' '<a href=\"{ page.link }\">{ page.index }. { page.title }</a>' }"));
+        let mut page = Page::new(Path::new(""), &PathBuf::from("setup.sgpage"), &template, "", &Rc::default()).unwrap();
+        page.process_metadata().unwrap();
+
+        let part = |path: &str, title: &str, series: &str, date: u32, weight: Option<i64>| Metadata {
+            title: title.to_string(),
+            series: Some(series.to_string()),
+            date: NaiveDate::from_ymd_opt(2024, date, 1),
+            weight,
+            ..Metadata::new(path)
+        };
+        let site = Site {
+            pages: vec![
+                part("/ownership.html", "Ownership", "Rust 101", 5, None),
+                page.get_metadata(),
+                part("/intro.html", "Intro", "Rust 101", 6, Some(1)),
+                part("/async.html", "Async", "Rust 201", 1, None),
+            ],
+            ..Site::default()
+        };
+        assert_eq!(page.referenced_pages(&site), ["/intro.html", "/ownership.html"]);
+        page.generate(&GroupContext::default(), &site).unwrap();
+        assert_eq!(page.contents, "Rust 101, part 2 of 3: <a href=\"intro.html\">1. Intro</a> [2] <a href=\"ownership.html\">3. Ownership</a>");
+    }
+
+    // Test that { canonical } gives the URL from the metadata, or the page's URL below the site's
+    // base_url and base path, or a link to the page itself without a base_url.
+    #[test]
+    fn test_canonical() {
+        let generate = |metadata: &str, config: &str| {
+            let template = TemplateSource::Memory(format!("--\n{}\n--\n<link rel=\"canonical\" href=\"{{ canonical }}\">", metadata));
+            let config: Config = toml::from_str(config).unwrap();
+            let mut page = Page::new(Path::new("/site"), Path::new("/site/posts/fish chips.sgpage"), &template, "", &Rc::new(config)).unwrap();
+            page.process_metadata().unwrap();
+            page.generate(&GroupContext::default(), &Site::default()).unwrap();
+            page.contents
+        };
+        let site = "[site]\nbase_url = \"https://example.com/\"";
+        assert_eq!(generate("title: Fish", site), "<link rel=\"canonical\" href=\"https://example.com/posts/fish%20chips.html\">");
+        assert_eq!(generate("canonical: https://blog.example.org/?p=1&s=2", site),
+            "<link rel=\"canonical\" href=\"https://blog.example.org/?p=1&amp;s=2\">");
+        assert_eq!(generate("title: Fish", ""), "<link rel=\"canonical\" href=\"fish%20chips.html\">");
+        for site in ["base_path = \"blog\"\n[site]\nbase_url = \"https://example.com\"", "base_path = \"/blog/\"\n[site]\nbase_url = \"https://example.com/blog/\""] {
+            assert_eq!(generate("title: Fish", site), "<link rel=\"canonical\" href=\"https://example.com/blog/posts/fish%20chips.html\">");
+        }
+    }
+
+    // Test that the configured social profiles get <link rel="me"> tags in the <head>, and are
+    // listed by { social } blocks.
+    #[test]
+    fn test_social_profiles() {
+        let config: Config = toml::from_str(r#"
+            [[social]]
+            name = "Mastodon"
+            url = "https://mastodon.social/@jane"
+            [[social]]
+            name = "Code"
+            url = "https://example.com/?user=jane&tab=repos"
+        "#).unwrap();
+        let template = TemplateSource::Memory(String::from(
+            "<head>\n</head>\n{ social sep=\" | \" '<a rel=\"me\" href=\"{ profile.url }\">{ profile.name }</a>' }"));
+        let mut page = Page::new(Path::new(""), &PathBuf::from("index.sgpage"), &template, "", &Rc::new(config)).unwrap();
+        page.process_metadata().unwrap();
+        page.generate(&GroupContext::default(), &Site::default()).unwrap();
+        assert_eq!(page.contents, "<head>\n<link rel=\"me\" href=\"https://mastodon.social/@jane\">\n\
+            <link rel=\"me\" href=\"https://example.com/?user=jane&amp;tab=repos\">\n</head>\n\
+            <a rel=\"me\" href=\"https://mastodon.social/@jane\">Mastodon</a> | \
+            <a rel=\"me\" href=\"https://example.com/?user=jane&amp;tab=repos\">Code</a>");
+    }
+
+    // Test that { gallery } blocks repeat their markup for each matching image, with thumbnails
+    // for the images larger than the thumbnail size, and show the empty markup if nothing matches.
+    #[test]
+    fn test_gallery() {
+        let root = TempSite::new("gallery");
+        std::fs::create_dir_all(root.join("photos")).unwrap();
+        image::RgbImage::new(40, 20).save(root.join("photos/harbour_at-night.png")).unwrap();
+        image::RgbImage::new(10, 10).save(root.join("photos/boat.png")).unwrap();
+
+        let template = TemplateSource::Memory(String::from(
+            "{ gallery \"photos/*.png\" sep=\" \" '<a href=\"{ image.src }\"><img src=\"{ image.thumb }\" alt=\"{ image.alt }\"></a>' }\n\
+            { gallery \"none/*.jpg\" empty='<p>No photos</p>' '<img>' }"
+        ));
+        let config = Rc::new(Config { thumbnail_size: Some(16), ..Config::default() });
+        let mut page = Page::new(&root, &root.join("index.sgpage"), &template, "", &config).unwrap();
+        page.process_metadata().unwrap();
+        page.generate(&GroupContext::default(), &Site::default()).unwrap();
+        let thumbnail = image::image_dimensions(root.join("photos/thumbs/harbour_at-night.png"));
+
+        assert_eq!(page.contents, "<a href=\"photos/boat.png\"><img src=\"photos/boat.png\" alt=\"boat\"></a> \
+            <a href=\"photos/harbour_at-night.png\"><img src=\"photos/thumbs/harbour_at-night.png\" alt=\"harbour at night\"></a>\n\
+            <p>No photos</p>");
+        assert_eq!(thumbnail.unwrap(), (16, 8));
     }
 }
@@ -0,0 +1,57 @@
+use anyhow::{Context, Result};
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+// Pass a generated page through the configured post-processing command, given as the program
+// and its arguments, e.g. ["tidy", "-quiet"]. The command reads the page on its standard input
+// and writes the transformed page to its standard output. It runs in the web root, so it can be
+// a script in the site, and the path of the page relative to the web root is in the
+// SITEWINDER_PAGE environment variable.
+pub fn run(command: &[String], html: &str, root: &Path, page_path: &str) -> Result<String> {
+    let Some((program, args)) = command.split_first() else {
+        return Ok(html.to_string());
+    };
+    let mut child = Command::new(program)
+        .args(args)
+        .current_dir(root)
+        .env("SITEWINDER_PAGE", page_path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Unable to run post-processing command '{}'", program))?;
+
+    // Write the page from another thread, so a command that writes its output before it has read
+    // all of its input doesn't block
+    let mut stdin = child.stdin.take().context("Unable to write to the post-processing command")?;
+    let input = html.to_string();
+    let writer = std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let output = child.wait_with_output()
+        .with_context(|| format!("Unable to run post-processing command '{}'", program))?;
+    // The command may exit without reading its input, which isn't an error in itself
+    let _ = writer.join();
+
+    if !output.status.success() {
+        anyhow::bail!("Post-processing command '{}' failed with {}", program, output.status);
+    }
+    String::from_utf8(output.stdout).with_context(|| format!("Post-processing command '{}' didn't write UTF-8", program))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Test that pages are passed through the command, which knows the page's path, and that
+    // failing commands are errors.
+    #[test]
+    #[cfg(unix)]
+    fn test_post_process() {
+        let root = std::env::temp_dir();
+        let run = |args: &[&str], html: &str| run(&args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>(), html, &root, "/a.html");
+        assert_eq!(run(&["tr", "a-z", "A-Z"], "<p>hi</p>").unwrap(), "<P>HI</P>");
+        assert_eq!(run(&["sh", "-c", "cat; printf \"<!-- $SITEWINDER_PAGE -->\""], "<p>").unwrap(), "<p><!-- /a.html -->");
+        assert_eq!(run(&[], "<p>").unwrap(), "<p>");
+        assert!(run(&["false"], "<p>").is_err());
+        assert!(run(&["sitewinder-no-such-command"], "<p>").is_err());
+    }
+}
@@ -0,0 +1,154 @@
+use anyhow::{Context, Result};
+use std::collections::{BTreeSet, HashSet};
+use std::path::Path;
+use walkdir::WalkDir;
+
+use crate::sitegen::bundles::{self, BUNDLE_BLOCKS};
+use crate::sitegen::checksums::{MANIFEST_FILENAME, SOURCE_EXTENSIONS};
+use crate::sitegen::config::{Config, Prune, CONFIG_FILENAME};
+use crate::sitegen::encoding;
+use crate::sitegen::regexes::*;
+use crate::sitegen::template;
+
+// Files that aren't referenced by pages, but are requested by browsers, search engines or hosting
+// services, so they're never pruned.
+const WELL_KNOWN_FILES: [&str; 5] = ["/robots.txt", "/favicon.ico", "/CNAME", "/humans.txt", "/sitemap.xml"];
+
+// Report or delete the assets in the web root that no page references, depending on the
+// configuration. Returns the paths of the unreferenced assets, relative to the web root.
+pub fn run(root: &Path, mode: Prune, config: &Config) -> Result<Vec<String>> {
+    let unreferenced = unreferenced_assets(root, config)?;
+    for path in &unreferenced {
+        match mode {
+            Prune::Report => println!("unreferenced asset '{}'", path),
+            Prune::Delete => {
+                let file = root.join(path.trim_start_matches('/'));
+                std::fs::remove_file(&file).with_context(|| format!("Unable to remove unreferenced asset '{}'", file.display()))?;
+                println!("removed unreferenced asset '{}'", path);
+            }
+        }
+    }
+    if !config.quiet {
+        println!("{} unreferenced asset{}", unreferenced.len(), if unreferenced.len() == 1 { "" } else { "s" });
+    }
+    Ok(unreferenced)
+}
+
+// The assets in the web root, i.e. the files other than pages, templates and sitewinder's own
+// files such as feeds, that aren't referenced by any page, sorted by path. References are followed from the
+// HTML files in the web root, generated or not, through the stylesheets they reference, so
+// images used by stylesheets are kept too. Private files and folders, whose names start with
+// "." or "_", the well-known files, the files bundled by { bundle_css } and { bundle_js } blocks,
+// the critical stylesheet, and those matching `prune_keep` patterns are never pruned.
+pub fn unreferenced_assets(root: &Path, config: &Config) -> Result<Vec<String>> {
+    let keep = config.prune_keep.iter()
+        .map(|pattern| glob::Pattern::new(&format!("/{}", pattern.trim_start_matches('/')))
+            .with_context(|| format!("Invalid prune_keep pattern '{}'", pattern)))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut pages = Vec::new();
+    let mut assets = BTreeSet::new();
+    let mut bundled = HashSet::new();
+    let entries = WalkDir::new(root)
+        .into_iter()
+        .filter_entry(|entry| entry.depth() == 0 || !entry.file_name().to_string_lossy().starts_with(['.', '_']));
+    for entry in entries {
+        let entry = entry.with_context(|| format!("Unable to read folder '{}'", root.display()))?;
+        let extension = entry.path().extension().map(|extension| extension.to_string_lossy().to_lowercase()).unwrap_or_default();
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        if SOURCE_EXTENSIONS.contains(&extension.as_str()) {
+            let contents = std::fs::read(entry.path()).ok().and_then(|bytes| encoding::decode(&bytes)).unwrap_or_default();
+            let nodes = template::parse(&contents).unwrap_or_default();
+            for (name, _) in BUNDLE_BLOCKS {
+                bundled.extend(template::find_blocks(&nodes, name).into_iter().flat_map(|block| bundles::sources(root, block)));
+            }
+            continue;
+        }
+        let path = site_path(root, entry.path());
+        if extension == "html" || extension == "htm" {
+            pages.push(path);
+        } else if path != format!("/{}", CONFIG_FILENAME) && path != format!("/{}", MANIFEST_FILENAME)
+            && !WELL_KNOWN_FILES.contains(&path.as_str()) && !keep.iter().any(|pattern| pattern.matches(&path))
+            && !config.feed.iter().any(|feed| feed.is_feed(&path))
+            && config.critical_css.as_ref().is_none_or(|critical_css| path != format!("/{}", critical_css.trim_start_matches('/')))
+        {
+            assets.insert(path);
+        }
+    }
+
+    // Follow the references from the pages, and from the stylesheets they reference
+    let mut referenced = HashSet::new();
+    let mut queue = pages;
+    while let Some(path) = queue.pop() {
+        let Ok(contents) = std::fs::read_to_string(root.join(path.trim_start_matches('/'))) else {
+            continue;
+        };
+        for reference in references(&contents, &path, config) {
+            if reference.ends_with(".css") && !referenced.contains(&reference) {
+                queue.push(reference.clone());
+            }
+            referenced.insert(reference);
+        }
+    }
+
+    Ok(assets.into_iter().filter(|path| !referenced.contains(path) && !bundled.contains(path)).collect())
+}
+
+// The local files referenced by a page or stylesheet, relative to the web root: the links and
+// sources of elements, the images of srcset attributes, and url() and @import references in
+// stylesheets and style attributes.
+fn references(contents: &str, path: &str, config: &Config) -> Vec<String> {
+    let attributes = [&*RE_LINK_HREF, &*RE_LINK_SRC, &*RE_LINK_DATA, &*RE_LINK_POSTER, &*RE_LINK_ACTION]
+        .into_iter()
+        .flat_map(|re| re.captures_iter(contents).map(|caps| caps.get(2).map_or("", |url| url.as_str())));
+    let srcsets = RE_LINK_SRCSET.captures_iter(contents)
+        .flat_map(|caps| caps.get(2).map_or("", |srcset| srcset.as_str()).split(',').map(|candidate| candidate.split_whitespace().next().unwrap_or_default()));
+    let styles = RE_CSS_URL.captures_iter(contents)
+        .map(|caps| caps.name("url").or(caps.name("import")).map_or("", |url| url.as_str()));
+
+    attributes.chain(srcsets).chain(styles)
+        .filter_map(|url| super::resolve_link(path, url.trim(), config))
+        .collect()
+}
+
+fn site_path(root: &Path, path: &Path) -> String {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    let components: Vec<_> = relative.components().map(|component| component.as_os_str().to_string_lossy()).collect();
+    format!("/{}", components.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sitegen::testing::TempSite;
+
+    // Test that assets referenced by pages and their stylesheets are kept, and other assets are
+    // reported and deleted.
+    #[test]
+    fn test_prune_unreferenced_assets() {
+        let root = TempSite::new("prune");
+        for (path, contents) in [
+            ("index.html", "<link href=\"css/site.css?v=1\"><img src=\"/img/a.png\" srcset=\"img/a.png 1x, img/a@2x.png 2x\">"),
+            ("index.sgpage", "<img src=\"/img/draft.png\">"),
+            ("css/site.css", "@import \"print.css\";\nbody { background: url('../img/bg.png'); }"),
+            ("css/print.css", "h1 { background: url(/img/print.png) }"),
+            ("img/a.png", ""), ("img/a@2x.png", ""), ("img/bg.png", ""), ("img/print.png", ""),
+            ("img/old.png", ""), ("img/draft.png", ""), ("downloads/guide.pdf", ""), ("robots.txt", ""), ("_drafts/x.png", ""),
+        ] {
+            root.write(path, contents);
+        }
+
+        let config = Config { quiet: true, prune_keep: vec![String::from("downloads/*")], ..Config::default() };
+        let reported = run(&root, Prune::Report, &config).unwrap();
+        let deleted = run(&root, Prune::Delete, &config).unwrap();
+        let remaining = unreferenced_assets(&root, &config).unwrap();
+        let kept = root.join("img/a.png").exists();
+
+        assert_eq!(reported, ["/img/draft.png", "/img/old.png"]);
+        assert_eq!(deleted, reported);
+        assert!(remaining.is_empty());
+        assert!(kept);
+    }
+}
@@ -0,0 +1,111 @@
+use serde::Deserialize;
+use std::collections::HashSet;
+
+use crate::sitegen::metadata::Metadata;
+
+// Maximum number of characters in a title, unless configured otherwise. Search engines show
+// about this many.
+const DEFAULT_MAX_TITLE_LENGTH: usize = 60;
+
+// Maximum number of characters in a description, unless configured otherwise.
+const DEFAULT_MAX_DESCRIPTION_LENGTH: usize = 160;
+
+// Options for checking the metadata of pages, set in a [quality] table in the configuration
+// file. Pages are only checked if this is set.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QualityConfig {
+    // Maximum number of characters in a title. Defaults to 60; 0 for no limit.
+    pub max_title_length: Option<usize>,
+
+    // Maximum number of characters in a description. Defaults to 160; 0 for no limit.
+    pub max_description_length: Option<usize>,
+
+    // Don't warn about pages without a description.
+    pub allow_missing_description: bool,
+
+    // Don't warn about pages without a date in groups whose other pages have one.
+    pub allow_undated: bool,
+}
+
+// Check the metadata of the content pages that search engines and listings rely on: pages
+// without a title, titles and descriptions that are too long, missing descriptions, and pages
+// without a date in a group of dated pages, such as a blog. Returns the warnings by page path,
+// sorted by path.
+pub fn check(pages: &[Metadata], config: &QualityConfig) -> Vec<(String, String)> {
+    let max_title_length = config.max_title_length.unwrap_or(DEFAULT_MAX_TITLE_LENGTH);
+    let max_description_length = config.max_description_length.unwrap_or(DEFAULT_MAX_DESCRIPTION_LENGTH);
+    let dated_groups: HashSet<&str> = pages.iter()
+        .filter(|page| page.date.is_some())
+        .flat_map(|page| page.group.iter().chain(&page.other_groups).map(String::as_str))
+        .collect();
+
+    let mut warnings = Vec::new();
+    for page in pages {
+        let mut warn = |message: String| warnings.push((page.path.clone(), message));
+
+        let title_length = page.title.chars().count();
+        if page.title.trim().is_empty() {
+            warn(String::from("no title"));
+        } else if max_title_length > 0 && title_length > max_title_length {
+            warn(format!("title is {} characters long, more than {}", title_length, max_title_length));
+        }
+
+        let description_length = page.description.chars().count();
+        if page.description.trim().is_empty() {
+            if !config.allow_missing_description {
+                warn(String::from("no description"));
+            }
+        } else if max_description_length > 0 && description_length > max_description_length {
+            warn(format!("description is {} characters long, more than {}", description_length, max_description_length));
+        }
+
+        let dated_group = page.group.iter().chain(&page.other_groups).find(|group| dated_groups.contains(group.as_str()));
+        if let Some(group) = dated_group.filter(|_| page.date.is_none()) {
+            if !config.allow_undated {
+                warn(format!("no date, though other pages in group '{}' have one", group));
+            }
+        }
+    }
+    warnings.sort_by(|a, b| a.0.cmp(&b.0));
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    // Test that missing and over-long titles and descriptions, and undated pages in dated groups,
+    // are reported unless allowed.
+    #[test]
+    fn test_quality_warnings() {
+        let page = |path: &str, title: &str, description: &str, group: Option<&str>, date: Option<NaiveDate>| Metadata {
+            title: title.to_string(),
+            description: description.to_string(),
+            group: group.map(str::to_string),
+            date,
+            ..Metadata::new(path)
+        };
+        let date = NaiveDate::from_ymd_opt(2024, 5, 1);
+        let pages = [
+            page("/blog/b.html", "A Long Title", "", Some("blog"), None),
+            page("/blog/a.html", "Hello", "Short", Some("blog"), date),
+            page("/about.html", "", "Much too long a description", None, None),
+            page("/docs/intro.html", "Intro", "Start here", Some("docs"), None),
+        ];
+
+        let config = QualityConfig { max_title_length: Some(10), max_description_length: Some(20), ..QualityConfig::default() };
+        let warnings = |config: &QualityConfig| check(&pages, config).into_iter().map(|(path, message)| format!("{}: {}", path, message)).collect::<Vec<_>>();
+        assert_eq!(warnings(&config), [
+            "/about.html: no title",
+            "/about.html: description is 27 characters long, more than 20",
+            "/blog/b.html: title is 12 characters long, more than 10",
+            "/blog/b.html: no description",
+            "/blog/b.html: no date, though other pages in group 'blog' have one",
+        ]);
+
+        let lenient = QualityConfig { max_title_length: Some(0), allow_missing_description: true, allow_undated: true, ..config };
+        assert_eq!(warnings(&lenient), ["/about.html: no title", "/about.html: description is 27 characters long, more than 20"]);
+    }
+}
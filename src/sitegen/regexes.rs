@@ -10,26 +10,32 @@ macro_rules! def_regex {
     };
 }
 
-def_regex!(RE_INCLUDE, r#"\{\s*include\s+\"(?<path>[^\"]+)\"\s*\}"#);
-def_regex!(RE_TITLE, r#"\{\s*title\s*\}"#);
-def_regex!(RE_DATE, r#"\{\s*date\s*\}"#);
-def_regex!(RE_AUTHOR, r#"\{\s*author\s*\}"#);
-def_regex!(RE_CURRENT_YEAR, r#"\{\s*current_year\s*\}"#);
-def_regex!(RE_GROUP_NAV, r#"\{\s*group\s+\"(?<path>[^\"]+)\"\s*\}"#);
-def_regex!(RE_PREV_TITLE, r#"\{\s*prev.title\s*\}"#);
-def_regex!(RE_PREV_PATH, r#"\{\s*prev.path\s*\}"#);
-def_regex!(RE_NEXT_TITLE, r#"\{\s*next.title\s*\}"#);
-def_regex!(RE_NEXT_PATH, r#"\{\s*next.path\s*\}"#);
-def_regex!(RE_TAGS, r#"\{\s*tags\s+'(?<markup>.*)'\s*\}"#);
-def_regex!(RE_TAG_PAGE_LINK, r#"\{\s*tag.page.link\s*\}"#);
-def_regex!(RE_TAG_PAGE_LINK_SIZE, r#"\{\s*tag.page.link_size\s*\}"#);
-def_regex!(RE_TAG_PAGE_TITLE, r#"\{\s*tag.page.title\s*\}"#);
-def_regex!(RE_PAGES, r#"\{\s*pages\s+'(?<link>.*)'\s*\}"#);
-def_regex!(RE_PAGE_LINK, r#"\{\s*page.link\s*\}"#);
-def_regex!(RE_PAGE_TITLE, r#"\{\s*page.title\s*\}"#);
+def_regex!(RE_LAYOUT_KEY, r#"(?m)^layout:\s*(?<path>\S+)\s*$"#);
+def_regex!(RE_FOOTNOTE_DEF, r#"(?m)^[ \t]*\[\^(?<label>[\w-]+)\]:(?<text>.*)(?:\n|$)"#);
+def_regex!(RE_FOOTNOTE_REF, r#"\[\^(?<label>[\w-]+)\]"#);
+def_regex!(RE_FOOTNOTES, r#"\{\s*footnotes\s*\}"#);
+def_regex!(RE_BODY_END, r#"(?i)</body\s*>"#);
+def_regex!(RE_MATH_DELIMITED, r#"(?is)<pre\b.*?</pre\s*>|<code\b.*?</code\s*>|<script\b.*?</script\s*>|<style\b.*?</style\s*>|<textarea\b.*?</textarea\s*>|\\\$|\$\$(?<display>.+?)\$\$|\$(?<inline>[^\s$](?:[^$\n]*?[^\s$\\])?)\$"#);
+def_regex!(RE_BODY_START, r#"(?i)<body\b[^>]*>"#);
+def_regex!(RE_MAIN_ELEMENT, r#"(?is)<main\b[^>]*>(?<content>.*)</main\s*>"#);
+def_regex!(RE_ARTICLE_ELEMENT, r#"(?is)<article\b[^>]*>(?<content>.*)</article\s*>"#);
+def_regex!(RE_HEAD_END, r#"(?i)</head\s*>"#);
+def_regex!(RE_CSS_URL, r#"url\(\s*["']?(?<url>[^"')]+)["']?\s*\)|@import\s+["'](?<import>[^"']+)["']"#);
+def_regex!(RE_STYLESHEET_LINK, r#"(?i)<link\s[^>]*\brel\s*=\s*["']?stylesheet\b[^>]*>"#);
+def_regex!(RE_REL_STYLESHEET, r#"(?i)\brel\s*=\s*(?:"stylesheet"|'stylesheet'|stylesheet\b)"#);
+def_regex!(RE_META_DESCRIPTION, r#"(?i)<meta\s[^>]*name\s*=\s*["']?description\b"#);
+def_regex!(RE_META_KEYWORDS, r#"(?i)<meta\s[^>]*name\s*=\s*["']?keywords\b"#);
 def_regex!(RE_LINK_HREF, r#"(<(?:a|link|area|base)\s+[^>]*href\s*=\s*["'])([^"']+)(["'][^>]*>)"#);
 def_regex!(RE_LINK_SRC, r#"(<(?:img|audio|video|script|source|iframe|embed|track)\s+[^>]*src\s*=\s*["'])([^"']+)(["'][^>]*>)"#);
 def_regex!(RE_LINK_DATA, r#"(<(?:object|embed)\s+[^>]*data\s*=\s*["'])([^"']+)(["'][^>]*>)"#);
 def_regex!(RE_LINK_POSTER, r#"(<(?:video)\s+[^>]*poster\s*=\s*["'])([^"']+)(["'][^>]*>)"#);
 def_regex!(RE_LINK_ACTION, r#"(<form\s+[^>]*action\s*=\s*["'])([^"']+)(["'][^>]*>)"#);
 def_regex!(RE_LINK_SRCSET, r#"(<(?:img|source)\s+[^>]*srcset\s*=\s*["'])([^"']+)(["'][^>]*>)"#);
+def_regex!(RE_HEADING, r#"(?is)<h(?<level>[1-6])(?<attrs>(?:\s[^>]*)?)>(?<text>.*?)</h[1-6]\s*>"#);
+def_regex!(RE_ID_ATTRIBUTE, r#"(?i)(?:^|\s)id\s*=\s*["'](?<id>[^"']*)["']"#);
+def_regex!(RE_TAG, r#"<[^>]*>"#);
+def_regex!(RE_ENTITY, r#"&#?\w+;"#);
+def_regex!(RE_PARAGRAPH_START, r#"(?i)<p[\s>]"#);
+def_regex!(RE_HTML_START, r#"(?i)<html\b(?<attrs>[^>]*)>"#);
+def_regex!(RE_LANG_ATTRIBUTE, r#"(?i)\slang\s*=\s*(?:"[^"]*"|'[^']*'|[^\s>]+)"#);
+def_regex!(RE_DIR_ATTRIBUTE, r#"(?i)\sdir\s*=\s*(?:"[^"]*"|'[^']*'|[^\s>]+)"#);
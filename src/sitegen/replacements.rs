@@ -0,0 +1,133 @@
+use anyhow::{Context, Error};
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::sitegen::regexes::*;
+
+// Elements whose text is left as it is, as it is code or otherwise meant literally.
+const SKIPPED_ELEMENTS: [&str; 8] = ["code", "kbd", "math", "pre", "samp", "script", "style", "textarea"];
+
+// A find/replace rule, set in a [[replace]] table in the configuration file.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ReplaceRuleSource {
+    // Text to find, or a regular expression if `regex` is true.
+    find: String,
+    // Text to replace it with. Regular expression rules may refer to capture groups, e.g. "$1".
+    replace: String,
+    #[serde(default)]
+    regex: bool,
+}
+
+// A find/replace rule, with its pattern compiled when the configuration is read, so invalid
+// regular expressions are reported before the build starts.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "ReplaceRuleSource")]
+pub struct ReplaceRule {
+    pattern: Regex,
+    replacement: String,
+}
+
+impl TryFrom<ReplaceRuleSource> for ReplaceRule {
+    type Error = Error;
+
+    fn try_from(source: ReplaceRuleSource) -> Result<ReplaceRule, Error> {
+        if source.regex {
+            let pattern = Regex::new(&source.find)
+                .with_context(|| format!("Invalid regular expression '{}' in replace rule", source.find))?;
+            Ok(ReplaceRule { pattern, replacement: source.replace })
+        } else {
+            // Literal rules don't expand "$" in the replacement text
+            Ok(ReplaceRule {
+                pattern: Regex::new(&regex::escape(&source.find))?,
+                replacement: source.replace.replace('$', "$$"),
+            })
+        }
+    }
+}
+
+// Apply find/replace rules, in order, to the text of the <body> of an HTML document (or the
+// whole document, if it has no <body>). Tags, comments and the contents of code, pre, script
+// and similar elements are left unchanged.
+pub fn apply(html: &str, rules: &[ReplaceRule]) -> String {
+    let start = RE_BODY_START.find(html).map_or(0, |body_start| body_start.end());
+    let end = RE_BODY_END.find_iter(html).last().map_or(html.len(), |body_end| body_end.start()).max(start);
+
+    let mut result = String::with_capacity(html.len());
+    result.push_str(&html[..start]);
+
+    let mut skip_depth = 0usize;
+    let mut rest = &html[start..end];
+    while !rest.is_empty() {
+        let end = if rest.starts_with("<!--") {
+            // Comment, copied as it is
+            rest.find("-->").map_or(rest.len(), |i| i + 3)
+        } else if rest.starts_with('<') {
+            // Tag, copied as it is, but keeping track of whether we're inside a skipped element
+            let end = rest.find('>').map_or(rest.len(), |i| i + 1);
+            let tag = &rest[..end];
+            let name = tag.trim_start_matches(['<', '/'])
+                .chars()
+                .take_while(char::is_ascii_alphanumeric)
+                .collect::<String>()
+                .to_lowercase();
+            if SKIPPED_ELEMENTS.contains(&name.as_str()) {
+                if tag.starts_with("</") {
+                    skip_depth = skip_depth.saturating_sub(1);
+                } else if !tag.ends_with("/>") {
+                    skip_depth += 1;
+                }
+            }
+            end
+        } else {
+            // Text, up to the next tag
+            let end = rest.find('<').unwrap_or(rest.len());
+            if skip_depth == 0 {
+                let text = rules.iter().fold(rest[..end].to_string(), |text, rule| {
+                    rule.pattern.replace_all(&text, rule.replacement.as_str()).to_string()
+                });
+                result.push_str(&text);
+                rest = &rest[end..];
+                continue;
+            }
+            end
+        };
+
+        result.push_str(&rest[..end]);
+        rest = &rest[end..];
+    }
+
+    result.push_str(&html[end..]);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Test that rules are applied in order to the text of the body, but not to tags or code.
+    #[test]
+    fn test_rules_applied_in_order_to_body_text() {
+        let rules: Vec<ReplaceRule> = toml::from_str::<toml::Table>(r#"
+            [[replace]]
+            find = "Acme"
+            replace = "Acme$"
+
+            [[replace]]
+            find = '\bAcme\$'
+            replace = "Acme&trade;"
+            regex = true
+
+            [[replace]]
+            find = '(\d+)x(\d+)'
+            replace = "$1&times;$2"
+            regex = true
+        "#).unwrap()["replace"].clone().try_into().unwrap();
+        let html = "<title>Acme</title><body class=\"Acme\"><p>Acme 4x4</p><code>Acme 4x4</code></body>";
+
+        assert_eq!(
+            apply(html, &rules),
+            "<title>Acme</title><body class=\"Acme\"><p>Acme&trade; 4&times;4</p><code>Acme 4x4</code></body>"
+        );
+    }
+}
@@ -0,0 +1,157 @@
+use anyhow::{Context, Result};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::sitegen::config::Config;
+use crate::sitegen::regexes::*;
+use crate::sitegen::BuildSummary;
+
+// Name of the build report file in the web root. The underscore marks it as private, i.e. not
+// meant to be deployed with the site.
+pub const REPORT_FILENAME: &str = "_report.html";
+
+// Number of pages listed in the slowest and biggest pages tables.
+const TOP_PAGES: usize = 10;
+
+// Statistics of a generated page, for the build report.
+#[derive(Debug)]
+pub struct PageStats {
+    pub path: String,
+    pub duration: Duration,
+    // Size of the generated HTML file, in bytes.
+    pub size: usize,
+    // Local links and references in the page, relative to the web root.
+    pub links: Vec<String>,
+}
+
+impl PageStats {
+    pub fn new(path: &str, duration: Duration, html: &str, config: &Config) -> PageStats {
+        let links = RE_LINK_HREF.captures_iter(html)
+            .chain(RE_LINK_SRC.captures_iter(html))
+            .filter_map(|caps| super::resolve_link(path, &caps[2], config))
+            .collect();
+        PageStats { path: path.to_string(), duration, size: html.len(), links }
+    }
+}
+
+// Write the build report, summarising the pages generated, the pages that failed, broken
+// links, metadata quality warnings, pages over budget, and the slowest and biggest pages.
+pub fn write(root: &Path, summary: &BuildSummary, duration: Duration, config: &Config) -> Result<()> {
+    let mut html = String::from(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<title>Build report</title>\n\
+        <style>body{font-family:sans-serif;margin:2em;color:#222}table{border-collapse:collapse;margin-bottom:2em}\
+        th,td{text-align:left;padding:4px 12px;border-bottom:1px solid #ddd}\
+        .error{color:#c00}</style>\n</head>\n<body>\n<h1>Build report</h1>\n"
+    );
+
+    html.push_str(&format!(
+        "<p>Built on {} in {:.2} s: {} pages generated, {} failed.</p>\n",
+        chrono::Local::now().format("%Y-%m-%d %H:%M:%S"), duration.as_secs_f64(), summary.generated, summary.failures.len()
+    ));
+
+    html.push_str(&format!("<h2>Failed pages ({})</h2>\n", summary.failures.len()));
+    let failures = summary.failures.iter()
+        .map(|(path, error)| vec![escape(path), format!("<span class=\"error\">{}</span>", escape(&format!("{:#}", error)))])
+        .collect();
+    html.push_str(&table(&["Page", "Error"], failures));
+
+    // Links to files that don't exist
+    let broken: Vec<Vec<String>> = summary.pages.iter()
+        .flat_map(|page| page.links.iter().map(move |link| (page, link)))
+        .filter(|(_, link)| !root.join(link.trim_start_matches('/')).exists())
+        .map(|(page, link)| vec![escape(&page.path), escape(link)])
+        .collect();
+    html.push_str(&format!("<h2>Broken links ({})</h2>\n", broken.len()));
+    html.push_str(&table(&["Page", "Link"], broken));
+
+    html.push_str(&format!("<h2>Quality warnings ({})</h2>\n", summary.quality.len()));
+    let quality = summary.quality.iter().map(|(path, message)| vec![escape(path), escape(message)]).collect();
+    html.push_str(&table(&["Page", "Warning"], quality));
+
+    html.push_str(&format!("<h2>Budget warnings ({})</h2>\n", summary.budget.len()));
+    let budget = summary.budget.iter().map(|(path, message)| vec![escape(path), escape(message)]).collect();
+    html.push_str(&table(&["Page", "Warning"], budget));
+
+    let mut pages: Vec<&PageStats> = summary.pages.iter().collect();
+    pages.sort_by_key(|page| std::cmp::Reverse(page.duration));
+    let slowest = pages.iter().take(TOP_PAGES)
+        .map(|page| vec![escape(&page.path), format!("{:.1} ms", page.duration.as_secs_f64() * 1000.0)])
+        .collect();
+    html.push_str("<h2>Slowest pages</h2>\n");
+    html.push_str(&table(&["Page", "Time"], slowest));
+
+    pages.sort_by_key(|page| std::cmp::Reverse(page.size));
+    let biggest = pages.iter().take(TOP_PAGES)
+        .map(|page| vec![escape(&page.path), format!("{:.1} kB", page.size as f64 / 1000.0)])
+        .collect();
+    html.push_str("<h2>Biggest pages</h2>\n");
+    html.push_str(&table(&["Page", "Size"], biggest));
+
+    html.push_str("</body>\n</html>\n");
+
+    let path = root.join(REPORT_FILENAME);
+    std::fs::write(&path, html).with_context(|| format!("Unable to write build report '{}'", path.display()))?;
+    if !config.quiet {
+        println!("build report written to '{}'", path.display());
+    }
+    Ok(())
+}
+
+// Render a table with the given column headings and rows, whose cells are HTML.
+fn table(headings: &[&str], rows: Vec<Vec<String>>) -> String {
+    if rows.is_empty() {
+        return String::from("<p>None</p>\n");
+    }
+
+    let mut html = String::from("<table>\n<tr>");
+    for heading in headings {
+        html.push_str(&format!("<th>{}</th>", heading));
+    }
+    html.push_str("</tr>\n");
+    for row in rows {
+        html.push_str("<tr>");
+        for cell in row {
+            html.push_str(&format!("<td>{}</td>", cell));
+        }
+        html.push_str("</tr>\n");
+    }
+    html.push_str("</table>\n");
+    html
+}
+
+// Escape text for use in HTML.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sitegen::testing::TempSite;
+
+    // Test that the report lists the pages that failed, with their errors escaped, and the links
+    // to files that don't exist.
+    #[test]
+    fn test_report() {
+        let root = TempSite::new("report");
+        root.write("css/site.css", "");
+
+        let config = Config { quiet: true, ..Config::default() };
+        let html = "<link href=\"../css/site.css\"><a href=\"../about.html\">About</a><img src=\"/img/logo.png\">";
+        let summary = BuildSummary {
+            generated: 1,
+            failures: vec![(String::from("/b.html"), anyhow::anyhow!("Unknown filter '<shout>'"))],
+            pages: vec![PageStats::new("/posts/a.html", Duration::from_millis(5), html, &config)],
+            ..BuildSummary::default()
+        };
+        write(&root, &summary, Duration::from_secs(1), &config).unwrap();
+        let report = root.read(REPORT_FILENAME);
+
+        assert!(report.contains("1 pages generated, 1 failed."));
+        assert!(report.contains("<h2>Failed pages (1)</h2>\n<table>\n<tr><th>Page</th><th>Error</th></tr>\n\
+            <tr><td>/b.html</td><td><span class=\"error\">Unknown filter '&lt;shout&gt;'</span></td></tr>\n</table>\n"));
+        assert!(report.contains("<h2>Broken links (2)</h2>\n<table>\n<tr><th>Page</th><th>Link</th></tr>\n\
+            <tr><td>/posts/a.html</td><td>/about.html</td></tr>\n<tr><td>/posts/a.html</td><td>/img/logo.png</td></tr>\n</table>\n"));
+        assert!(report.contains("<h2>Quality warnings (0)</h2>\n<p>None</p>\n"));
+    }
+}
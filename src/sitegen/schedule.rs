@@ -0,0 +1,89 @@
+use chrono::{Datelike, Local, NaiveDate, TimeZone};
+use std::collections::BTreeSet;
+use std::path::Path;
+use walkdir::WalkDir;
+
+use crate::sitegen::checksums::SOURCE_EXTENSIONS;
+use crate::sitegen::config::Config;
+use crate::sitegen::encoding;
+use crate::sitegen::template;
+
+// The dates after `today` on which building the site again gives different output, with the
+// reason for each change, sorted by date: the dates of scheduled pages, which are generated from
+// their date on, the next new year if templates or include files use { current_year }, and
+// tomorrow if they use { random_page } without a configured random seed, as the seed is the
+// build date then.
+pub fn next_changes(root: &Path, scheduled: &[(NaiveDate, String)], today: NaiveDate, config: &Config) -> Vec<(NaiveDate, String)> {
+    let mut changes: BTreeSet<(NaiveDate, String)> = scheduled.iter()
+        .map(|(date, path)| (*date, format!("page '{}' is scheduled", path)))
+        .collect();
+
+    for entry in WalkDir::new(root).into_iter().filter_map(|e| e.ok()).filter(|entry| entry.file_type().is_file()) {
+        let extension = entry.path().extension().map(|extension| extension.to_string_lossy());
+        if !extension.is_some_and(|extension| SOURCE_EXTENSIONS.contains(&extension.as_ref())) {
+            continue;
+        }
+        let Ok(contents) = encoding::read_text(entry.path()) else {
+            continue;
+        };
+        let nodes = template::parse(&contents).unwrap_or_default();
+        for name in ["current_year", "copyright_years"] {
+            if !template::find_blocks(&nodes, name).is_empty() {
+                if let Some(new_year) = NaiveDate::from_ymd_opt(today.year() + 1, 1, 1) {
+                    changes.insert((new_year, format!("{{ {} }} changes", name)));
+                }
+            }
+        }
+        if !template::find_blocks(&nodes, "random_page").is_empty() && config.random_seed.is_none() {
+            if let Some(tomorrow) = today.succ_opt() {
+                changes.insert((tomorrow, String::from("{ random_page } picks other pages")));
+            }
+        }
+    }
+    changes.into_iter().collect()
+}
+
+// Print the dates on which building the site again gives different output, as the local time at
+// which the date starts, e.g. "2025-06-01T00:00:00+02:00", for scheduling builds.
+pub fn print(changes: &[(NaiveDate, String)]) {
+    if changes.is_empty() {
+        println!("next change: none, building the site again gives the same output");
+        return;
+    }
+    println!("next changes:");
+    for (date, reason) in changes {
+        let start = date.and_hms_opt(0, 0, 0).and_then(|start| Local.from_local_datetime(&start).earliest());
+        match start {
+            Some(start) => println!("  {}  {}", start.to_rfc3339(), reason),
+            None => println!("  {}  {}", date, reason),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sitegen::testing::TempSite;
+
+    // Test that scheduled pages, { current_year } and unseeded { random_page } blocks give the
+    // dates on which the output changes.
+    #[test]
+    fn test_next_changes() {
+        let root = TempSite::new("schedule");
+        root.write("footer.sginc", "<p>&copy; { current_year }</p>");
+        root.write("index.sgpage", "{ random_page '{ page.title }' }");
+
+        let today = NaiveDate::from_ymd_opt(2025, 6, 30).unwrap();
+        let scheduled = [(NaiveDate::from_ymd_opt(2025, 7, 4).unwrap(), String::from("/posts/launch.html"))];
+        let unseeded = next_changes(&root, &scheduled, today, &Config::default());
+        let seeded = next_changes(&root, &[], today, &Config { random_seed: Some(7), ..Config::default() });
+
+        let date = |year, month, day| NaiveDate::from_ymd_opt(year, month, day).unwrap();
+        assert_eq!(unseeded, [
+            (date(2025, 7, 1), String::from("{ random_page } picks other pages")),
+            (date(2025, 7, 4), String::from("page '/posts/launch.html' is scheduled")),
+            (date(2026, 1, 1), String::from("{ current_year } changes")),
+        ]);
+        assert_eq!(seeded, [(date(2026, 1, 1), String::from("{ current_year } changes"))]);
+    }
+}
@@ -0,0 +1,177 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::path::Path;
+
+// Elements whose text isn't checked, as it is code or otherwise not prose.
+const SKIPPED_ELEMENTS: [&str; 8] = ["code", "kbd", "math", "pre", "samp", "script", "style", "textarea"];
+
+// Number of characters shown on either side of a misspelled word.
+const CONTEXT_CHARS: usize = 30;
+
+// Options for spell checking the generated pages, set in a [spell_check] table in the
+// configuration file.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SpellCheckConfig {
+    // Word lists to check against, one word per line, e.g. "/usr/share/dict/words" and a
+    // custom dictionary for the site. Relative paths are relative to the web root.
+    pub dictionaries: Vec<String>,
+}
+
+// A word that isn't in the dictionary, with the text around it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Misspelling {
+    pub word: String,
+    pub context: String,
+}
+
+// The words of the configured dictionaries.
+#[derive(Debug)]
+pub struct Dictionary {
+    words: HashSet<String>,
+}
+
+impl Dictionary {
+    // Read the dictionaries listed in the configuration. Lines may also be in Hunspell .dic
+    // format, i.e. with affix flags after a slash, which are ignored.
+    pub fn load(root: &Path, config: &SpellCheckConfig) -> Result<Dictionary> {
+        if config.dictionaries.is_empty() {
+            anyhow::bail!("No dictionaries set in the [spell_check] configuration");
+        }
+
+        let mut words = HashSet::new();
+        for dictionary in &config.dictionaries {
+            let path = root.join(dictionary);
+            let contents = std::fs::read_to_string(&path)
+                .with_context(|| format!("Unable to read dictionary '{}'", path.display()))?;
+            words.extend(contents
+                .lines()
+                .map(|line| line.split('/').next().unwrap_or_default().trim())
+                .filter(|word| !word.is_empty() && !word.starts_with('#'))
+                .map(|word| word.replace('\u{2019}', "'")));
+        }
+        Ok(Dictionary { words })
+    }
+
+    // Check whether a word is spelled correctly. Words in lowercase in the dictionary may be
+    // capitalised, e.g. at the start of a sentence, but not the other way around.
+    fn contains(&self, word: &str) -> bool {
+        let lowercase = word.to_lowercase();
+        let known = |word: &str| self.words.contains(word);
+        known(word) || known(&lowercase)
+            || lowercase.strip_suffix("'s").is_some_and(|stem| known(&word[..stem.len()]) || known(stem))
+    }
+}
+
+// Find the misspelled words in the text of an HTML document. Words in the ignore list are
+// accepted (case-insensitive), and so are words in capitals, which are mostly abbreviations.
+pub fn check(html: &str, dictionary: &Dictionary, ignore: &[String]) -> Vec<Misspelling> {
+    let text = text_content(html);
+    let ignore: HashSet<String> = ignore.iter().map(|word| word.to_lowercase()).collect();
+
+    words(&text)
+        .filter(|(_, word)| word.chars().count() > 1 && word.chars().any(char::is_lowercase))
+        .filter(|(_, word)| !ignore.contains(&word.to_lowercase()) && !dictionary.contains(word))
+        .map(|(start, word)| Misspelling {
+            word: word.to_string(),
+            context: context(&text, start, start + word.len()),
+        })
+        .collect()
+}
+
+// The text of an HTML document, without tags, comments and the contents of skipped elements.
+// Tags and entities are replaced with a space, except entities for apostrophes.
+fn text_content(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut skip_depth = 0usize;
+    let mut rest = html;
+
+    while !rest.is_empty() {
+        let end = if rest.starts_with("<!--") {
+            rest.find("-->").map_or(rest.len(), |i| i + 3)
+        } else if rest.starts_with('<') {
+            let end = rest.find('>').map_or(rest.len(), |i| i + 1);
+            let tag = &rest[..end];
+            let name = tag.trim_start_matches(['<', '/'])
+                .chars()
+                .take_while(char::is_ascii_alphanumeric)
+                .collect::<String>()
+                .to_lowercase();
+            if SKIPPED_ELEMENTS.contains(&name.as_str()) {
+                if tag.starts_with("</") {
+                    skip_depth = skip_depth.saturating_sub(1);
+                } else if !tag.ends_with("/>") {
+                    skip_depth += 1;
+                }
+            }
+            end
+        } else if rest.starts_with('&') {
+            let end = rest.find(';').filter(|&i| i < 10).map_or(1, |i| i + 1);
+            if skip_depth == 0 {
+                let apostrophe = ["&#39;", "&#x27;", "&apos;", "&rsquo;", "&#8217;"].contains(&&rest[..end]);
+                text.push(if apostrophe { '\'' } else { ' ' });
+            }
+            rest = &rest[end..];
+            continue;
+        } else {
+            let end = rest.find(['<', '&']).unwrap_or(rest.len());
+            if skip_depth == 0 {
+                text.push_str(&rest[..end]);
+            }
+            rest = &rest[end..];
+            continue;
+        };
+
+        text.push(' ');
+        rest = &rest[end..];
+    }
+
+    text.replace('\u{2019}', "'")
+}
+
+// The words of a text, with their byte offsets. Words are runs of letters, and may contain
+// apostrophes, e.g. "don't", but not start or end with them. Words containing digits, e-mail
+// addresses and links aren't prose, so they are skipped.
+fn words(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.split_whitespace()
+        .filter(|token| {
+            !token.trim_end_matches(|c: char| c.is_ascii_punctuation())
+                .contains(|c: char| c.is_ascii_digit() || "@/_.".contains(c))
+        })
+        .flat_map(|token| token.split(|c: char| !c.is_alphabetic() && c != '\''))
+        .map(|word| word.trim_matches('\''))
+        .filter(|word| !word.is_empty())
+        .map(move |word| (word.as_ptr() as usize - text.as_ptr() as usize, word))
+}
+
+// The text around a word, on a single line, without partial words at either end.
+fn context(text: &str, start: usize, end: usize) -> String {
+    let before: Vec<char> = text[..start].chars().rev().take(CONTEXT_CHARS + 1).collect();
+    let before: String = before.iter().rev().skip_while(|c| before.len() > CONTEXT_CHARS && !c.is_whitespace()).collect();
+    let after: Vec<char> = text[end..].chars().take(CONTEXT_CHARS + 1).collect();
+    let after: String = after.iter().rev().skip_while(|c| after.len() > CONTEXT_CHARS && !c.is_whitespace()).collect::<Vec<_>>().into_iter().rev().collect();
+    format!("{}{}{}", before, &text[start..end], after).split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Test that words outside of code are checked against the dictionary, with page-specific exceptions.
+    #[test]
+    fn test_misspellings_outside_code() {
+        let dictionary = Dictionary {
+            words: ["the", "cat", "sat", "on", "mat", "in", "don't", "London"].iter().map(|word| word.to_string()).collect(),
+        };
+        let html = "<p title=\"Tabby\">The cat&rsquo;s sat on teh mat in London, don't <code>fn mian()</code></p>\
+            <p>Sitewinder FAQ london zorp</p>";
+
+        let words: Vec<String> = check(html, &dictionary, &[String::from("sitewinder")])
+            .into_iter()
+            .map(|misspelling| misspelling.word)
+            .collect();
+
+        assert_eq!(words, ["teh", "london", "zorp"]);
+    }
+}
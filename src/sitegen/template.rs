@@ -0,0 +1,401 @@
+use anyhow::Result;
+
+// Blocks that enclose a body up to an end block. The body of a { raw } block is kept as text.
+const RAW_BLOCK: (&str, &str) = ("raw", "endraw");
+const PAIRED_BLOCKS: [(&str, &str); 4] = [("for", "endfor"), ("section", "endsection"), ("if", "endif"), RAW_BLOCK];
+
+// A node of a parsed template: text that is written as it is, or a { ... } block.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Node {
+    Text(String),
+    Block(Block),
+}
+
+// A block, e.g. { title | upper }, { include "/header.sginc" } or { tags sep=", " '<markup>' }.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Block {
+    // Name of the block, e.g. "title", "page.link" or "tags".
+    pub name: String,
+    pub args: Vec<Arg>,
+    pub filters: Vec<Filter>,
+    // Nodes between a { for }, { section } or { if } block and its end block, or the text
+    // between { raw } and { endraw }.
+    pub body: Vec<Node>,
+    // The block as written in the template, including its body and end block.
+    pub source: String,
+}
+
+// An argument of a block: a value, e.g. "/header.sginc" or vars.links, or a named value, e.g. sep=", "
+#[derive(Clone, Debug, PartialEq)]
+pub struct Arg {
+    pub name: Option<String>,
+    pub value: String,
+}
+
+// A filter of a placeholder, e.g. "truncate 60" in { title | truncate 60 }.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Filter {
+    pub name: String,
+    pub args: Vec<String>,
+}
+
+impl Block {
+    // Value of a named argument, e.g. ", " for sep=", "
+    pub fn arg(&self, name: &str) -> Option<&str> {
+        self.args.iter().find(|arg| arg.name.as_deref() == Some(name)).map(|arg| arg.value.as_str())
+    }
+
+    // Values of the arguments without a name, in order.
+    pub fn values(&self) -> Vec<&str> {
+        self.args.iter().filter(|arg| arg.name.is_none()).map(|arg| arg.value.as_str()).collect()
+    }
+
+    // Whether this is the given block without arguments or filters, e.g. { endfor }
+    fn is_bare(&self, name: &str) -> bool {
+        self.name == name && self.args.is_empty() && self.filters.is_empty()
+    }
+
+    // The end block of a block that encloses a body, i.e. { for <name> in <list> },
+    // { section "<name>" }, { if <condition> "<value>" } or { raw }
+    fn end_name(&self) -> Option<&'static str> {
+        let values = self.values();
+        let paired = self.filters.is_empty() && values.len() == self.args.len() && match self.name.as_str() {
+            "for" => values.len() == 3 && values[1] == "in",
+            "section" => values.len() == 1,
+            "if" => values.len() == 2,
+            "raw" => values.is_empty(),
+            _ => false,
+        };
+        PAIRED_BLOCKS.iter().find(|(start, _)| paired && *start == self.name).map(|(_, end)| *end)
+    }
+}
+
+// Parse a template into text and blocks. Braces that don't start a block, e.g. in CSS rules or
+// scripts, are text. A { for } block must be closed by { endfor }, { section } by { endsection },
+// { if } by { endif }, and { raw } by { endraw }. A block written as {- ... } removes the
+// whitespace, including line breaks, before it, and { ... -} the whitespace after it, so blocks
+// that give nothing, e.g. { group } blocks on pages without a group, don't leave blank lines.
+pub fn parse(template: &str) -> Result<Vec<Node>> {
+    Ok(Parser { template, pos: 0 }.nodes(None)?.0)
+}
+
+// Render parsed nodes, replacing each block with the text given by `resolve`. Blocks for which
+// `resolve` gives None are written as they are.
+pub fn render(nodes: &[Node], mut resolve: impl FnMut(&Block) -> Result<Option<String>>) -> Result<String> {
+    let mut html = String::new();
+    for node in nodes {
+        match node {
+            Node::Text(text) => html.push_str(text),
+            Node::Block(block) => match resolve(block)? {
+                Some(text) => html.push_str(&text),
+                None => html.push_str(&block.source),
+            },
+        }
+    }
+    Ok(html)
+}
+
+// Split the body of an { if } block at its { else } block, if it has one, into the nodes for
+// either case. An { else } in a nested { if } block belongs to that block.
+pub fn split_else(body: &[Node]) -> (&[Node], &[Node]) {
+    let position = body.iter().position(|node| matches!(node, Node::Block(block) if block.is_bare("else")));
+    match position {
+        Some(position) => (&body[..position], &body[position + 1..]),
+        None => (body, &[]),
+    }
+}
+
+// All blocks with the given name, including those in the body of { for } and { section } blocks.
+pub fn find_blocks<'n>(nodes: &'n [Node], name: &str) -> Vec<&'n Block> {
+    let mut blocks = Vec::new();
+    for node in nodes {
+        if let Node::Block(block) = node {
+            if block.name == name {
+                blocks.push(block);
+            }
+            blocks.extend(find_blocks(&block.body, name));
+        }
+    }
+    blocks
+}
+
+struct Parser<'t> {
+    template: &'t str,
+    // Byte offset of the next character to parse.
+    pos: usize,
+}
+
+// Whether a block removes the whitespace before it, {- ... }, and after it, { ... -}
+#[derive(Clone, Copy, Default)]
+struct Trim {
+    before: bool,
+    after: bool,
+}
+
+impl Parser<'_> {
+    // Parse nodes up to the given end block, e.g. { endfor }, or up to the end of the template.
+    // Also gives whether the end block was found.
+    fn nodes(&mut self, end: Option<&str>) -> Result<(Vec<Node>, bool)> {
+        let mut nodes = Vec::new();
+        let mut text_start = self.pos;
+
+        while let Some(offset) = self.template[self.pos..].find('{') {
+            let start = self.pos + offset;
+            self.pos = start;
+            let Some((mut block, trim)) = self.block() else {
+                // Not a block, the brace is text
+                self.pos = start + 1;
+                continue;
+            };
+
+            let text = &self.template[text_start..start];
+            let text = if trim.before { text.trim_end() } else { text };
+            if !text.is_empty() {
+                nodes.push(Node::Text(text.to_string()));
+            }
+            if trim.after {
+                self.whitespace();
+            }
+
+            if end.is_some_and(|end| block.is_bare(end)) {
+                return Ok((nodes, true));
+            } else if let Some((start, _)) = PAIRED_BLOCKS.iter().find(|(_, end)| block.is_bare(end)) {
+                anyhow::bail!("{} without {{ {} }}", block.source, start);
+            } else if block.end_name() == Some(RAW_BLOCK.1) {
+                let body_start = self.pos;
+                let (body_end, end_trim) = self.find_end(RAW_BLOCK.1)
+                    .ok_or_else(|| anyhow::anyhow!("{{ raw }} block without {{ endraw }}"))?;
+                let body = &self.template[body_start..body_end];
+                let body = if end_trim.before { body.trim_end() } else { body };
+                block.body = vec![Node::Text(body.to_string())];
+                block.source = self.template[start..self.pos].to_string();
+                if end_trim.after {
+                    self.whitespace();
+                }
+            } else if let Some(end_name) = block.end_name() {
+                let (body, closed) = self.nodes(Some(end_name))?;
+                if !closed {
+                    anyhow::bail!("{} block without {{ {} }}", block.source, end_name);
+                }
+                block.body = body;
+                block.source = self.template[start..self.pos].to_string();
+            }
+
+            nodes.push(Node::Block(block));
+            text_start = self.pos;
+        }
+
+        if text_start < self.template.len() {
+            nodes.push(Node::Text(self.template[text_start..].to_string()));
+        }
+        self.pos = self.template.len();
+        Ok((nodes, false))
+    }
+
+    // Find the given end block, e.g. { endraw }, without parsing the text before it. Gives the
+    // start of the end block and its trim markers, and moves past it.
+    fn find_end(&mut self, end: &str) -> Option<(usize, Trim)> {
+        while let Some(offset) = self.template[self.pos..].find('{') {
+            let start = self.pos + offset;
+            self.pos = start;
+            if let Some((block, trim)) = self.block() {
+                if block.is_bare(end) {
+                    return Some((start, trim));
+                }
+            }
+            self.pos = start + 1;
+        }
+        None
+    }
+
+    // Parse a block at the current position, which is an opening brace, and its trim markers
+    fn block(&mut self) -> Option<(Block, Trim)> {
+        let start = self.pos;
+        self.pos += 1;
+        let mut trim = Trim::default();
+        if self.peek() == Some('-') {
+            trim.before = true;
+            self.pos += 1;
+        }
+        self.whitespace();
+        let name = self.name()?;
+        let mut args = Vec::new();
+        let mut filters = Vec::new();
+
+        loop {
+            self.whitespace();
+            if self.template[self.pos..].starts_with("-}") {
+                trim.after = true;
+                self.pos += 1;
+            }
+            match self.peek()? {
+                '}' => break,
+                '|' => {
+                    self.pos += 1;
+                    self.whitespace();
+                    let name = self.identifier()?;
+                    let mut filter_args = Vec::new();
+                    loop {
+                        self.whitespace();
+                        if matches!(self.peek()?, '|' | '}') || self.template[self.pos..].starts_with("-}") {
+                            break;
+                        }
+                        filter_args.push(self.value()?);
+                    }
+                    filters.push(Filter { name, args: filter_args });
+                }
+                _ => args.push(self.arg()?),
+            }
+        }
+        self.pos += 1;
+
+        let block = Block { name, args, filters, body: Vec::new(), source: self.template[start..self.pos].to_string() };
+        Some((block, trim))
+    }
+
+    // Parse an argument, e.g. "/header.sginc", vars.links or sep=", "
+    fn arg(&mut self) -> Option<Arg> {
+        let quoted = matches!(self.peek()?, '"' | '\'');
+        let value = self.value()?;
+        if !quoted {
+            let value_end = self.pos;
+            self.whitespace();
+            if self.peek() == Some('=') {
+                self.pos += 1;
+                self.whitespace();
+                return Some(Arg { name: Some(value), value: self.value()? });
+            }
+            self.pos = value_end;
+        }
+        Some(Arg { name: None, value })
+    }
+
+    // Parse a quoted string or a bare word. A quote only ends a string if it is followed by
+    // whitespace, '|' or '}', so strings can contain the other kind of quote, e.g. HTML markup in
+    // single quotes, and apostrophes. Strings can't span lines.
+    fn value(&mut self) -> Option<String> {
+        let rest = &self.template[self.pos..];
+        let quote = rest.chars().next()?;
+        if quote == '"' || quote == '\'' {
+            let mut chars = rest.char_indices().skip(1).peekable();
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    '\n' => return None,
+                    c if c == quote && chars.peek().is_none_or(|&(_, next)| next.is_whitespace() || next == '|' || next == '}') => {
+                        self.pos += i + 1;
+                        return Some(rest[1..i].to_string());
+                    }
+                    _ => {}
+                }
+            }
+            return None;
+        }
+
+        let len = rest.find(|c: char| c.is_whitespace() || "\"'{}|=".contains(c)).unwrap_or(rest.len());
+        if len == 0 {
+            return None;
+        }
+        self.pos += len;
+        Some(rest[..len].to_string())
+    }
+
+    // Parse a block name, e.g. "title" or "tag.page.link"
+    fn name(&mut self) -> Option<String> {
+        let mut name = self.identifier()?;
+        while self.peek() == Some('.') {
+            let rest = &self.template[self.pos + 1..];
+            let len = rest.find(|c: char| !c.is_alphanumeric() && c != '_' && c != '-').unwrap_or(rest.len());
+            if len == 0 {
+                return None;
+            }
+            name.push('.');
+            name.push_str(&rest[..len]);
+            self.pos += len + 1;
+        }
+        Some(name)
+    }
+
+    // Parse an identifier, e.g. "title" or "truncate"
+    fn identifier(&mut self) -> Option<String> {
+        let rest = &self.template[self.pos..];
+        if !rest.starts_with(|c: char| c.is_alphabetic() || c == '_') {
+            return None;
+        }
+        let len = rest.find(|c: char| !c.is_alphanumeric() && c != '_' && c != '-').unwrap_or(rest.len());
+        self.pos += len;
+        Some(rest[..len].to_string())
+    }
+
+    fn whitespace(&mut self) {
+        let rest = &self.template[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.template[self.pos..].chars().next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(name: &str, args: &[(Option<&str>, &str)], source: &str) -> Node {
+        Node::Block(Block {
+            name: name.to_string(),
+            args: args.iter().map(|(name, value)| Arg { name: name.map(str::to_string), value: value.to_string() }).collect(),
+            filters: Vec::new(),
+            body: Vec::new(),
+            source: source.to_string(),
+        })
+    }
+
+    // Test that blocks are parsed with nested quotes and braces, several blocks on a line, and
+    // braces that don't start a block as text.
+    #[test]
+    fn test_parse_blocks() {
+        let nodes = parse(
+            "<script>var x = {\"a\": 1};</script>{ tags sep=\", \" '<a href=\"{ tag.page.link }\">it's</a>' }{ title | truncate 60|upper }\
+            { include \"/nav.sginc\" }{ raw }{ for }{ endraw }"
+        ).unwrap();
+
+        let Node::Block(title) = &nodes[2] else { panic!("expected a block") };
+        assert_eq!(title.filters, [
+            Filter { name: String::from("truncate"), args: vec![String::from("60")] },
+            Filter { name: String::from("upper"), args: Vec::new() },
+        ]);
+        let Node::Block(raw) = &nodes[4] else { panic!("expected a block") };
+        assert_eq!(raw.body, [Node::Text(String::from("{ for }"))]);
+
+        assert_eq!(nodes[..2], [
+            Node::Text(String::from("<script>var x = {\"a\": 1};</script>")),
+            block("tags", &[(Some("sep"), ", "), (None, "<a href=\"{ tag.page.link }\">it's</a>")],
+                "{ tags sep=\", \" '<a href=\"{ tag.page.link }\">it's</a>' }"),
+        ]);
+        assert_eq!(nodes[3], block("include", &[(None, "/nav.sginc")], "{ include \"/nav.sginc\" }"));
+
+        assert!(parse("{ for x in meta.tags }<li>{ x }</li>").is_err());
+        assert!(parse("{ endraw }").is_err());
+        assert!(parse("{ section \"install\" }<p>Run it</p>").is_err());
+    }
+
+    // Test that trim markers remove the whitespace before and after blocks, including line breaks
+    // and the whitespace at the start and end of raw blocks.
+    #[test]
+    fn test_trim_markers() {
+        let nodes = parse("<nav>\n  {- group \"nav.html\" -}\n</nav>\n{ title | truncate 5 -}  \n!{ raw -} { x } {- endraw -}\n.").unwrap();
+        assert_eq!(nodes[..2], [
+            Node::Text(String::from("<nav>")),
+            block("group", &[(None, "nav.html")], "{- group \"nav.html\" -}"),
+        ]);
+        let Node::Block(title) = &nodes[3] else { panic!("expected a block") };
+        assert_eq!(title.filters, [Filter { name: String::from("truncate"), args: vec![String::from("5")] }]);
+        let Node::Block(raw) = &nodes[5] else { panic!("expected a block") };
+        assert_eq!(raw.body, [Node::Text(String::from("{ x }"))]);
+        assert_eq!([&nodes[2], &nodes[4], &nodes[6]], [
+            &Node::Text(String::from("</nav>\n")),
+            &Node::Text(String::from("!")),
+            &Node::Text(String::from(".")),
+        ]);
+    }
+}
@@ -0,0 +1,50 @@
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+// A web root for tests in the system's temporary folder, named after the test and the process.
+// Anything left over from an earlier run is removed first, and the folder is removed when the
+// value is dropped, also when an assertion fails.
+pub struct TempSite {
+    root: PathBuf,
+}
+
+impl TempSite {
+    pub fn new(name: &str) -> TempSite {
+        let root = std::env::temp_dir().join(format!("sitewinder-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        TempSite { root }
+    }
+
+    // Write a file below the web root, creating the folders it is in.
+    pub fn write(&self, path: &str, contents: impl AsRef<[u8]>) {
+        let path = self.root.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    // Read a file below the web root, or an empty string if it doesn't exist.
+    pub fn read(&self, path: &str) -> String {
+        std::fs::read_to_string(self.root.join(path)).unwrap_or_default()
+    }
+}
+
+impl Deref for TempSite {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.root
+    }
+}
+
+impl AsRef<Path> for TempSite {
+    fn as_ref(&self) -> &Path {
+        &self.root
+    }
+}
+
+impl Drop for TempSite {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.root);
+    }
+}
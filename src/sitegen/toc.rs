@@ -0,0 +1,153 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::sitegen::regexes::*;
+
+// A heading in a page, which can be linked to by its id.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Heading {
+    pub level: usize,
+    pub id: String,
+    // Text of the heading, without tags.
+    pub text: String,
+}
+
+// Find the <h1> to <h6> headings in an HTML document, and give the headings that don't have an
+// id an id derived from their text, e.g. "getting-started" for "Getting Started". Ids are made
+// unique by adding a number, e.g. "examples-2".
+pub fn anchor_headings(html: &str) -> (String, Vec<Heading>) {
+    let mut used: HashSet<String> = RE_HEADING
+        .captures_iter(html)
+        .filter_map(|caps| RE_ID_ATTRIBUTE.captures(&caps["attrs"]).map(|id| id["id"].to_string()))
+        .collect();
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    let mut headings = Vec::new();
+
+    let html = RE_HEADING
+        .replace_all(html, |caps: &regex::Captures| {
+            let text = RE_TAG.replace_all(&caps["text"], "").split_whitespace().collect::<Vec<_>>().join(" ");
+            let level = caps["level"].parse().unwrap_or(1);
+
+            if let Some(id) = RE_ID_ATTRIBUTE.captures(&caps["attrs"]) {
+                headings.push(Heading { level, id: id["id"].to_string(), text });
+                return caps[0].to_string();
+            }
+
+            // Derive an id from the text that isn't used yet
+            let slug = Some(super::slugify(&text)).filter(|slug| !slug.is_empty()).unwrap_or(String::from("section"));
+            let mut id = slug.clone();
+            while used.contains(&id) {
+                let count = counts.entry(slug.clone()).or_insert(1);
+                *count += 1;
+                id = format!("{}-{}", slug, count);
+            }
+            used.insert(id.clone());
+
+            let heading = format!("<h{}{} id=\"{}\">{}</h{}>", level, &caps["attrs"], id, &caps["text"], level);
+            headings.push(Heading { level, id, text });
+            heading
+        })
+        .to_string();
+
+    (html, headings)
+}
+
+// Create a table of contents: a <nav> element with a nested list of links to the headings from
+// the first to the last level. Gives nothing if there are no headings at those levels.
+pub fn list(headings: &[Heading], first_level: usize, last_level: usize) -> String {
+    if !headings.iter().any(|heading| (first_level..=last_level).contains(&heading.level)) {
+        return String::new();
+    }
+    let lists = nested_lists(headings, first_level, last_level, "<ol>", |heading| {
+        format!("<li><a href=\"#{}\">{}</a>\n", heading.id, heading.text)
+    });
+    format!("<nav class=\"toc\" aria-label=\"Table of contents\">\n{}</nav>", lists)
+}
+
+// Create a table of contents for a sidebar: a <nav> element with a nested list of links to the
+// headings from the first to the last level. Links carry data attributes identifying the target
+// heading and its level, so scripts can highlight the section being read ("scroll-spy").
+pub fn sidebar(headings: &[Heading], first_level: usize, last_level: usize) -> String {
+    let lists = nested_lists(headings, first_level, last_level, "<ol class=\"toc-list\">", |heading| format!(
+        "<li class=\"toc-item toc-level-{}\" data-toc-level=\"{}\"><a class=\"toc-link\" href=\"#{}\" data-toc-target=\"{}\">{}</a>\n",
+        heading.level, heading.level, heading.id, heading.id, heading.text
+    ));
+    format!("<nav class=\"toc-sidebar\" id=\"toc\" aria-label=\"Table of contents\" data-toc>\n{}</nav>", lists)
+}
+
+// Nest the headings from the first to the last level in <ol> lists, which start with the given
+// tag. `item` gives the start of a heading's list item.
+fn nested_lists(headings: &[Heading], first_level: usize, last_level: usize, list_start: &str, item: impl Fn(&Heading) -> String) -> String {
+    let mut html = String::new();
+    // Levels of the lists that are currently open
+    let mut open: Vec<usize> = Vec::new();
+
+    for heading in headings.iter().filter(|heading| (first_level..=last_level).contains(&heading.level)) {
+        // Close the items and lists of deeper or equal levels, then open a list for a deeper level
+        while open.last().is_some_and(|&level| level > heading.level) {
+            html.push_str("</li>\n</ol>\n");
+            open.pop();
+        }
+        if open.last() == Some(&heading.level) {
+            html.push_str("</li>\n");
+        } else {
+            html.push_str(list_start);
+            html.push('\n');
+            open.push(heading.level);
+        }
+        html.push_str(&item(heading));
+    }
+
+    for _ in open {
+        html.push_str("</li>\n</ol>\n");
+    }
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Test that headings get unique ids and are listed in a nested sidebar list.
+    #[test]
+    fn test_sidebar_nested_with_unique_ids() {
+        let html = "<h1>Guide</h1><h2 class=\"x\">Setup <em>now</em></h2><h3>Examples</h3>\
+            <h2 id=\"examples\">Usage</h2><h3>Examples</h3>";
+        let (html, headings) = anchor_headings(html);
+
+        assert_eq!(
+            html,
+            "<h1 id=\"guide\">Guide</h1><h2 class=\"x\" id=\"setup-now\">Setup <em>now</em></h2><h3 id=\"examples-2\">Examples</h3>\
+            <h2 id=\"examples\">Usage</h2><h3 id=\"examples-3\">Examples</h3>"
+        );
+        assert_eq!(
+            sidebar(&headings, 2, 3),
+            "<nav class=\"toc-sidebar\" id=\"toc\" aria-label=\"Table of contents\" data-toc>\n\
+            <ol class=\"toc-list\">\n\
+            <li class=\"toc-item toc-level-2\" data-toc-level=\"2\"><a class=\"toc-link\" href=\"#setup-now\" data-toc-target=\"setup-now\">Setup now</a>\n\
+            <ol class=\"toc-list\">\n\
+            <li class=\"toc-item toc-level-3\" data-toc-level=\"3\"><a class=\"toc-link\" href=\"#examples-2\" data-toc-target=\"examples-2\">Examples</a>\n\
+            </li>\n</ol>\n</li>\n\
+            <li class=\"toc-item toc-level-2\" data-toc-level=\"2\"><a class=\"toc-link\" href=\"#examples\" data-toc-target=\"examples\">Usage</a>\n\
+            <ol class=\"toc-list\">\n\
+            <li class=\"toc-item toc-level-3\" data-toc-level=\"3\"><a class=\"toc-link\" href=\"#examples-3\" data-toc-target=\"examples-3\">Examples</a>\n\
+            </li>\n</ol>\n</li>\n</ol>\n</nav>"
+        );
+    }
+
+    // Test that the table of contents nests the headings at the given levels, and is empty without headings.
+    #[test]
+    fn test_toc_list() {
+        let (_, headings) = anchor_headings("<h1>Guide</h1><h2>Setup</h2><h2>Usage</h2><h3>Options</h3><h4>More</h4>");
+
+        assert_eq!(
+            list(&headings, 1, 3),
+            "<nav class=\"toc\" aria-label=\"Table of contents\">\n\
+            <ol>\n<li><a href=\"#guide\">Guide</a>\n\
+            <ol>\n<li><a href=\"#setup\">Setup</a>\n\
+            </li>\n<li><a href=\"#usage\">Usage</a>\n\
+            <ol>\n<li><a href=\"#options\">Options</a>\n\
+            </li>\n</ol>\n</li>\n</ol>\n</li>\n</ol>\n</nav>"
+        );
+        assert_eq!(list(&headings, 5, 6), "");
+    }
+}
@@ -0,0 +1,116 @@
+// Elements whose text is left as it is, as it is code or otherwise meant literally.
+const SKIPPED_ELEMENTS: [&str; 8] = ["code", "kbd", "math", "pre", "samp", "script", "style", "textarea"];
+
+// Apply typographic replacements to the text of an HTML document: straight quotes become curly
+// quotes, "--" and "---" become en and em dashes, and "..." becomes an ellipsis. Tags, comments
+// and the contents of code, pre, script and similar elements are left unchanged.
+pub fn smarten(html: &str) -> String {
+    let mut result = String::with_capacity(html.len());
+    let mut skip_depth = 0usize;
+    // Last character of text, used to decide between opening and closing quotes
+    let mut previous = ' ';
+    let mut rest = html;
+
+    while !rest.is_empty() {
+        let end = if rest.starts_with("<!--") {
+            // Comment, copied as it is
+            rest.find("-->").map_or(rest.len(), |i| i + 3)
+        } else if rest.starts_with('<') {
+            // Tag, copied as it is, but keeping track of whether we're inside a skipped element
+            let end = rest.find('>').map_or(rest.len(), |i| i + 1);
+            let tag = &rest[..end];
+            let name: String = tag.trim_start_matches(['<', '/'])
+                .chars()
+                .take_while(char::is_ascii_alphanumeric)
+                .collect::<String>()
+                .to_lowercase();
+            if SKIPPED_ELEMENTS.contains(&name.as_str()) {
+                if tag.starts_with("</") {
+                    skip_depth = skip_depth.saturating_sub(1);
+                } else if !tag.ends_with("/>") {
+                    skip_depth += 1;
+                }
+            }
+            end
+        } else {
+            // Text, up to the next tag
+            let end = rest.find('<').unwrap_or(rest.len());
+            if skip_depth == 0 {
+                result.push_str(&smarten_text(&rest[..end], &mut previous));
+                rest = &rest[end..];
+                continue;
+            }
+            end
+        };
+
+        result.push_str(&rest[..end]);
+        rest = &rest[end..];
+    }
+
+    result
+}
+
+// Apply typographic replacements to a piece of text, given the last character of the text before it.
+fn smarten_text(text: &str, previous: &mut char) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let replacement = match c {
+            '"' => if is_opening_context(*previous) { '\u{201C}' } else { '\u{201D}' },
+            '\'' => if is_opening_context(*previous) { '\u{2018}' } else { '\u{2019}' },
+            '.' if text_continues_with(&chars, "..") => {
+                chars.nth(1);
+                '\u{2026}'
+            }
+            '-' => {
+                // Count the run of hyphens; longer runs than "---" are left as they are
+                let mut hyphens = 1;
+                while chars.next_if_eq(&'-').is_some() {
+                    hyphens += 1;
+                }
+                match hyphens {
+                    1 => '-',
+                    2 => '\u{2013}',
+                    3 => '\u{2014}',
+                    _ => {
+                        result.push_str(&"-".repeat(hyphens));
+                        *previous = '-';
+                        continue;
+                    }
+                }
+            }
+            other => other,
+        };
+
+        result.push(replacement);
+        *previous = replacement;
+    }
+
+    result
+}
+
+// Check whether the upcoming characters start with the given text.
+fn text_continues_with(chars: &std::iter::Peekable<std::str::Chars>, text: &str) -> bool {
+    chars.clone().take(text.len()).eq(text.chars())
+}
+
+// Check whether a quote following the given character opens a quotation, rather than closing
+// one or being an apostrophe.
+fn is_opening_context(previous: char) -> bool {
+    previous.is_whitespace() || "([{\u{2018}\u{201C}\u{2013}\u{2014}".contains(previous)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Test that quotes, dashes and ellipses are replaced in text, but not in tags or code.
+    #[test]
+    fn test_smarten_text_but_not_tags_or_code() {
+        let html = "<p class=\"intro\">\"It's 9--5,\" she said -- <em>'again'</em>...</p><pre>\"x\" -- y...</pre>";
+        let expected = "<p class=\"intro\">\u{201C}It\u{2019}s 9\u{2013}5,\u{201D} she said \u{2013} <em>\u{2018}again\u{2019}</em>\u{2026}</p><pre>\"x\" -- y...</pre>";
+
+        assert_eq!(smarten(html), expected);
+    }
+}
@@ -0,0 +1,147 @@
+use anyhow::{Context, Error};
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::sitegen::metadata::Metadata;
+
+// A metadata rule, set in a [[validate]] table in the configuration file.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ValidationRuleSource {
+    // Group whose pages the rule applies to, as their primary group or another one. Without a
+    // group, it applies to every content page.
+    group: Option<String>,
+    // Metadata keys that the pages must have, e.g. "title" and "date", or custom keys.
+    #[serde(default)]
+    required: Vec<String>,
+    // Maximum number of characters in a page title.
+    max_title_length: Option<usize>,
+    // Regular expression that every tag must match, e.g. "[a-z0-9-]+".
+    tag_pattern: Option<String>,
+}
+
+// A metadata rule, with its tag pattern compiled when the configuration is read, so invalid
+// regular expressions are reported before the build starts.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "ValidationRuleSource")]
+pub struct ValidationRule {
+    group: Option<String>,
+    required: Vec<String>,
+    max_title_length: Option<usize>,
+    // The tag pattern as configured, for messages, and compiled to match whole tags.
+    tag_pattern: Option<(String, Regex)>,
+}
+
+impl TryFrom<ValidationRuleSource> for ValidationRule {
+    type Error = Error;
+
+    fn try_from(source: ValidationRuleSource) -> Result<ValidationRule, Error> {
+        let tag_pattern = source.tag_pattern
+            .map(|pattern| {
+                let regex = Regex::new(&format!("^(?:{})$", pattern))
+                    .with_context(|| format!("Invalid regular expression '{}' in validate rule", pattern))?;
+                Ok::<_, Error>((pattern, regex))
+            })
+            .transpose()?;
+        Ok(ValidationRule { group: source.group, required: source.required, max_title_length: source.max_title_length, tag_pattern })
+    }
+}
+
+// Check the metadata of content pages against the configured rules. Returns the violations by
+// page path, sorted by path.
+pub fn check(pages: &[Metadata], rules: &[ValidationRule]) -> Vec<(String, String)> {
+    let mut violations = Vec::new();
+    for page in pages {
+        let mut violation = |message: String| violations.push((page.path.clone(), message));
+        let applicable = rules.iter().filter(|rule| rule.group.as_deref().is_none_or(|group| page.in_group(group)));
+        for rule in applicable {
+            for key in rule.required.iter().filter(|key| !has_field(page, key)) {
+                violation(match &rule.group {
+                    Some(group) => format!("no '{}', which pages in group '{}' must have", key, group),
+                    None => format!("no '{}', which every page must have", key),
+                });
+            }
+
+            let title_length = page.title.chars().count();
+            if let Some(max_title_length) = rule.max_title_length.filter(|max| title_length > *max) {
+                violation(format!("title is {} characters long, more than the allowed {}", title_length, max_title_length));
+            }
+
+            if let Some((pattern, regex)) = &rule.tag_pattern {
+                for tag in page.tags.iter().flatten().filter(|tag| !regex.is_match(tag)) {
+                    violation(format!("tag '{}' doesn't match the pattern '{}'", tag, pattern));
+                }
+            }
+        }
+    }
+    violations.sort_by(|a, b| a.0.cmp(&b.0));
+    violations
+}
+
+// Whether a page's metadata has a value for a key, which is a metadata field or a custom key.
+fn has_field(page: &Metadata, key: &str) -> bool {
+    match key {
+        "title" => !page.title.is_empty(),
+        "author" => !page.author.name.is_empty(),
+        "description" => !page.description.is_empty(),
+        "keywords" => !page.keywords.is_empty(),
+        "excerpt" => !page.excerpt.is_empty(),
+        "date" => page.date.is_some(),
+        "updated" => page.updated.is_some(),
+        "weight" => page.weight.is_some(),
+        "group" => page.group.is_some(),
+        "series" => page.series.is_some(),
+        "tags" => page.tags.as_ref().is_some_and(|tags| tags.iter().any(|tag| !tag.is_empty())),
+        "lang" => page.lang.is_some(),
+        "canonical" => page.canonical.is_some(),
+        "layout" => page.layout.is_some(),
+        "pinned" => page.pinned,
+        _ => page.custom.get(key).is_some_and(|value| !value.is_empty()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sitegen::config::Config;
+    use chrono::NaiveDate;
+
+    // Test that missing required keys, long titles and tags that don't match the pattern are
+    // reported, for the pages of the rule's group only.
+    #[test]
+    fn test_validation_rules() {
+        let config: Config = toml::from_str(r#"
+            [[validate]]
+            group = "blog"
+            required = ["title", "date", "topic"]
+            max_title_length = 12
+            [[validate]]
+            tag_pattern = "[a-z-]+"
+        "#).unwrap();
+
+        let page = |path: &str, title: &str, group: Option<&str>, tags: &[&str]| Metadata {
+            title: title.to_string(),
+            group: group.map(str::to_string),
+            tags: Some(tags.iter().map(|tag| tag.to_string()).collect()),
+            ..Metadata::new(path)
+        };
+        let mut dated = page("/blog/a.html", "Short", Some("blog"), &["travel"]);
+        dated.date = NaiveDate::from_ymd_opt(2024, 5, 1);
+        dated.custom.insert(String::from("topic"), String::from("Italy"));
+        let pages = [
+            page("/blog/b.html", "A Much Longer Title", Some("blog"), &["Street Food"]),
+            dated,
+            page("/about.html", "", None, &[]),
+        ];
+
+        let violations: Vec<_> = check(&pages, &config.validate).into_iter().map(|(path, message)| format!("{}: {}", path, message)).collect();
+        assert_eq!(violations, [
+            "/blog/b.html: no 'date', which pages in group 'blog' must have",
+            "/blog/b.html: no 'topic', which pages in group 'blog' must have",
+            "/blog/b.html: title is 19 characters long, more than the allowed 12",
+            "/blog/b.html: tag 'Street Food' doesn't match the pattern '[a-z-]+'",
+        ]);
+
+        assert!(toml::from_str::<Config>("[[validate]]\ntag_pattern = \"[a-z\"").is_err());
+    }
+}
@@ -0,0 +1,127 @@
+use anyhow::Result;
+use serde::Deserialize;
+
+use crate::sitegen::page::escape_html_characters;
+
+// Options for embedding videos from a provider, set in a [video.<provider>] table in the
+// configuration file.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VideoConfig {
+    // Use the provider's privacy-enhanced mode: youtube-nocookie.com for YouTube, and the
+    // "do not track" option for Vimeo.
+    pub privacy: bool,
+
+    // Show a poster image with a play button, and only load the provider's player (and its
+    // cookies and scripts) when the poster is clicked.
+    pub click_to_load: bool,
+
+    // Aspect ratio of the embedded player, as a CSS aspect-ratio value.
+    pub aspect_ratio: String,
+}
+
+impl Default for VideoConfig {
+    fn default() -> VideoConfig {
+        VideoConfig {
+            privacy: true,
+            click_to_load: true,
+            aspect_ratio: String::from("16 / 9"),
+        }
+    }
+}
+
+// Styles for the click-to-load poster document, which is shown until the player is loaded.
+const POSTER_STYLE: &str = "*{padding:0;margin:0;overflow:hidden}\
+    html,body{height:100%;background:#000}\
+    img,span{position:absolute;width:100%;top:0;bottom:0;margin:auto}\
+    span{height:1.5em;text-align:center;font:48px/1.5 sans-serif;color:#fff;text-shadow:0 0 0.5em #000}\
+    p{position:absolute;bottom:0;padding:1em;font:16px sans-serif;color:#fff}";
+
+// Create the markup for an embedded video. The poster image, if any, must already be a link
+// that works from the current page. Without a poster, the click-to-load poster document shows
+// the title, rather than the provider's thumbnail, so nothing is loaded from the provider until
+// the visitor clicks.
+pub fn embed(provider: &str, id: &str, title: &str, poster: Option<&str>, config: &VideoConfig) -> Result<String> {
+    // The id is part of the player's URL
+    if id.is_empty() || id.contains(['/', '?', '#', '"']) {
+        anyhow::bail!("Invalid {} video id '{}', expected an id without '/', '?', '#' or '\"'", provider, id);
+    }
+    let player = match provider {
+        "youtube" => {
+            let host = if config.privacy { "www.youtube-nocookie.com" } else { "www.youtube.com" };
+            format!("https://{}/embed/{}", host, id)
+        }
+        "vimeo" => {
+            let query = if config.privacy { "?dnt=1" } else { "" };
+            format!("https://player.vimeo.com/video/{}{}", id, query)
+        }
+        _ => anyhow::bail!("Unknown video provider '{}', expected 'youtube' or 'vimeo'", provider),
+    };
+
+    let mut attributes = format!("src=\"{}\"", escape_html_characters(&player));
+    if config.click_to_load {
+        // The poster document replaces the player until its link, which loads the player, is clicked
+        let separator = if player.contains('?') { '&' } else { '?' };
+        let autoplay = format!("{}{}autoplay=1", player, separator);
+        let image = match poster {
+            Some(poster) => format!("<img src=\"{}\" alt=\"{}\">", escape_html_characters(poster), escape_html_characters(title)),
+            None => format!("<p>{}</p>", escape_html_characters(title)),
+        };
+        let poster_document = format!(
+            "<style>{}</style><a href=\"{}\">{}<span>&#9654;</span></a>",
+            POSTER_STYLE, escape_html_characters(&autoplay), image
+        );
+        // The poster document is markup in an attribute, so its character references are escaped
+        // too, to be unescaped once when the attribute is read
+        let srcdoc = escape_html_characters(&poster_document.replace('&', "&amp;"));
+        attributes = format!("src=\"{}\" srcdoc=\"{}\"", escape_html_characters(&autoplay), srcdoc);
+    }
+
+    Ok(format!(
+        "<iframe class=\"video video-{}\" {} title=\"{}\" loading=\"lazy\" \
+        allow=\"accelerometer; autoplay; encrypted-media; gyroscope; picture-in-picture\" allowfullscreen \
+        style=\"width: 100%; aspect-ratio: {}; border: 0;\"></iframe>",
+        provider, attributes, escape_html_characters(title), escape_html_characters(&config.aspect_ratio)
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Test the players and hosts of the providers, with and without privacy mode, and that unknown
+    // providers and ids that would change the player's URL are errors.
+    #[test]
+    fn test_embed() {
+        let direct = VideoConfig { click_to_load: false, ..VideoConfig::default() };
+        let tracking = VideoConfig { privacy: false, ..direct.clone() };
+        let src = |html: String| html.split('"').nth(3).unwrap_or_default().to_string();
+
+        assert_eq!(src(embed("youtube", "abc123", "Video", None, &direct).unwrap()), "https://www.youtube-nocookie.com/embed/abc123");
+        assert_eq!(src(embed("youtube", "abc123", "Video", None, &tracking).unwrap()), "https://www.youtube.com/embed/abc123");
+        assert_eq!(src(embed("vimeo", "76979871", "Video", None, &direct).unwrap()), "https://player.vimeo.com/video/76979871?dnt=1");
+        assert_eq!(src(embed("vimeo", "76979871", "Video", None, &tracking).unwrap()), "https://player.vimeo.com/video/76979871");
+        assert_eq!(embed("dailymotion", "x7", "Video", None, &direct).unwrap_err().to_string(),
+            "Unknown video provider 'dailymotion', expected 'youtube' or 'vimeo'");
+        for id in ["", "abc/../x", "abc?list=1", "abc#t=10", "abc\" onload=\"x"] {
+            assert!(embed("youtube", id, "Video", None, &direct).is_err(), "{}", id);
+        }
+    }
+
+    // Test that click-to-load players start playing once loaded, adding autoplay to the player's
+    // query if it has one, and that without a poster, the poster document shows the escaped title
+    // and nothing from the provider.
+    #[test]
+    fn test_click_to_load() {
+        let config = VideoConfig::default();
+        let vimeo = embed("vimeo", "76979871", "Video", Some("poster.jpg"), &config).unwrap();
+        let youtube = embed("youtube", "abc123", "Fish & \"Chips\" <Leeds>", None, &config).unwrap();
+
+        assert!(vimeo.contains("src=\"https://player.vimeo.com/video/76979871?dnt=1&amp;autoplay=1\""));
+        assert!(vimeo.contains("&lt;img src=&quot;poster.jpg&quot;"));
+        assert!(youtube.contains("src=\"https://www.youtube-nocookie.com/embed/abc123?autoplay=1\""));
+        assert!(youtube.contains("&lt;p&gt;Fish &amp;amp; &amp;quot;Chips&amp;quot; &amp;lt;Leeds&amp;gt;&lt;/p&gt;"));
+        assert!(!youtube.contains("&lt;img") && !youtube.contains("ytimg.com"));
+        assert!(youtube.contains("title=\"Fish &amp; &quot;Chips&quot; &lt;Leeds&gt;\""));
+    }
+}
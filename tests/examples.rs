@@ -80,6 +80,7 @@ fn tags_example_produces_expected_output() {
         "tags/italy.html",
         "tags/posts.html",
         "tags/spain.html",
+        "sitemap.html",
     ];
 
     // Delete any existing output files to ensure a clean test run
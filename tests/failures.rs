@@ -0,0 +1,43 @@
+use std::process::Command;
+
+#[path = "../src/sitegen/testing.rs"]
+mod testing;
+use testing::TempSite;
+
+#[test]
+fn failing_page_exits_with_status_2() {
+    // Build a site with one page that can't be generated, in a fresh folder
+    let webroot = TempSite::new("failures");
+    webroot.write("good.sgpage", "--\ntitle: Good\n--\n<h1>{ title | upper }</h1>");
+    webroot.write("broken.sgpage", "--\ntitle: Broken\n--\n<h1>{ title | shout }</h1>");
+
+    let sitewinder = env!("CARGO_BIN_EXE_sitewinder");
+    let output = Command::new(sitewinder)
+        .args([webroot.as_os_str()])
+        .output()
+        .expect("Failed to run sitewinder");
+    let good = webroot.read("good.html");
+    let broken = webroot.join("broken.html").exists();
+
+    // The build completes, skipping the failing page
+    assert_eq!(output.status.code(), Some(2), "sitewinder exited with status: {}", output.status);
+    assert_eq!(good, "<h1>GOOD</h1>");
+    assert!(!broken, "the failing page was written");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Unknown filter 'shout'"));
+}
+
+#[test]
+fn lint_problems_exit_with_status_3() {
+    // Lint a site with one page that can't be generated, in a fresh folder
+    let webroot = TempSite::new("lint");
+    webroot.write("broken.sgpage", "--\ntitle: Broken\n--\n<h1>{ title | shout }</h1>");
+
+    let sitewinder = env!("CARGO_BIN_EXE_sitewinder");
+    let output = Command::new(sitewinder)
+        .args([std::ffi::OsStr::new("lint"), webroot.as_os_str()])
+        .output()
+        .expect("Failed to run sitewinder");
+
+    assert_eq!(output.status.code(), Some(3), "sitewinder exited with status: {}", output.status);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("1 problem found"));
+}